@@ -19,6 +19,30 @@ pub enum ChatCoreError {
     EmailAlreadyExists(String),
     #[error("create chat error: {0}")]
     CreateChatError(String),
+    #[error("jwks error: {0}")]
+    JwksError(String),
+    #[error("mail error: {0}")]
+    MailError(String),
+    #[error("auth provider error: {0}")]
+    AuthProviderError(String),
+    #[error("oidc error: {0}")]
+    OidcError(String),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("cross-workspace reference: {0}")]
+    CrossWorkspace(String),
+    #[error("invalid email or password")]
+    InvalidCredentials,
+    #[error("too many requests: {0}")]
+    TooManyRequests(String),
+    #[error("locked: {0}")]
+    Locked(String),
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
 }
 
 impl IntoResponse for ChatCoreError {
@@ -31,6 +55,18 @@ impl IntoResponse for ChatCoreError {
             ChatCoreError::JwtError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             ChatCoreError::EmailAlreadyExists(_) => StatusCode::CONFLICT,
             ChatCoreError::CreateChatError(_) => StatusCode::BAD_REQUEST,
+            ChatCoreError::JwksError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ChatCoreError::MailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ChatCoreError::AuthProviderError(_) => StatusCode::BAD_GATEWAY,
+            ChatCoreError::OidcError(_) => StatusCode::BAD_GATEWAY,
+            ChatCoreError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ChatCoreError::Conflict(_) => StatusCode::CONFLICT,
+            ChatCoreError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ChatCoreError::CrossWorkspace(_) => StatusCode::FORBIDDEN,
+            ChatCoreError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ChatCoreError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ChatCoreError::Locked(_) => StatusCode::LOCKED,
+            ChatCoreError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
         };
 
         (status, Json(self.to_string())).into_response()
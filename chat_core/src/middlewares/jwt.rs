@@ -1,4 +1,5 @@
 use std::fmt;
+use std::future::Future;
 
 use axum::extract::{FromRequestParts, Query, Request, State};
 use axum::http::StatusCode;
@@ -10,7 +11,7 @@ use axum_extra::TypedHeader;
 use serde::Deserialize;
 use tracing::warn;
 
-use crate::User;
+use crate::{AccessToken, User, PERSONAL_TOKEN_PREFIX};
 
 /*
     two ways to write middleware:
@@ -25,7 +26,30 @@ struct Params {
 
 pub trait JwtVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    fn verify(&self, token: &str) -> Result<AccessToken, Self::Error>;
+    /// look up a personal access token, `None` if it's unknown, revoked or expired
+    fn verify_personal_token(
+        &self,
+        token: &str,
+    ) -> impl Future<Output = Result<Option<AccessToken>, Self::Error>> + Send;
+    /// check the token against the denylist of revoked tokens and signed-out sessions
+    fn is_revoked(
+        &self,
+        access: &AccessToken,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    /// record activity on the token's session once the request is let through
+    fn touch_session(
+        &self,
+        _access: &AccessToken,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+    /// load the user the token was issued to as a member of the token's workspace,
+    /// `None` if the user isn't a member of it (anymore). Tokens only carry the user id
+    fn resolve_user(
+        &self,
+        access: &AccessToken,
+    ) -> impl Future<Output = Result<Option<User>, Self::Error>> + Send;
 }
 
 pub async fn jwt_verify<T>(State(state): State<T>, req: Request, next: Next) -> Response
@@ -56,19 +80,57 @@ where
         };
 
     let mut req = Request::from_parts(parts, body);
-    match state.verify(&token) {
-        Ok(user) => {
+    let access = if token.starts_with(PERSONAL_TOKEN_PREFIX) {
+        match state.verify_personal_token(&token).await {
+            Ok(Some(access)) => access,
+            Ok(None) => return (StatusCode::UNAUTHORIZED, "verify token failed").into_response(),
+            Err(e) => {
+                warn!("error: {:?}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "check token failed").into_response();
+            }
+        }
+    } else {
+        match state.verify(&token) {
+            Ok(access) => access,
+            Err(_) => return (StatusCode::UNAUTHORIZED, "verify token failed").into_response(),
+        }
+    };
+
+    match state.is_revoked(&access).await {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::UNAUTHORIZED, "token revoked").into_response(),
+        Err(e) => {
+            warn!("error: {:?}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "check token failed").into_response();
+        }
+    }
+
+    // reject tokens if the user left the token's workspace or changed password
+    // since the token was issued
+    match state.resolve_user(&access).await {
+        Ok(Some(user))
+            if user.ws_id == access.ws_id && user.token_generation == access.generation =>
+        {
+            if let Err(e) = state.touch_session(&access).await {
+                warn!("touch session error: {:?}", e);
+            }
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(access);
             next.run(req).await
         }
-        Err(_) => (StatusCode::UNAUTHORIZED, "verify token failed").into_response(),
+        Ok(_) => (StatusCode::UNAUTHORIZED, "user not found").into_response(),
+        Err(e) => {
+            warn!("error: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "resolve user failed").into_response()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
     use std::ops::Deref;
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
 
     use axum::body::Body;
     use axum::middleware::from_fn_with_state;
@@ -83,7 +145,7 @@ mod tests {
     use super::*;
 
     #[derive(Clone)]
-    struct AppState(Arc<JwtSigner>);
+    struct AppState(Arc<JwtSigner>, Arc<Mutex<HashSet<String>>>);
 
     impl Deref for AppState {
         type Target = JwtSigner;
@@ -94,9 +156,35 @@ mod tests {
 
     impl JwtVerify for AppState {
         type Error = ChatCoreError;
-        fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        fn verify(&self, token: &str) -> Result<AccessToken, Self::Error> {
             self.0.verify(token)
         }
+
+        async fn verify_personal_token(
+            &self,
+            token: &str,
+        ) -> Result<Option<AccessToken>, Self::Error> {
+            // a single api key for user 1
+            Ok((token == "chat_pat_test").then(|| AccessToken {
+                jti: "pat-1".to_string(),
+                user_id: 1,
+                ws_id: 0,
+                scopes: vec!["chats:read".to_string()],
+                generation: 0,
+                session_id: None,
+                expires_at: chrono::DateTime::<chrono::Utc>::MAX_UTC,
+            }))
+        }
+
+        async fn is_revoked(&self, access: &AccessToken) -> Result<bool, Self::Error> {
+            Ok(self.1.lock().unwrap().contains(&access.jti))
+        }
+
+        async fn resolve_user(&self, access: &AccessToken) -> Result<Option<User>, Self::Error> {
+            // only user 1 exists
+            Ok((access.user_id == 1)
+                .then(|| User::new(1, 0, "lign".to_string(), "testlign@gmail.com".to_string())))
+        }
     }
 
     async fn handler() -> impl IntoResponse {
@@ -106,14 +194,14 @@ mod tests {
     #[tokio::test]
     async fn test_jwt_verify_middleware() -> anyhow::Result<()> {
         let signer = JwtSigner::load("./fixtures/pkcs8.pem").expect("Failed to load ek.pem");
-        let state = AppState(Arc::new(signer));
+        let state = AppState(Arc::new(signer), Default::default());
         let app = Router::new()
             .route("/", get(handler))
             .layer(from_fn_with_state(state.clone(), jwt_verify::<AppState>));
 
         let user = User::new(1, 0, "lign".to_string(), "testlign@gmail.com".to_string());
 
-        let token = state.sign(user)?;
+        let token = state.sign(&user)?;
         // happy path
         let res = app
             .clone()
@@ -163,6 +251,7 @@ mod tests {
 
         // bad token in query string
         let res = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .uri(format!("/?access_token={}", "bad_token"))
@@ -171,6 +260,85 @@ mod tests {
             .await?;
         assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
 
+        // personal access token
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header("Authorization", "Bearer chat_pat_test")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header("Authorization", "Bearer chat_pat_unknown")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // unknown user
+        let unknown = User::new(2, 0, "alice".to_string(), "alice@gmail.com".to_string());
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header("Authorization", format!("Bearer {}", state.sign(&unknown)?))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // user moved to another workspace
+        let moved = User::new(1, 3, "lign".to_string(), "testlign@gmail.com".to_string());
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header("Authorization", format!("Bearer {}", state.sign(&moved)?))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // token of an older generation
+        let mut outdated = User::new(1, 0, "lign".to_string(), "testlign@gmail.com".to_string());
+        outdated.token_generation = -1;
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", state.sign(&outdated)?),
+                    )
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // revoked token
+        let access = state.verify(&token)?;
+        state.1.lock().unwrap().insert(access.jti);
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
         Ok(())
     }
 }
@@ -2,7 +2,7 @@ use sqlx::{query_as, PgPool};
 use tracing::info;
 
 use crate::error::ChatCoreError;
-use crate::models::{Chat, ChatType, CreateChat, User};
+use crate::models::{Chat, ChatType, CreateChat, User, WorkspaceMember, WorkspaceSettings};
 
 impl Chat {
     pub async fn create(
@@ -22,23 +22,41 @@ impl Chat {
                 "Chat must have at least 2 members".to_string(),
             ));
         }
-        if len > 8 && create_chat.name.is_none() {
-            return Err(ChatCoreError::CreateChatError(
-                "Chat with more than 8 members must have a name".to_string(),
-            ));
+        let settings = WorkspaceSettings::get(ws_id, pool).await?;
+        let max_group_size = settings.max_group_size as usize;
+        if len > max_group_size && create_chat.name.is_none() {
+            return Err(ChatCoreError::CreateChatError(format!(
+                "Chat with more than {} members must have a name",
+                max_group_size
+            )));
         }
-        let users = User::find_user_by_ids(&create_chat.members, pool).await?;
+        let users = User::find_user_by_ids(&create_chat.members, ws_id, pool).await?;
         if users.len() != len {
-            return Err(ChatCoreError::CreateChatError(
-                "Some Chat members not found".to_string(),
-            ));
+            let outsiders: Vec<_> = create_chat
+                .members
+                .iter()
+                .filter(|id| !users.iter().any(|u| u.id == **id))
+                .collect();
+            return Err(ChatCoreError::CrossWorkspace(format!(
+                "users {:?} are not members of workspace {}",
+                outsiders, ws_id
+            )));
         }
         let typ = match len {
             2 => ChatType::Single,
-            3..=8 => ChatType::Group,
+            _ if len <= max_group_size => ChatType::Group,
             _ if create_chat.is_public => ChatType::PublicChannel,
             _ => ChatType::PrivateChannel,
         };
+        if typ == ChatType::PublicChannel {
+            let member = WorkspaceMember::find(ws_id, user_id, pool).await?;
+            if !member.is_some_and(|m| m.role.at_least(settings.public_channel_role)) {
+                return Err(ChatCoreError::Forbidden(format!(
+                    "only a {} or above may create public channels",
+                    settings.public_channel_role
+                )));
+            }
+        }
 
         let owner_id = if typ == ChatType::Single {
             None
@@ -64,18 +82,21 @@ impl Chat {
         Ok(chat)
     }
 
+    /// chats of other workspaces are never found
     pub(crate) async fn find_chat_by_id(
         id: i64,
+        ws_id: i64,
         pool: &PgPool,
     ) -> Result<Option<Self>, ChatCoreError> {
         let chat = query_as(
             r#"
             SELECT *
             FROM chats
-            WHERE id = $1
+            WHERE id = $1 AND ws_id = $2
             "#,
         )
         .bind(id)
+        .bind(ws_id)
         .fetch_optional(pool)
         .await?;
 
@@ -99,8 +120,34 @@ impl Chat {
         Ok(chats)
     }
 
-    pub async fn delete(id: i64, user_id: i64, pool: &PgPool) -> Result<Self, ChatCoreError> {
-        let chat = Chat::find_chat_by_id(id, pool).await?;
+    /// the chats of the workspace the user takes part in
+    pub async fn list_chats_of_member(
+        ws_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, ChatCoreError> {
+        let chats = query_as(
+            r#"
+            SELECT *
+            FROM chats
+            WHERE ws_id = $1 AND $2 = ANY(members)
+            "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(chats)
+    }
+
+    pub async fn delete(
+        id: i64,
+        ws_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        let chat = Chat::find_chat_by_id(id, ws_id, pool).await?;
         match chat {
             Some(chat) => {
                 if chat.typ != ChatType::Single && chat.owner_id != Some(user_id) {
@@ -115,11 +162,12 @@ impl Chat {
         let chat = query_as(
             r#"
             DELETE FROM chats
-            WHERE id = $1 AND $2 = ANY(members)
+            WHERE id = $1 AND ws_id = $2 AND $3 = ANY(members)
             RETURNING *
             "#,
         )
         .bind(id)
+        .bind(ws_id)
         .bind(user_id)
         .fetch_one(pool)
         .await?;
@@ -129,11 +177,12 @@ impl Chat {
 
     pub async fn update_owner(
         id: i64,
+        ws_id: i64,
         user_id: i64,
         new_owner_id: i64,
         pool: &PgPool,
     ) -> Result<Self, ChatCoreError> {
-        let chat = Chat::find_chat_by_id(id, pool).await?;
+        let chat = Chat::find_chat_by_id(id, ws_id, pool).await?;
         match chat {
             Some(chat) => {
                 if chat.typ != ChatType::Single && chat.owner_id != Some(user_id) {
@@ -152,13 +201,14 @@ impl Chat {
             r#"
             UPDATE chats
             SET owner_id = $1
-            WHERE id = $2 AND $1 = ANY(members) AND $3 = ANY(members)
+            WHERE id = $2 AND ws_id = $4 AND $1 = ANY(members) AND $3 = ANY(members)
             RETURNING *
             "#,
         )
         .bind(new_owner_id)
         .bind(id)
         .bind(user_id)
+        .bind(ws_id)
         .fetch_one(pool)
         .await?;
 
@@ -168,6 +218,7 @@ impl Chat {
 
     pub async fn is_chat_member(
         id: i64,
+        ws_id: i64,
         user_id: i64,
         pool: &PgPool,
     ) -> Result<bool, ChatCoreError> {
@@ -175,19 +226,40 @@ impl Chat {
             r#"
             SELECT *
             FROM chats
-            WHERE id = $1 AND $2 = ANY(members)
+            WHERE id = $1 AND ws_id = $2 AND $3 = ANY(members)
             "#,
         )
         .bind(id)
+        .bind(ws_id)
         .bind(user_id)
         .fetch_optional(pool)
         .await?;
         Ok(chat.is_some())
     }
+
+    /// ids of the users sharing a chat with the user, in workspaces both belong to
+    pub async fn peer_ids(user_id: i64, pool: &PgPool) -> Result<Vec<i64>, ChatCoreError> {
+        let ids: Vec<(i64,)> = query_as(
+            r#"
+            SELECT DISTINCT p.peer_id
+            FROM chats c
+            CROSS JOIN LATERAL unnest(c.members) AS p(peer_id)
+            JOIN workspace_members um ON um.ws_id = c.ws_id AND um.user_id = $1
+            JOIN workspace_members pm ON pm.ws_id = c.ws_id AND pm.user_id = p.peer_id
+            WHERE $1 = ANY(c.members) AND p.peer_id <> $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::models::{UpdateWorkspaceSettings, WorkspaceRole};
     use crate::test_util::get_test_pool;
 
     use super::*;
@@ -207,16 +279,71 @@ mod tests {
         assert_eq!(chat.typ, ChatType::Group);
         assert_eq!(chat.owner_id, Some(1));
 
-        let chat = Chat::update_owner(chat.id, 1, 2, &pool).await?;
+        let chat = Chat::update_owner(chat.id, 1, 1, 2, &pool).await?;
         assert_eq!(chat.owner_id, Some(2));
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_chat_should_stay_in_its_workspace() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        // doe(5) belongs to workspace 3
+        let create_chat = CreateChat {
+            name: None,
+            members: vec![1, 2, 5],
+            is_public: false,
+        };
+        let err = Chat::create(create_chat, 1, 1, &pool).await.unwrap_err();
+        assert!(matches!(err, ChatCoreError::CrossWorkspace(_)));
+
+        assert!(Chat::find_chat_by_id(1, 3, &pool).await?.is_none());
+        assert!(!Chat::is_chat_member(1, 3, 1, &pool).await?);
+        let err = Chat::delete(1, 3, 1, &pool).await.unwrap_err();
+        assert!(matches!(err, ChatCoreError::NotFound(_)));
+
+        // general_ch(3) of workspace 1 still lists doe(5) and eve(6)
+        let mut peers = Chat::peer_ids(1, &pool).await?;
+        peers.sort();
+        assert_eq!(peers, [2, 3, 4]);
+        assert!(Chat::peer_ids(5, &pool).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_chat_should_follow_workspace_settings() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let update = UpdateWorkspaceSettings {
+            max_group_size: Some(3),
+            public_channel_role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        WorkspaceSettings::update(1, update, &pool).await?;
+        let create_chat = |is_public| CreateChat {
+            name: Some("channel".to_string()),
+            members: vec![1, 2, 3, 4],
+            is_public,
+        };
+
+        let chat = Chat::create(create_chat(false), 1, 1, &pool).await?;
+        assert_eq!(chat.typ, ChatType::PrivateChannel);
+        let err = Chat::create(create_chat(true), 1, 1, &pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ChatCoreError::Forbidden(_)));
+
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 1")
+            .execute(&pool)
+            .await?;
+        let chat = Chat::create(create_chat(true), 1, 1, &pool).await?;
+        assert_eq!(chat.typ, ChatType::PublicChannel);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_find_chat_by_id() -> anyhow::Result<()> {
         let (pool, _tdb) = get_test_pool(None).await;
-        let chat = Chat::find_chat_by_id(1, &pool).await?;
+        let chat = Chat::find_chat_by_id(1, 1, &pool).await?;
         assert!(chat.is_some());
         let chat = chat.unwrap();
         assert_eq!(chat.typ, ChatType::Group);
@@ -0,0 +1,105 @@
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as, PgPool};
+
+use crate::error::ChatCoreError;
+use crate::models::{EmailVerificationToken, User};
+use crate::utils::token::{generate_token, hash_token};
+
+const VERIFICATION_TOKEN_HOURS: i64 = 48;
+
+impl EmailVerificationToken {
+    /// issue a verification token for the user, any previous unused token is invalidated
+    pub async fn create(user_id: i64, pool: &PgPool) -> Result<String, ChatCoreError> {
+        query(
+            r#"
+            UPDATE email_verification_tokens
+            SET used_at = NOW()
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        let token = generate_token();
+        query(
+            r#"
+            INSERT INTO email_verification_tokens (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(user_id)
+        .bind(hash_token(&token))
+        .bind(Utc::now() + Duration::hours(VERIFICATION_TOKEN_HOURS))
+        .execute(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// mark the email of the token owner as verified, the token can't be used again
+    pub async fn verify(token: &str, pool: &PgPool) -> Result<User, ChatCoreError> {
+        let verification: Option<EmailVerificationToken> = query_as(
+            r#"
+            UPDATE email_verification_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(pool)
+        .await?;
+
+        let Some(verification) = verification else {
+            return Err(ChatCoreError::Unauthorized(
+                "invalid or expired verification token".to_string(),
+            ));
+        };
+        let mut user: User = query_as(
+            r#"
+            UPDATE users
+            SET email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(verification.user_id)
+        .fetch_one(pool)
+        .await?;
+
+        user.password_hash.take();
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::CreateUser;
+    use crate::test_util::get_test_pool;
+    use crate::utils::password::Argon2Config;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_verify_email() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let create_user = CreateUser {
+            ws_name: "test_ws".to_string(),
+            fullname: "lign".to_string(),
+            email: "testlign@gmail.com".to_string(),
+            password: "password123".to_string(),
+            invite_code: None,
+        };
+        let user = User::create(create_user, &Argon2Config::default(), &pool).await?;
+        assert!(user.email_verified_at.is_none());
+
+        let token = EmailVerificationToken::create(user.id, &pool).await?;
+        let user = EmailVerificationToken::verify(&token, &pool).await?;
+        assert!(user.email_verified_at.is_some());
+
+        // single use
+        assert!(EmailVerificationToken::verify(&token, &pool).await.is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,13 @@
-use sqlx::{query_as, PgPool};
+use sqlx::{query_as, query_scalar, PgPool};
 
 use crate::error::ChatCoreError;
 use crate::models::{CreateMessage, ListMessages, Messages};
 
 impl Messages {
+    /// the chat must belong to the workspace and have the sender as a member
     pub async fn create(
         create_message: CreateMessage,
+        ws_id: i64,
         sender_id: i64,
         chat_id: i64,
         pool: &PgPool,
@@ -14,8 +16,9 @@ impl Messages {
             r#"
             INSERT INTO messages
             (content, file, sender_id, chat_id)
-            VALUES
-            ($1, $2, $3, $4)
+            SELECT $1, $2, $3, id
+            FROM chats
+            WHERE id = $4 AND ws_id = $5 AND $3 = ANY(members)
             RETURNING *
             "#,
         )
@@ -23,33 +26,99 @@ impl Messages {
         .bind(create_message.file)
         .bind(sender_id)
         .bind(chat_id)
-        .fetch_one(pool)
+        .bind(ws_id)
+        .fetch_optional(pool)
         .await?;
 
-        Ok(message)
+        message.ok_or_else(|| ChatCoreError::NotFound("chat".to_string()))
     }
 
-    #[allow(unused)]
     pub async fn list_messages_in_chat(
         list_messages: ListMessages,
+        ws_id: i64,
         chat_id: i64,
         pool: &PgPool,
     ) -> Result<Vec<Self>, ChatCoreError> {
         let last_id = list_messages.last_id.unwrap_or(i64::MAX);
         let messages: Vec<Messages> = query_as(
             r#"
-            SELECT *
-            FROM messages
-            WHERE chat_id = $1 and id < $2
-            ORDER BY created_at DESC
+            SELECT m.*
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.chat_id = $1 AND c.ws_id = $4 AND m.id < $2
+            ORDER BY m.created_at DESC
             LIMIT $3
             "#,
         )
         .bind(chat_id)
         .bind(last_id)
         .bind(list_messages.limit)
+        .bind(ws_id)
         .fetch_all(pool)
         .await?;
         Ok(messages)
     }
+
+    /// whether the file is attached to a message in one of the user's chats of the workspace
+    pub async fn is_file_shared_with(
+        file: &str,
+        ws_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<bool, ChatCoreError> {
+        let shared = query_scalar(
+            r#"
+            SELECT EXISTS(
+                SELECT 1
+                FROM messages m
+                JOIN chats c ON c.id = m.chat_id
+                WHERE c.ws_id = $2 AND $3 = ANY(c.members) AND $1 = ANY(m.file)
+            )
+            "#,
+        )
+        .bind(file)
+        .bind(ws_id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(shared)
+    }
+
+    /// delete the messages older than the retention of their workspace. Returns how many
+    /// were deleted and the files no longer attached to any message or used as an avatar,
+    /// removing those from storage is left to the caller
+    pub async fn purge_expired(pool: &PgPool) -> Result<(u64, Vec<String>), ChatCoreError> {
+        let mut tx = pool.begin().await?;
+        let purged: Vec<Vec<String>> = query_scalar(
+            r#"
+            DELETE FROM messages m
+            USING chats c, workspace_settings s
+            WHERE m.chat_id = c.id AND s.ws_id = c.ws_id
+                AND s.message_retention_days IS NOT NULL
+                AND m.created_at < NOW() - make_interval(days => s.message_retention_days)
+            RETURNING m.file
+            "#,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut files: Vec<String> = purged.iter().flatten().cloned().collect();
+        files.sort();
+        files.dedup();
+
+        let orphaned = query_scalar(
+            r#"
+            SELECT f
+            FROM unnest($1::text[]) f
+            WHERE NOT EXISTS(SELECT 1 FROM messages WHERE f = ANY(file))
+                AND NOT EXISTS(SELECT 1 FROM users WHERE avatar = f)
+            "#,
+        )
+        .bind(&files)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok((purged.len() as u64, orphaned))
+    }
 }
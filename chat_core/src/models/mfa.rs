@@ -0,0 +1,361 @@
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as, PgPool};
+
+use crate::error::ChatCoreError;
+use crate::models::{MfaChallenge, TotpEnrollment, User, UserTotp};
+use crate::utils::token::{generate_salt, generate_token, hash_salted, hash_token};
+use crate::utils::totp;
+
+const TOTP_ISSUER: &str = "chat";
+const RECOVERY_CODES: usize = 10;
+const CHALLENGE_MINUTES: i64 = 5;
+const CHALLENGE_MAX_ATTEMPTS: i32 = 5;
+
+impl UserTotp {
+    /// start enrollment with a fresh secret, TOTP is not enforced until `confirm` succeeds
+    pub async fn enroll(user: &User, pool: &PgPool) -> Result<TotpEnrollment, ChatCoreError> {
+        if Self::is_enabled(user.id, pool).await? {
+            return Err(ChatCoreError::Conflict(
+                "two-factor authentication already enabled".to_string(),
+            ));
+        }
+
+        let secret = totp::generate_secret();
+        query(
+            r#"
+            INSERT INTO user_totp (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, created_at = NOW()
+            "#,
+        )
+        .bind(user.id)
+        .bind(&secret)
+        .execute(pool)
+        .await?;
+
+        let otpauth_uri = totp::otpauth_uri(&secret, TOTP_ISSUER, &user.email);
+        Ok(TotpEnrollment {
+            secret,
+            otpauth_uri,
+        })
+    }
+
+    /// enable TOTP with the first valid code, returns the recovery codes (shown only once)
+    pub async fn confirm(
+        user_id: i64,
+        code: &str,
+        pool: &PgPool,
+    ) -> Result<Vec<String>, ChatCoreError> {
+        let totp: Option<UserTotp> = query_as(
+            r#"
+            SELECT *
+            FROM user_totp
+            WHERE user_id = $1 AND enabled_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        let Some(totp) = totp else {
+            return Err(ChatCoreError::NotFound(
+                "pending totp enrollment".to_string(),
+            ));
+        };
+        let Some(step) = totp::verify(&totp.secret, code, Utc::now().timestamp()) else {
+            return Err(ChatCoreError::Unauthorized("invalid mfa code".to_string()));
+        };
+
+        query(
+            r#"
+            UPDATE user_totp
+            SET enabled_at = NOW(), last_used_step = $2
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(pool)
+        .await?;
+
+        Self::regenerate_recovery_codes(user_id, pool).await
+    }
+
+    /// turn TOTP off, a valid code is required so a stolen session can't do it silently
+    pub async fn disable(user_id: i64, code: &str, pool: &PgPool) -> Result<(), ChatCoreError> {
+        if !Self::verify_code(user_id, code, pool).await? {
+            return Err(ChatCoreError::Unauthorized("invalid mfa code".to_string()));
+        }
+
+        query(
+            r#"
+            DELETE FROM mfa_recovery_codes
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+        query(
+            r#"
+            DELETE FROM user_totp
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_enabled(user_id: i64, pool: &PgPool) -> Result<bool, ChatCoreError> {
+        let enabled: Option<(i64,)> = query_as(
+            r#"
+            SELECT user_id
+            FROM user_totp
+            WHERE user_id = $1 AND enabled_at IS NOT NULL
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(enabled.is_some())
+    }
+
+    /// check a TOTP code (each time step is accepted only once) or burn a recovery code
+    pub async fn verify_code(
+        user_id: i64,
+        code: &str,
+        pool: &PgPool,
+    ) -> Result<bool, ChatCoreError> {
+        let totp: Option<UserTotp> = query_as(
+            r#"
+            SELECT *
+            FROM user_totp
+            WHERE user_id = $1 AND enabled_at IS NOT NULL
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+        let Some(totp) = totp else {
+            return Ok(false);
+        };
+
+        if let Some(step) = totp::verify(&totp.secret, code, Utc::now().timestamp()) {
+            let used = query(
+                r#"
+                UPDATE user_totp
+                SET last_used_step = $2
+                WHERE user_id = $1 AND last_used_step < $2
+                "#,
+            )
+            .bind(user_id)
+            .bind(step)
+            .execute(pool)
+            .await?;
+            return Ok(used.rows_affected() == 1);
+        }
+
+        // each code has its own salt, so the few unused ones are hashed one by one
+        let code = code.trim().to_lowercase();
+        let unused: Vec<(i64, String, Option<String>)> = query_as(
+            r#"
+            SELECT id, code_hash, salt
+            FROM mfa_recovery_codes
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        let Some((id, _, _)) = unused.iter().find(|(_, hash, salt)| match salt {
+            Some(salt) => *hash == hash_salted(salt, &code),
+            None => *hash == hash_token(&code),
+        }) else {
+            return Ok(false);
+        };
+
+        let used = query(
+            r#"
+            UPDATE mfa_recovery_codes
+            SET used_at = NOW()
+            WHERE id = $1 AND used_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(used.rows_affected() == 1)
+    }
+
+    async fn regenerate_recovery_codes(
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<String>, ChatCoreError> {
+        query(
+            r#"
+            DELETE FROM mfa_recovery_codes
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        let codes: Vec<String> = (0..RECOVERY_CODES)
+            .map(|_| totp::generate_recovery_code())
+            .collect();
+        let salts: Vec<String> = codes.iter().map(|_| generate_salt()).collect();
+        let hashes: Vec<String> = codes
+            .iter()
+            .zip(&salts)
+            .map(|(code, salt)| hash_salted(salt, code))
+            .collect();
+        query(
+            r#"
+            INSERT INTO mfa_recovery_codes (user_id, code_hash, salt)
+            SELECT $1, UNNEST($2::char(64)[]), UNNEST($3::char(32)[])
+            "#,
+        )
+        .bind(user_id)
+        .bind(&hashes)
+        .bind(&salts)
+        .execute(pool)
+        .await?;
+
+        Ok(codes)
+    }
+}
+
+impl MfaChallenge {
+    /// short-lived token proving the password was verified, pending the second factor
+    pub async fn create(user_id: i64, pool: &PgPool) -> Result<String, ChatCoreError> {
+        let token = generate_token();
+        query(
+            r#"
+            INSERT INTO mfa_challenges (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(user_id)
+        .bind(hash_token(&token))
+        .bind(Utc::now() + Duration::minutes(CHALLENGE_MINUTES))
+        .execute(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// the user a pending token was issued to, without spending an attempt
+    pub async fn pending_user(token: &str, pool: &PgPool) -> Result<i64, ChatCoreError> {
+        let user_id: Option<(i64,)> = query_as(
+            r#"
+            SELECT user_id
+            FROM mfa_challenges
+            WHERE token_hash = $1 AND expires_at > NOW() AND attempts < $2
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(CHALLENGE_MAX_ATTEMPTS)
+        .fetch_optional(pool)
+        .await?;
+
+        user_id
+            .map(|(id,)| id)
+            .ok_or_else(|| ChatCoreError::Unauthorized("invalid or expired mfa token".to_string()))
+    }
+
+    /// exchange the pending token and a valid code for the user id, the token is single-use
+    /// and dies after too many wrong codes
+    pub async fn verify(token: &str, code: &str, pool: &PgPool) -> Result<i64, ChatCoreError> {
+        let challenge: Option<MfaChallenge> = query_as(
+            r#"
+            UPDATE mfa_challenges
+            SET attempts = attempts + 1
+            WHERE token_hash = $1 AND expires_at > NOW() AND attempts < $2
+            RETURNING *
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(CHALLENGE_MAX_ATTEMPTS)
+        .fetch_optional(pool)
+        .await?;
+
+        let Some(challenge) = challenge else {
+            return Err(ChatCoreError::Unauthorized(
+                "invalid or expired mfa token".to_string(),
+            ));
+        };
+        if !UserTotp::verify_code(challenge.user_id, code, pool).await? {
+            return Err(ChatCoreError::Unauthorized("invalid mfa code".to_string()));
+        }
+
+        query(
+            r#"
+            DELETE FROM mfa_challenges
+            WHERE id = $1 OR expires_at < NOW()
+            "#,
+        )
+        .bind(challenge.id)
+        .execute(pool)
+        .await?;
+
+        Ok(challenge.user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_totp_enroll_and_challenge() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let user = User::find_user_by_id(2, &pool).await?.unwrap();
+
+        let enrollment = UserTotp::enroll(&user, &pool).await?;
+        assert!(!UserTotp::is_enabled(user.id, &pool).await?);
+        assert!(UserTotp::confirm(user.id, "000000x", &pool).await.is_err());
+
+        let now = Utc::now().timestamp();
+        let code = totp::generate_code(&enrollment.secret, now).unwrap();
+        let recovery_codes = UserTotp::confirm(user.id, &code, &pool).await?;
+        assert_eq!(recovery_codes.len(), RECOVERY_CODES);
+        assert!(UserTotp::is_enabled(user.id, &pool).await?);
+        assert!(UserTotp::enroll(&user, &pool).await.is_err());
+
+        // the code used to confirm can't be replayed
+        let token = MfaChallenge::create(user.id, &pool).await?;
+        assert_eq!(MfaChallenge::pending_user(&token, &pool).await?, user.id);
+        assert!(MfaChallenge::verify(&token, &code, &pool).await.is_err());
+
+        // recovery codes are single-use
+        assert_eq!(
+            MfaChallenge::verify(&token, &recovery_codes[0], &pool).await?,
+            user.id
+        );
+        let token = MfaChallenge::create(user.id, &pool).await?;
+        assert!(MfaChallenge::verify(&token, &recovery_codes[0], &pool)
+            .await
+            .is_err());
+
+        // the pending token dies after too many wrong codes
+        for _ in 0..CHALLENGE_MAX_ATTEMPTS {
+            let _ = MfaChallenge::verify(&token, "000000", &pool).await;
+        }
+        assert!(MfaChallenge::verify(&token, &recovery_codes[1], &pool)
+            .await
+            .is_err());
+
+        UserTotp::disable(user.id, &recovery_codes[2], &pool).await?;
+        assert!(!UserTotp::is_enabled(user.id, &pool).await?);
+        Ok(())
+    }
+}
@@ -4,23 +4,71 @@ use sqlx::FromRow;
 use utoipa::{IntoParams, ToSchema};
 
 mod chat;
+mod email_verification;
 mod message;
+mod mfa;
+mod oidc;
+mod password_reset;
+mod personal_token;
+mod session;
+mod signin_throttle;
+mod token;
 mod users;
 mod workspace;
+mod workspace_invite;
+mod workspace_member;
+mod workspace_settings;
+
+pub use personal_token::{PERSONAL_TOKEN_PREFIX, PERSONAL_TOKEN_SCOPES};
+pub use workspace_settings::MAX_UPLOAD_SIZE;
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
 pub struct User {
     pub id: i64,
+    /// the workspace the request acts in: the token's workspace once authenticated,
+    /// otherwise the default workspace of the user
     pub ws_id: i64,
     pub fullname: String,
     pub email: String,
     #[sqlx(default)]
     #[serde(skip)]
     pub password_hash: Option<String>,
+    #[serde(skip)]
+    pub token_generation: i32,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// set once an admin offboarded the user
+    pub deactivated_at: Option<DateTime<Utc>>,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub profile: Profile,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// what users tell about themselves, all optional
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+pub struct Profile {
+    /// shown instead of the full name when set
+    pub display_name: Option<String>,
+    pub title: Option<String>,
+    /// IANA time zone name, e.g. Europe/Berlin
+    pub timezone: Option<String>,
+    pub pronoun: Option<String>,
+    /// url of an image uploaded through /api/files
+    pub avatar: Option<String>,
+}
+
+/// fields left out are kept, an empty string clears an optional field
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpdateProfile {
+    pub fullname: Option<String>,
+    pub display_name: Option<String>,
+    pub title: Option<String>,
+    pub timezone: Option<String>,
+    pub pronoun: Option<String>,
+    pub avatar: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
 pub struct Chat {
     pub id: i64,
@@ -61,21 +109,157 @@ pub struct ListMessages {
     pub limit: i64,
 }
 
+/// a page of the workspace directory, the next page starts after last_id
+#[derive(Debug, Default, Deserialize, IntoParams, ToSchema)]
+pub struct ListUsers {
+    /// prefix of the fullname, one of its words or the email
+    pub q: Option<String>,
+    /// match q by trigram similarity instead of prefix
+    #[serde(default)]
+    pub fuzzy: bool,
+    pub role: Option<WorkspaceRole>,
+    /// only active, or only deactivated users
+    pub active: Option<bool>,
+    #[serde(default)]
+    pub sort: UserSort,
+    #[serde(default)]
+    pub desc: bool,
+    pub last_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    #[default]
+    Name,
+    Email,
+    /// when the user joined the workspace
+    Joined,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Workspace {
     pub id: i64,
     pub name: String,
     pub owner_id: i64,
+    pub join_policy: JoinPolicy,
+    pub allowed_domains: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// who may join an existing workspace at signup, a valid invite is always accepted
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "join_policy", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JoinPolicy {
+    InviteOnly,
+    /// users with a verified email in one of `allowed_domains`
+    AllowedDomains,
+    Open,
+}
+
+/// role of a user in a workspace, each role can do everything the roles below it can
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceRole {
+    Owner,
+    Admin,
+    Member,
+    /// may only take part in the chats they were added to
+    Guest,
+}
+
+/// what a handler requires the caller's role to allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    CreateWorkspace,
+    /// join policy, invitations and lockouts
+    ManageWorkspace,
+    /// change the roles of other members
+    ManageMembers,
+    /// see every chat, member and file of the workspace
+    ViewWorkspace,
+    /// read the chats the caller takes part in and the files shared there
+    ViewChat,
+    CreateChat,
+    /// update or delete a chat, the chat's own owner check still applies
+    ManageChat,
+    SendMessage,
+    UploadFile,
+    /// hand the workspace over to another member
+    TransferOwnership,
+    DeleteWorkspace,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+pub struct WorkspaceMember {
+    pub ws_id: i64,
+    pub user_id: i64,
+    pub role: WorkspaceRole,
+    pub created_at: DateTime<Utc>,
+}
+
+/// join another workspace with the account signed in, under the same rules as signup
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JoinWorkspace {
+    pub ws_name: String,
+    #[serde(default)]
+    pub invite_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateMemberRole {
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateJoinPolicy {
+    pub join_policy: JoinPolicy,
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+pub struct WorkspaceInvite {
+    pub id: i64,
+    pub ws_id: i64,
+    pub created_by: i64,
+    #[serde(skip)]
+    pub code_hash: String,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateWorkspaceInvite {
+    /// 1 for a single-use invite, omitted for an invite without limit
+    pub max_uses: Option<i32>,
+    pub expires_in_hours: Option<i64>,
+}
+
+/// returned once on creation, the code can't be retrieved later
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewWorkspaceInvite {
+    pub code: String,
+    #[serde(flatten)]
+    pub info: WorkspaceInvite,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateUser {
     pub ws_name: String,
     pub fullname: String,
     pub email: String,
     pub password: String,
+    /// required to join an existing workspace unless its join policy admits the email
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -84,10 +268,273 @@ pub struct SigninUser {
     pub password: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct EmailVerificationToken {
+    pub id: i64,
+    pub user_id: i64,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ForgotPassword {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct PasswordResetToken {
+    pub id: i64,
+    pub user_id: i64,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+/// claims of a verified access token, inserted into request extensions by `jwt_verify`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessToken {
+    pub jti: String,
+    pub user_id: i64,
+    pub ws_id: i64,
+    pub scopes: Vec<String>,
+    pub generation: i32,
+    /// `None` for api keys and tokens issued before sessions existed
+    pub session_id: Option<i64>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+pub struct Session {
+    pub id: i64,
+    pub user_id: i64,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// the workspace switched to, `None` for the default workspace
+    pub ws_id: Option<i64>,
+    /// whether the session is the one of the request
+    #[sqlx(default)]
+    #[serde(default)]
+    pub current: bool,
+}
+
+/// where a sign-in comes from, recorded on the session it creates
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionInfo {
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+pub struct PersonalAccessToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreatePersonalAccessToken {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// never expires if not set
+    pub expires_in_days: Option<i64>,
+}
+
+/// the plain token is only returned once, on creation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewPersonalAccessToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: PersonalAccessToken,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct RefreshToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub session_id: Option<i64>,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct UserTotp {
+    pub user_id: i64,
+    #[serde(skip)]
+    pub secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub last_used_step: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct MfaChallenge {
+    pub id: i64,
+    pub user_id: i64,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub attempts: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// a TOTP code or one of the recovery codes
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MfaCode {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MfaSignin {
+    pub mfa_token: String,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct OidcLogin {
+    pub id: i64,
+    #[serde(skip)]
+    pub state_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct UserIdentity {
+    pub id: i64,
+    pub user_id: i64,
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, IntoParams)]
+pub struct OidcCallback {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq)]
+pub struct SigninThrottle {
+    pub scope: String,
+    pub key: String,
+    pub failures: i32,
+    pub blocked_until: Option<DateTime<Utc>>,
+    pub locked: bool,
+    pub last_failure_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+pub struct SigninLockout {
+    pub id: i64,
+    pub scope: String,
+    pub key: String,
+    pub user_id: Option<i64>,
+    pub failures: i32,
+    pub locked_until: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateWorkspace {
     pub name: String,
-    pub owner_id: i64,
+}
+
+/// limits and policies of a workspace, the defaults apply until an admin changes them
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+pub struct WorkspaceSettings {
+    pub ws_id: i64,
+    /// chats with more members are channels, which need a name
+    pub max_group_size: i32,
+    /// in bytes, per uploaded file
+    pub max_upload_size: i64,
+    /// lowercase extensions without the dot, empty to allow any
+    pub allowed_extensions: Vec<String>,
+    /// messages older than this are deleted, kept forever if not set
+    pub message_retention_days: Option<i32>,
+    /// lowest role allowed to create public channels
+    pub public_channel_role: WorkspaceRole,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// settings left out are kept
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpdateWorkspaceSettings {
+    pub max_group_size: Option<i32>,
+    pub max_upload_size: Option<i64>,
+    pub allowed_extensions: Option<Vec<String>>,
+    /// 0 to keep messages forever
+    pub message_retention_days: Option<i32>,
+    pub public_channel_role: Option<WorkspaceRole>,
+}
+
+/// chats and workspace owned by the deactivated user go to the successor
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeactivateUser {
+    pub successor_id: i64,
+}
+
+/// rename the workspace and/or transfer its ownership to another member
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpdateWorkspace {
+    pub name: Option<String>,
+    pub owner_id: Option<i64>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
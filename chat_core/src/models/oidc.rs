@@ -0,0 +1,201 @@
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as, PgPool};
+use tracing::info;
+
+use crate::error::ChatCoreError;
+use crate::models::{OidcLogin, User, UserIdentity};
+use crate::utils::oidc::OidcIdentity;
+use crate::utils::token::{generate_token, hash_token};
+
+const LOGIN_MINUTES: i64 = 10;
+
+impl OidcLogin {
+    /// start an authorization request, returns the `state` to send to the provider
+    pub async fn create(pool: &PgPool) -> Result<(String, Self), ChatCoreError> {
+        let state = generate_token();
+        let login = query_as(
+            r#"
+            INSERT INTO oidc_logins (state_hash, nonce, code_verifier, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+        )
+        .bind(hash_token(&state))
+        .bind(generate_token())
+        .bind(generate_token())
+        .bind(Utc::now() + Duration::minutes(LOGIN_MINUTES))
+        .fetch_one(pool)
+        .await?;
+
+        Ok((state, login))
+    }
+
+    /// consume the pending request matching the `state` echoed back to the callback
+    pub async fn take(state: &str, pool: &PgPool) -> Result<Self, ChatCoreError> {
+        query(
+            r#"
+            DELETE FROM oidc_logins
+            WHERE expires_at < NOW()
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        let login: Option<OidcLogin> = query_as(
+            r#"
+            DELETE FROM oidc_logins
+            WHERE state_hash = $1
+            RETURNING *
+            "#,
+        )
+        .bind(hash_token(state))
+        .fetch_optional(pool)
+        .await?;
+
+        login.ok_or_else(|| ChatCoreError::Unauthorized("invalid or expired state".to_string()))
+    }
+}
+
+impl UserIdentity {
+    /// find the user linked to the identity. An existing account is linked when the
+    /// provider vouches for the email, otherwise a new user is provisioned into `ws_name`
+    pub async fn resolve(
+        identity: &OidcIdentity,
+        ws_name: &str,
+        pool: &PgPool,
+    ) -> Result<User, ChatCoreError> {
+        let linked: Option<UserIdentity> = query_as(
+            r#"
+            SELECT *
+            FROM user_identities
+            WHERE issuer = $1 AND subject = $2
+            "#,
+        )
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(linked) = linked {
+            let user = User::find_user_by_id(linked.user_id, pool)
+                .await?
+                .ok_or_else(|| ChatCoreError::NotFound("user".to_string()))?;
+            user.ensure_active()?;
+            return Ok(user);
+        }
+
+        let Some(email) = identity.email.as_deref() else {
+            return Err(ChatCoreError::Unauthorized(
+                "id token has no email".to_string(),
+            ));
+        };
+        // a provisioned user is only kept along with its identity
+        let mut tx = pool.begin().await?;
+        let user = match User::find_user_by_email(email, pool).await? {
+            Some(_) if !identity.email_verified => {
+                return Err(ChatCoreError::Unauthorized(format!(
+                    "email {} not verified by identity provider",
+                    email
+                )))
+            }
+            Some(user) => {
+                user.ensure_active()?;
+                user
+            }
+            None => {
+                let fullname = identity.name.clone().unwrap_or_else(|| email.to_string());
+                let user = User::insert(
+                    ws_name.to_string(),
+                    fullname,
+                    email.to_string(),
+                    None,
+                    identity.email_verified,
+                    &mut tx,
+                )
+                .await?;
+                info!("user {} provisioned from {}", email, identity.issuer);
+                user
+            }
+        };
+
+        query(
+            r#"
+            INSERT INTO user_identities (user_id, issuer, subject)
+            VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(user.id)
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::WorkspaceMember;
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_identity() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let (state, login) = OidcLogin::create(&pool).await?;
+        assert_eq!(OidcLogin::take(&state, &pool).await?, login);
+        assert!(OidcLogin::take(&state, &pool).await.is_err());
+
+        let mut identity = OidcIdentity {
+            issuer: "https://idp.example.com".to_string(),
+            subject: "42".to_string(),
+            email: Some("alice@bbc.com".to_string()),
+            email_verified: false,
+            name: None,
+        };
+        // unverified emails can't take over existing accounts
+        assert!(UserIdentity::resolve(&identity, "bbc", &pool)
+            .await
+            .is_err());
+        identity.email_verified = true;
+        let user = UserIdentity::resolve(&identity, "bbc", &pool).await?;
+        assert_eq!(user.id, 2);
+
+        let identity = OidcIdentity {
+            subject: "43".to_string(),
+            email: Some("zoe@acme.com".to_string()),
+            name: Some("Zoe".to_string()),
+            ..identity
+        };
+        let user = UserIdentity::resolve(&identity, "acme", &pool).await?;
+        assert_eq!(user.fullname, "Zoe");
+        assert!(user.email_verified_at.is_some());
+        // the subject is linked now
+        assert_eq!(UserIdentity::resolve(&identity, "acme", &pool).await?, user);
+
+        // linked identities of deactivated users are refused, as is provisioning under their email
+        sqlx::query("UPDATE users SET deactivated_at = NOW() WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await?;
+        assert!(UserIdentity::resolve(&identity, "acme", &pool)
+            .await
+            .is_err());
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE ws_id = 1 AND user_id = 1")
+            .execute(&pool)
+            .await?;
+        let admin = WorkspaceMember::find(1, 1, &pool).await?.unwrap();
+        admin.deactivate(3, 1, &pool).await?;
+        let identity = OidcIdentity {
+            subject: "44".to_string(),
+            email: Some("bob@bbc.com".to_string()),
+            ..identity
+        };
+        assert!(UserIdentity::resolve(&identity, "bbc", &pool)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as, PgPool};
+
+use crate::error::ChatCoreError;
+use crate::models::{PasswordResetToken, User};
+use crate::utils::password::Argon2Config;
+use crate::utils::token::{generate_token, hash_token};
+
+const RESET_TOKEN_MINUTES: i64 = 60;
+
+impl PasswordResetToken {
+    /// issue a reset token for the user, any previous unused token is invalidated
+    pub async fn create(user_id: i64, pool: &PgPool) -> Result<String, ChatCoreError> {
+        query(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = NOW()
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        let token = generate_token();
+        query(
+            r#"
+            INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(user_id)
+        .bind(hash_token(&token))
+        .bind(Utc::now() + Duration::minutes(RESET_TOKEN_MINUTES))
+        .execute(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// set a new password with a reset token, the token can't be used again.
+    /// Every session of the user is revoked so they have to sign in again
+    pub async fn reset_password(
+        token: &str,
+        password: &str,
+        argon2: &Argon2Config,
+        pool: &PgPool,
+    ) -> Result<User, ChatCoreError> {
+        let password_hash = argon2.hash(password)?;
+        let mut tx = pool.begin().await?;
+        let reset: Option<PasswordResetToken> = query_as(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(reset) = reset else {
+            return Err(ChatCoreError::Unauthorized(
+                "invalid or expired reset token".to_string(),
+            ));
+        };
+        let mut user: User = query_as(
+            r#"
+            UPDATE users
+            SET password_hash = $1, token_generation = token_generation + 1, updated_at = NOW()
+            WHERE id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(password_hash)
+        .bind(reset.user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let statements = [
+            "UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+        ];
+        for statement in statements {
+            query(statement)
+                .bind(reset.user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        user.password_hash.take();
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reset_password() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let argon2 = Argon2Config::default();
+        let old = PasswordResetToken::create(2, &pool).await?;
+        let token = PasswordResetToken::create(2, &pool).await?;
+
+        // issuing a new token invalidates the old one
+        assert!(
+            PasswordResetToken::reset_password(&old, "new_pwd", &argon2, &pool)
+                .await
+                .is_err()
+        );
+
+        let user = PasswordResetToken::reset_password(&token, "new_pwd", &argon2, &pool).await?;
+        assert_eq!(user.id, 2);
+        assert!(
+            User::verify_password("alice@bbc.com", "new_pwd", &argon2, &pool)
+                .await
+                .is_ok()
+        );
+
+        // single use
+        assert!(
+            PasswordResetToken::reset_password(&token, "other_pwd", &argon2, &pool)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+}
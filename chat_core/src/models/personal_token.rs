@@ -0,0 +1,232 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{query, query_as, FromRow, PgPool};
+
+use crate::error::ChatCoreError;
+use crate::models::{
+    AccessToken, CreatePersonalAccessToken, NewPersonalAccessToken, PersonalAccessToken, User,
+};
+use crate::utils::token::{generate_token, hash_token};
+
+/// lets `jwt_verify` tell api keys apart from JWTs
+pub const PERSONAL_TOKEN_PREFIX: &str = "chat_pat_";
+const MAX_TOKEN_NAME_LEN: usize = 64;
+const MAX_TOKEN_DAYS: i64 = 365;
+
+/// scopes that can be granted to api keys, each is `{resource}:{read|write}`.
+/// Account management (password, mfa, api keys themselves) is never grantable
+pub const PERSONAL_TOKEN_SCOPES: &[&str] = &[
+    "chats:read",
+    "chats:write",
+    "messages:read",
+    "messages:write",
+    "files:read",
+    "files:write",
+    "users:read",
+    "workspaces:read",
+    "workspaces:write",
+    "events:read",
+    "events:write",
+];
+
+#[derive(Debug, FromRow)]
+struct VerifiedToken {
+    id: i64,
+    user_id: i64,
+    ws_id: i64,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+    token_generation: i32,
+}
+
+impl PersonalAccessToken {
+    pub async fn create(
+        user: &User,
+        input: CreatePersonalAccessToken,
+        pool: &PgPool,
+    ) -> Result<NewPersonalAccessToken, ChatCoreError> {
+        let name = input.name.trim();
+        if name.is_empty() {
+            return Err(ChatCoreError::BadRequest("token name is empty".to_string()));
+        }
+        if name.chars().count() > MAX_TOKEN_NAME_LEN {
+            return Err(ChatCoreError::BadRequest(format!(
+                "token name must be at most {} characters",
+                MAX_TOKEN_NAME_LEN
+            )));
+        }
+        if input.scopes.is_empty() {
+            return Err(ChatCoreError::BadRequest("no scope granted".to_string()));
+        }
+        if let Some(scope) = input
+            .scopes
+            .iter()
+            .find(|s| !PERSONAL_TOKEN_SCOPES.contains(&s.as_str()))
+        {
+            return Err(ChatCoreError::BadRequest(format!(
+                "unknown scope {}",
+                scope
+            )));
+        }
+        let expires_at = match input.expires_in_days {
+            Some(days) if !(1..=MAX_TOKEN_DAYS).contains(&days) => {
+                return Err(ChatCoreError::BadRequest(format!(
+                    "expires_in_days must be between 1 and {}",
+                    MAX_TOKEN_DAYS
+                )))
+            }
+            Some(days) => Some(Utc::now() + Duration::days(days)),
+            None => None,
+        };
+
+        let token = format!("{}{}", PERSONAL_TOKEN_PREFIX, generate_token());
+        let info = query_as(
+            r#"
+            INSERT INTO personal_access_tokens (user_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(user.id)
+        .bind(name)
+        .bind(hash_token(&token))
+        .bind(&input.scopes)
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(NewPersonalAccessToken { token, info })
+    }
+
+    /// tokens of the user that are not revoked, newest first
+    pub async fn list(user_id: i64, pool: &PgPool) -> Result<Vec<Self>, ChatCoreError> {
+        let tokens = query_as(
+            r#"
+            SELECT *
+            FROM personal_access_tokens
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    pub async fn revoke(id: i64, user_id: i64, pool: &PgPool) -> Result<(), ChatCoreError> {
+        let revoked = query(
+            r#"
+            UPDATE personal_access_tokens
+            SET revoked_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        if revoked.rows_affected() == 0 {
+            return Err(ChatCoreError::NotFound(format!("token {}", id)));
+        }
+        Ok(())
+    }
+
+    /// look up a live api key and turn it into the same claims a JWT would carry
+    pub async fn verify(token: &str, pool: &PgPool) -> Result<Option<AccessToken>, ChatCoreError> {
+        let verified: Option<VerifiedToken> = query_as(
+            r#"
+            UPDATE personal_access_tokens p
+            SET last_used_at = NOW()
+            FROM users u
+            WHERE p.token_hash = $1 AND p.revoked_at IS NULL
+                AND (p.expires_at IS NULL OR p.expires_at > NOW())
+                AND u.id = p.user_id
+            RETURNING p.id, p.user_id, u.ws_id, p.scopes, p.expires_at, u.token_generation
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(verified.map(|v| AccessToken {
+            jti: format!("pat-{}", v.id),
+            user_id: v.user_id,
+            ws_id: v.ws_id,
+            scopes: v.scopes,
+            generation: v.token_generation,
+            session_id: None,
+            expires_at: v.expires_at.unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_personal_access_token() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let user = User::find_user_by_id(2, &pool).await?.unwrap();
+
+        let input = CreatePersonalAccessToken {
+            name: "ci".to_string(),
+            scopes: vec!["messages:write".to_string()],
+            expires_in_days: Some(30),
+        };
+        let new = PersonalAccessToken::create(&user, input, &pool).await?;
+        assert!(new.token.starts_with(PERSONAL_TOKEN_PREFIX));
+
+        let access = PersonalAccessToken::verify(&new.token, &pool)
+            .await?
+            .unwrap();
+        assert_eq!(access.user_id, user.id);
+        assert_eq!(access.ws_id, user.ws_id);
+        assert!(access.has_scope("messages:write"));
+        assert!(!access.has_scope("chats:write"));
+
+        let tokens = PersonalAccessToken::list(user.id, &pool).await?;
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used_at.is_some());
+
+        // only the owner can revoke
+        assert!(PersonalAccessToken::revoke(new.info.id, 3, &pool)
+            .await
+            .is_err());
+        PersonalAccessToken::revoke(new.info.id, user.id, &pool).await?;
+        assert!(PersonalAccessToken::verify(&new.token, &pool)
+            .await?
+            .is_none());
+        assert!(PersonalAccessToken::list(user.id, &pool).await?.is_empty());
+
+        let input = CreatePersonalAccessToken {
+            name: "admin".to_string(),
+            scopes: vec!["*".to_string()],
+            expires_in_days: None,
+        };
+        assert!(PersonalAccessToken::create(&user, input, &pool)
+            .await
+            .is_err());
+
+        for (name, expires_in_days) in [
+            ("ci", Some(1_000_000_000)),
+            ("ci", Some(i64::MAX)),
+            (&*"a".repeat(65), None),
+        ] {
+            let input = CreatePersonalAccessToken {
+                name: name.to_string(),
+                scopes: vec!["messages:write".to_string()],
+                expires_in_days,
+            };
+            let err = PersonalAccessToken::create(&user, input, &pool)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, ChatCoreError::BadRequest(_)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+use sqlx::{query, query_as, query_scalar, PgPool};
+
+use crate::error::ChatCoreError;
+use crate::models::token::REFRESH_TOKEN_DAYS;
+use crate::models::{Session, SessionInfo};
+
+/// clients may name the device, e.g. "Alice's laptop", shown in the session list
+const DEVICE_NAME_HEADER: &str = "x-device-name";
+const DEVICE_NAME_MAX_LEN: usize = 128;
+
+impl Session {
+    pub async fn create(
+        user_id: i64,
+        info: &SessionInfo,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        let session = query_as(
+            r#"
+            INSERT INTO sessions (user_id, device_name, user_agent, ip)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(&info.device_name)
+        .bind(&info.user_agent)
+        .bind(&info.ip)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// active sessions of the user, most recently seen first. Sessions unused for longer
+    /// than a refresh token lives can't be resumed and are left out
+    pub async fn list(
+        user_id: i64,
+        current: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, ChatCoreError> {
+        let sessions = query_as(
+            r#"
+            SELECT *, id IS NOT DISTINCT FROM $2 AS current
+            FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL
+                AND last_seen_at > NOW() - make_interval(days => $3)
+            ORDER BY last_seen_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(current)
+        .bind(REFRESH_TOKEN_DAYS)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// sign out a session: its refresh tokens are revoked and its access tokens rejected
+    pub async fn revoke(id: i64, user_id: i64, pool: &PgPool) -> Result<(), ChatCoreError> {
+        let mut tx = pool.begin().await?;
+        let revoked = query(
+            r#"
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if revoked.rows_affected() == 0 {
+            return Err(ChatCoreError::NotFound(format!("session {}", id)));
+        }
+
+        query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE session_id = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// sign out every session of the user but `except`. Every refresh token is revoked,
+    /// the kept session needs a new one
+    pub async fn revoke_all(
+        user_id: i64,
+        except: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<(), ChatCoreError> {
+        let mut tx = pool.begin().await?;
+        query(
+            r#"
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL AND id IS DISTINCT FROM $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(except)
+        .execute(&mut *tx)
+        .await?;
+
+        query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn find(id: i64, pool: &PgPool) -> Result<Option<Self>, ChatCoreError> {
+        let session = query_as(
+            r#"
+            SELECT *
+            FROM sessions
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// make tokens refreshed in this session act in the workspace
+    pub async fn switch_workspace(id: i64, ws_id: i64, pool: &PgPool) -> Result<(), ChatCoreError> {
+        query(
+            r#"
+            UPDATE sessions
+            SET ws_id = $1
+            WHERE id = $2
+            "#,
+        )
+        .bind(ws_id)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// delete sessions signed out or unused for longer than a refresh token lives, along
+    /// with their refresh tokens. Returns the number of sessions deleted
+    pub async fn purge_expired(pool: &PgPool) -> Result<u64, ChatCoreError> {
+        let mut tx = pool.begin().await?;
+        let expired: Vec<i64> = query_scalar(
+            r#"
+            SELECT id
+            FROM sessions
+            WHERE COALESCE(revoked_at, last_seen_at) < NOW() - make_interval(days => $1)
+            FOR UPDATE
+            "#,
+        )
+        .bind(REFRESH_TOKEN_DAYS)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        query("DELETE FROM refresh_tokens WHERE session_id = ANY($1)")
+            .bind(&expired)
+            .execute(&mut *tx)
+            .await?;
+        let purged = query("DELETE FROM sessions WHERE id = ANY($1)")
+            .bind(&expired)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(purged.rows_affected())
+    }
+
+    /// record activity, the row is written at most once a minute
+    pub async fn touch(id: i64, pool: &PgPool) -> Result<(), ChatCoreError> {
+        query(
+            r#"
+            UPDATE sessions
+            SET last_seen_at = NOW()
+            WHERE id = $1 AND last_seen_at < NOW() - INTERVAL '1 minute'
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for SessionInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let device_name =
+            header(DEVICE_NAME_HEADER).map(|v| v.chars().take(DEVICE_NAME_MAX_LEN).collect());
+        let user_agent = header(USER_AGENT.as_str());
+        // only set when the server is run with `into_make_service_with_connect_info`
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        Ok(Self {
+            device_name,
+            user_agent,
+            ip,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::RefreshToken;
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_revoke_session() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let info = SessionInfo {
+            device_name: Some("laptop".to_string()),
+            ..Default::default()
+        };
+        let laptop = Session::create(1, &info, &pool).await?;
+        let phone = Session::create(1, &SessionInfo::default(), &pool).await?;
+        let token = RefreshToken::create(1, Some(laptop.id), &pool).await?;
+
+        let sessions = Session::list(1, Some(phone.id), &pool).await?;
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().any(|s| s.id == phone.id && s.current));
+        assert!(sessions.iter().any(|s| s.id == laptop.id && !s.current));
+
+        // only the owner can revoke a session
+        assert!(Session::revoke(laptop.id, 2, &pool).await.is_err());
+        Session::revoke(laptop.id, 1, &pool).await?;
+        assert!(RefreshToken::rotate(&token, &pool).await.is_err());
+        assert!(Session::revoke(laptop.id, 1, &pool).await.is_err());
+
+        let sessions = Session::list(1, None, &pool).await?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, phone.id);
+
+        Session::revoke_all(1, Some(phone.id), &pool).await?;
+        assert_eq!(Session::list(1, None, &pool).await?.len(), 1);
+        Session::revoke_all(1, None, &pool).await?;
+        assert!(Session::list(1, None, &pool).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let idle = Session::create(1, &SessionInfo::default(), &pool).await?;
+        let active = Session::create(1, &SessionInfo::default(), &pool).await?;
+        let token = RefreshToken::create(1, Some(idle.id), &pool).await?;
+        query("UPDATE sessions SET last_seen_at = NOW() - INTERVAL '31 days' WHERE id = $1")
+            .bind(idle.id)
+            .execute(&pool)
+            .await?;
+
+        let sessions = Session::list(1, None, &pool).await?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, active.id);
+
+        assert_eq!(Session::purge_expired(&pool).await?, 1);
+        assert!(Session::find(idle.id, &pool).await?.is_none());
+        assert!(Session::find(active.id, &pool).await?.is_some());
+        assert!(RefreshToken::rotate(&token, &pool).await.is_err());
+        Ok(())
+    }
+}
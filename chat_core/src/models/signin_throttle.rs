@@ -0,0 +1,267 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{query, query_as, PgPool};
+use tracing::warn;
+
+use crate::error::ChatCoreError;
+use crate::models::{SigninLockout, SigninThrottle, User};
+
+const SCOPE_ACCOUNT: &str = "account";
+const SCOPE_IP: &str = "ip";
+// failures older than this don't count anymore
+const FAILURE_WINDOW_MINUTES: i64 = 60;
+const LOCKOUT_MINUTES: i64 = 15;
+
+/// failures allowed before backoff kicks in, and before the key is locked out
+struct Policy {
+    backoff_after: i32,
+    lockout_after: i32,
+}
+
+const ACCOUNT_POLICY: Policy = Policy {
+    backoff_after: 3,
+    lockout_after: 10,
+};
+
+const IP_POLICY: Policy = Policy {
+    backoff_after: 10,
+    lockout_after: 50,
+};
+
+impl Policy {
+    fn for_scope(scope: &str) -> &'static Policy {
+        match scope {
+            SCOPE_IP => &IP_POLICY,
+            _ => &ACCOUNT_POLICY,
+        }
+    }
+
+    /// exponential backoff (1s, 2s, 4s...) and a temporary lockout once the limit is reached
+    fn penalty(&self, failures: i32) -> Option<(Duration, bool)> {
+        if failures >= self.lockout_after {
+            Some((Duration::minutes(LOCKOUT_MINUTES), true))
+        } else if failures >= self.backoff_after {
+            let secs = 1i64 << (failures - self.backoff_after).min(10);
+            Some((Duration::seconds(secs), false))
+        } else {
+            None
+        }
+    }
+}
+
+impl SigninThrottle {
+    /// reject the attempt if the account or the client ip is backing off or locked out
+    pub async fn check(email: &str, ip: Option<&str>, pool: &PgPool) -> Result<(), ChatCoreError> {
+        let throttles: Vec<SigninThrottle> = query_as(
+            r#"
+            SELECT *
+            FROM signin_throttles
+            WHERE ((scope = $1 AND key = $2) OR (scope = $3 AND key = $4))
+                AND blocked_until > NOW()
+            "#,
+        )
+        .bind(SCOPE_ACCOUNT)
+        .bind(email)
+        .bind(SCOPE_IP)
+        .bind(ip)
+        .fetch_all(pool)
+        .await?;
+
+        let now = Utc::now();
+        let retry_after =
+            |t: &SigninThrottle| (t.blocked_until.unwrap_or(now) - now).num_seconds().max(1);
+        if let Some(t) = throttles
+            .iter()
+            .find(|t| t.locked && t.scope == SCOPE_ACCOUNT)
+        {
+            return Err(ChatCoreError::Locked(format!(
+                "account locked, retry after {} seconds",
+                retry_after(t)
+            )));
+        }
+        if let Some(t) = throttles.first() {
+            return Err(ChatCoreError::TooManyRequests(format!(
+                "retry after {} seconds",
+                retry_after(t)
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn record_failure(
+        email: &str,
+        ip: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<(), ChatCoreError> {
+        Self::fail(SCOPE_ACCOUNT, email, pool).await?;
+        if let Some(ip) = ip {
+            Self::fail(SCOPE_IP, ip, pool).await?;
+        }
+        Ok(())
+    }
+
+    /// a successful sign-in clears the account's failures, the ip keeps its record
+    pub async fn record_success(email: &str, pool: &PgPool) -> Result<(), ChatCoreError> {
+        query(
+            r#"
+            DELETE FROM signin_throttles
+            WHERE scope = $1 AND key = $2
+            "#,
+        )
+        .bind(SCOPE_ACCOUNT)
+        .bind(email)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn fail(scope: &str, key: &str, pool: &PgPool) -> Result<(), ChatCoreError> {
+        let throttle: SigninThrottle = query_as(
+            r#"
+            INSERT INTO signin_throttles (scope, key, failures)
+            VALUES ($1, $2, 1)
+            ON CONFLICT (scope, key) DO UPDATE
+            SET failures = CASE
+                    WHEN signin_throttles.last_failure_at < $3 THEN 1
+                    ELSE signin_throttles.failures + 1
+                END,
+                last_failure_at = NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(Utc::now() - Duration::minutes(FAILURE_WINDOW_MINUTES))
+        .fetch_one(pool)
+        .await?;
+
+        let Some((delay, locked)) = Policy::for_scope(scope).penalty(throttle.failures) else {
+            return Ok(());
+        };
+        let blocked_until = Utc::now() + delay;
+        query(
+            r#"
+            UPDATE signin_throttles
+            SET blocked_until = $3, locked = $4
+            WHERE scope = $1 AND key = $2
+            "#,
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(blocked_until)
+        .bind(locked)
+        .execute(pool)
+        .await?;
+
+        if locked {
+            warn!(
+                "signin locked for {} {} after {} failures",
+                scope, key, throttle.failures
+            );
+            SigninLockout::create(scope, key, throttle.failures, blocked_until, pool).await?;
+        }
+        Ok(())
+    }
+}
+
+impl SigninLockout {
+    async fn create(
+        scope: &str,
+        key: &str,
+        failures: i32,
+        locked_until: DateTime<Utc>,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        let user_id = if scope == SCOPE_ACCOUNT {
+            User::find_user_by_email(key, pool).await?.map(|u| u.id)
+        } else {
+            None
+        };
+        let lockout = query_as(
+            r#"
+            INSERT INTO signin_lockouts (scope, key, user_id, failures, locked_until)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(user_id)
+        .bind(failures)
+        .bind(locked_until)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(lockout)
+    }
+
+    /// account lockouts of users in the workspace and the ip lockouts, newest first.
+    /// Ip lockouts belong to no account, so every workspace sees them
+    pub async fn list_in_workspace(ws_id: i64, pool: &PgPool) -> Result<Vec<Self>, ChatCoreError> {
+        let lockouts = query_as(
+            r#"
+            SELECT l.*
+            FROM signin_lockouts l
+            LEFT JOIN workspace_members m ON m.user_id = l.user_id AND m.ws_id = $1
+            WHERE m.ws_id IS NOT NULL OR l.scope = $2
+            ORDER BY l.created_at DESC
+            "#,
+        )
+        .bind(ws_id)
+        .bind(SCOPE_IP)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(lockouts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signin_throttle() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let email = "alice@bbc.com";
+        let ip = Some("127.0.0.1");
+
+        for _ in 0..ACCOUNT_POLICY.backoff_after - 1 {
+            SigninThrottle::record_failure(email, ip, &pool).await?;
+        }
+        SigninThrottle::check(email, ip, &pool).await?;
+
+        SigninThrottle::record_failure(email, ip, &pool).await?;
+        let err = SigninThrottle::check(email, ip, &pool).await.unwrap_err();
+        assert!(matches!(err, ChatCoreError::TooManyRequests(_)));
+        // other accounts are not affected
+        SigninThrottle::check("bob@bbc.com", None, &pool).await?;
+
+        for _ in ACCOUNT_POLICY.backoff_after..ACCOUNT_POLICY.lockout_after {
+            SigninThrottle::record_failure(email, ip, &pool).await?;
+        }
+        let err = SigninThrottle::check(email, ip, &pool).await.unwrap_err();
+        assert!(matches!(err, ChatCoreError::Locked(_)));
+
+        let lockouts = SigninLockout::list_in_workspace(1, &pool).await?;
+        assert_eq!(lockouts.len(), 1);
+        assert_eq!(lockouts[0].user_id, Some(2));
+        assert!(SigninLockout::list_in_workspace(3, &pool).await?.is_empty());
+
+        // the ip goes on with unknown accounts until it's locked out too
+        for _ in ACCOUNT_POLICY.lockout_after..IP_POLICY.lockout_after {
+            SigninThrottle::record_failure("nobody@bbc.com", ip, &pool).await?;
+        }
+        for ws_id in [1, 3] {
+            let lockouts = SigninLockout::list_in_workspace(ws_id, &pool).await?;
+            assert_eq!(lockouts[0].scope, SCOPE_IP);
+            assert!(lockouts.iter().all(|l| l.key != "nobody@bbc.com"));
+        }
+
+        SigninThrottle::record_success(email, &pool).await?;
+        SigninThrottle::check(email, None, &pool).await?;
+        Ok(())
+    }
+}
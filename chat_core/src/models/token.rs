@@ -0,0 +1,255 @@
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as, query_scalar, PgPool};
+use tracing::warn;
+
+use crate::error::ChatCoreError;
+use crate::models::{AccessToken, RefreshToken};
+use crate::utils::jwt::{ACCOUNT_SCOPES, FULL_ACCESS_SCOPE};
+use crate::utils::token::{generate_token, hash_token};
+
+/// also how long a session can go unused before it's signed out
+pub(crate) const REFRESH_TOKEN_DAYS: i32 = 30;
+
+impl RefreshToken {
+    /// issue a new refresh token for the user, the raw token is only returned to the client
+    pub async fn create(
+        user_id: i64,
+        session_id: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<String, ChatCoreError> {
+        let token = generate_token();
+        query(
+            r#"
+            INSERT INTO refresh_tokens (user_id, session_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(user_id)
+        .bind(session_id)
+        .bind(hash_token(&token))
+        .bind(Utc::now() + Duration::days(REFRESH_TOKEN_DAYS.into()))
+        .execute(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// exchange a refresh token for a new one of the same session, returns the used token
+    /// and the new raw token. A refresh token can only be used once: presenting an already
+    /// rotated token revokes every refresh token of that user.
+    pub async fn rotate(token: &str, pool: &PgPool) -> Result<(Self, String), ChatCoreError> {
+        let token_hash = hash_token(token);
+        let rotated: Option<RefreshToken> = query_as(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(&token_hash)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(rotated) = rotated {
+            let token = Self::create(rotated.user_id, rotated.session_id, pool).await?;
+            return Ok((rotated, token));
+        }
+
+        let reused: Option<RefreshToken> = query_as(
+            r#"
+            SELECT *
+            FROM refresh_tokens
+            WHERE token_hash = $1 AND revoked_at IS NOT NULL
+            "#,
+        )
+        .bind(&token_hash)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(reused) = reused {
+            warn!(
+                "refresh token {} reused, revoking all tokens of user {}",
+                reused.id, reused.user_id
+            );
+            Self::revoke_all(reused.user_id, pool).await?;
+        }
+
+        Err(ChatCoreError::Unauthorized(
+            "invalid refresh token".to_string(),
+        ))
+    }
+
+    pub async fn revoke(token: &str, user_id: i64, pool: &PgPool) -> Result<(), ChatCoreError> {
+        query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE token_hash = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_all(user_id: i64, pool: &PgPool) -> Result<(), ChatCoreError> {
+        query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl AccessToken {
+    /// tokens of signed-in users carry the full access scope, api keys only what they were granted
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|s| s == FULL_ACCESS_SCOPE || s == scope)
+    }
+
+    /// whether the token only grants the account routes, see `JwtSigner::sign_account_session`
+    pub fn is_account_only(&self) -> bool {
+        !self.scopes.is_empty()
+            && self
+                .scopes
+                .iter()
+                .all(|s| ACCOUNT_SCOPES.contains(&s.as_str()))
+    }
+
+    /// put the token on the denylist until it expires
+    pub async fn revoke(&self, pool: &PgPool) -> Result<(), ChatCoreError> {
+        query(
+            r#"
+            INSERT INTO revoked_tokens (jti, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (jti) DO NOTHING
+            "#,
+        )
+        .bind(&self.jti)
+        .bind(self.expires_at)
+        .execute(pool)
+        .await?;
+
+        // expired tokens are rejected anyway, no need to keep them
+        query(
+            r#"
+            DELETE FROM revoked_tokens
+            WHERE expires_at < NOW()
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// whether the token is on the denylist or its session was signed out, expired or purged
+    pub async fn is_revoked(&self, pool: &PgPool) -> Result<bool, ChatCoreError> {
+        let revoked = query_scalar(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = $1)
+                OR ($2::bigint IS NOT NULL AND NOT EXISTS(
+                    SELECT 1 FROM sessions
+                    WHERE id = $2 AND revoked_at IS NULL
+                        AND last_seen_at > NOW() - make_interval(days => $3)
+                ))
+            "#,
+        )
+        .bind(&self.jti)
+        .bind(self.session_id)
+        .bind(REFRESH_TOKEN_DAYS)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(revoked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Session, SessionInfo};
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rotate_refresh_token() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let session = Session::create(1, &SessionInfo::default(), &pool).await?;
+        let token = RefreshToken::create(1, Some(session.id), &pool).await?;
+
+        let (rotated, new_token) = RefreshToken::rotate(&token, &pool).await?;
+        assert_eq!(rotated.user_id, 1);
+        assert_eq!(rotated.session_id, Some(session.id));
+        assert_ne!(new_token, token);
+
+        // reusing a rotated token revokes the whole family
+        assert!(RefreshToken::rotate(&token, &pool).await.is_err());
+        assert!(RefreshToken::rotate(&new_token, &pool).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revoke_refresh_token() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let token = RefreshToken::create(1, None, &pool).await?;
+
+        // only the owner can revoke the token
+        RefreshToken::revoke(&token, 2, &pool).await?;
+        let (_, token) = RefreshToken::rotate(&token, &pool).await?;
+
+        RefreshToken::revoke(&token, 1, &pool).await?;
+        assert!(RefreshToken::rotate(&token, &pool).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revoke_access_token() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let access = AccessToken {
+            jti: "test_jti".to_string(),
+            user_id: 1,
+            ws_id: 1,
+            scopes: vec![],
+            generation: 0,
+            session_id: None,
+            expires_at: Utc::now() + Duration::minutes(15),
+        };
+        assert!(!access.is_revoked(&pool).await?);
+
+        access.revoke(&pool).await?;
+        assert!(access.is_revoked(&pool).await?);
+
+        // tokens of a signed-out session are rejected too
+        let session = Session::create(1, &SessionInfo::default(), &pool).await?;
+        let access = AccessToken {
+            jti: "session_jti".to_string(),
+            session_id: Some(session.id),
+            ..access
+        };
+        assert!(!access.is_revoked(&pool).await?);
+        Session::revoke(session.id, 1, &pool).await?;
+        assert!(access.is_revoked(&pool).await?);
+
+        // and so are tokens of a purged session
+        let access = AccessToken {
+            jti: "purged_jti".to_string(),
+            session_id: Some(i64::MAX),
+            ..access
+        };
+        assert!(access.is_revoked(&pool).await?);
+        Ok(())
+    }
+}
@@ -1,80 +1,149 @@
-use argon2::password_hash::{rand_core::OsRng, SaltString};
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use sqlx::{query_as, PgPool};
-use tracing::info;
+use std::time::Instant;
+
+use argon2::{PasswordHash, PasswordVerifier};
+use sqlx::{query, query_as, query_scalar, PgConnection, PgPool};
+use tracing::{info, warn};
 
 use crate::error::ChatCoreError;
-use crate::models::{CreateUser, CreateWorkspace, User, Workspace};
+use crate::models::{
+    CreateUser, ListUsers, UpdateProfile, User, UserSort, Workspace, WorkspaceMember, WorkspaceRole,
+};
+use crate::utils::password::Argon2Config;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+/// members of workspace $1, and the deactivated users who left it. Deactivation drops the
+/// membership but keeps the user's default workspace, their role and join date are gone
+const DIRECTORY: &str = r#"
+    FROM users u
+    LEFT JOIN workspace_members m ON m.user_id = u.id AND m.ws_id = $1
+    WHERE (m.user_id IS NOT NULL OR (u.deactivated_at IS NOT NULL AND u.ws_id = $1))
+"#;
 
 impl User {
-    pub async fn create(create_user: CreateUser, pool: &PgPool) -> Result<Self, ChatCoreError> {
+    /// joining an existing workspace takes an invite or an email its join policy admits
+    pub async fn create(
+        create_user: CreateUser,
+        argon2: &Argon2Config,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
         if let Some(user) = Self::find_user_by_email(&create_user.email, pool).await? {
             return Err(ChatCoreError::EmailAlreadyExists(user.email));
         }
-        let ws = match Workspace::find_workspace_by_name(&create_user.ws_name, pool).await? {
+        let mut tx = pool.begin().await?;
+        if let Some(ws) = Workspace::find_workspace_by_name(&create_user.ws_name, pool).await? {
+            // the email of a new account is not verified yet
+            ws.admit(
+                &create_user.email,
+                false,
+                create_user.invite_code.as_deref(),
+                &mut tx,
+            )
+            .await?;
+        }
+        let password_hash = argon2.hash(&create_user.password)?;
+        let user = Self::insert(
+            create_user.ws_name,
+            create_user.fullname,
+            create_user.email,
+            Some(password_hash),
+            false,
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    /// insert the user into the workspace, the workspace is created (owned by the user)
+    /// if it doesn't exist. Users without password can only sign in through an identity provider
+    pub(crate) async fn insert(
+        ws_name: String,
+        fullname: String,
+        email: String,
+        password_hash: Option<String>,
+        email_verified: bool,
+        conn: &mut PgConnection,
+    ) -> Result<Self, ChatCoreError> {
+        let ws: Option<Workspace> = query_as(
+            r#"
+            SELECT *
+            FROM workspaces
+            WHERE name = $1 AND archived_at IS NULL
+            "#,
+        )
+        .bind(&ws_name)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let reserved: bool = query_scalar(
+            r#"
+            SELECT EXISTS(
+                SELECT 1
+                FROM users
+                WHERE deactivated_email_hash = encode(sha256(convert_to(lower($1), 'UTF8')), 'hex')
+            )
+            "#,
+        )
+        .bind(&email)
+        .fetch_one(&mut *conn)
+        .await?;
+        if reserved {
+            return Err(ChatCoreError::EmailAlreadyExists(email));
+        }
+        let ws = match ws {
             Some(ws) => ws,
             None => {
-                let ws = Workspace::create(
-                    CreateWorkspace {
-                        name: create_user.ws_name,
-                        owner_id: 0,
-                    },
-                    pool,
-                )
-                .await?;
+                let ws = Workspace::insert(&ws_name, 0, conn).await?;
                 info!("workspace {} created", ws.name);
                 ws
             }
         };
 
-        let password_hash = Self::hash_password(&create_user.password)?;
         let user: User = sqlx::query_as(
             r#"
-            INSERT INTO users (ws_id, fullname, email, password_hash)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO users (ws_id, fullname, email, password_hash, email_verified_at)
+            VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN NOW() END)
             RETURNING *
             "#,
         )
         .bind(ws.id)
-        .bind(create_user.fullname)
-        .bind(create_user.email)
+        .bind(fullname)
+        .bind(email)
         .bind(password_hash)
-        .fetch_one(pool)
+        .bind(email_verified)
+        .fetch_one(&mut *conn)
         .await?;
 
+        // the first user of a new workspace owns it
         if ws.owner_id == 0 {
-            Workspace::update_owner(&ws.name, &user.email, pool).await?;
+            WorkspaceMember::add(ws.id, user.id, WorkspaceRole::Owner, conn).await?;
+            query("UPDATE workspaces SET owner_id = $2, updated_at = NOW() WHERE id = $1")
+                .bind(ws.id)
+                .bind(user.id)
+                .execute(&mut *conn)
+                .await?;
             info!(
                 "workspace {} owner updated([super admin] => [{}])",
                 ws.name, user.fullname
             );
+        } else {
+            WorkspaceMember::add(ws.id, user.id, WorkspaceRole::Member, conn).await?;
         }
         Ok(user)
     }
 
-    fn hash_password(pwd: &str) -> Result<String, ChatCoreError> {
-        let salt = SaltString::generate(&mut OsRng);
-
-        // Argon2 with default params (Argon2id v19)
-        let argon2 = Argon2::default();
-
-        // Hash password to PHC string ($argon2id$v=19$...)
-        let password_hash = argon2.hash_password(pwd.as_ref(), &salt)?.to_string();
-
-        Ok(password_hash)
-    }
-
-    #[allow(dead_code)]
+    /// every token issued before the password change is invalidated
     pub async fn update_password(
         id: i64,
         password: &str,
+        argon2: &Argon2Config,
         pool: &PgPool,
     ) -> Result<Self, ChatCoreError> {
-        let password_hash = Self::hash_password(password)?;
-        let user = sqlx::query_as(
+        let password_hash = argon2.hash(password)?;
+        let mut user: User = sqlx::query_as(
             r#"
             UPDATE users
-            SET password_hash = $1
+            SET password_hash = $1, token_generation = token_generation + 1, updated_at = NOW()
             WHERE id = $2
             RETURNING *
             "#,
@@ -84,6 +153,7 @@ impl User {
         .fetch_one(pool)
         .await?;
 
+        user.password_hash.take();
         Ok(user)
     }
 
@@ -110,70 +180,430 @@ impl User {
         }
     }
 
+    pub async fn find_user_by_id(id: i64, pool: &PgPool) -> Result<Option<Self>, ChatCoreError> {
+        let user: Option<User> = query_as(
+            r#"
+            SELECT *
+            FROM users
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(user.map(|mut user| {
+            user.password_hash.take();
+            user
+        }))
+    }
+
+    /// deactivated accounts are refused by every way of signing in
+    pub fn ensure_active(&self) -> Result<(), ChatCoreError> {
+        match self.deactivated_at {
+            Some(_) => Err(ChatCoreError::Unauthorized(format!(
+                "user {} is deactivated",
+                self.id
+            ))),
+            None => Ok(()),
+        }
+    }
+
     pub async fn verify_password(
         email: &str,
         password: &str,
+        argon2: &Argon2Config,
         pool: &PgPool,
     ) -> Result<Self, ChatCoreError> {
         let user: Option<User> = query_as(
             r#"
             SELECT *
             FROM users
-            WHERE email = $1
+            WHERE email = $1 AND deactivated_at IS NULL
         "#,
         )
         .bind(email)
         .fetch_optional(pool)
         .await?;
 
-        if let Some(user) = user {
-            let argon2 = Argon2::default();
-            let parsed_hash = PasswordHash::new(user.password_hash.as_ref().unwrap())?;
-            match argon2.verify_password(password.as_ref(), &parsed_hash) {
-                Ok(_) => Ok(user),
-                Err(_) => Err(ChatCoreError::Unauthorized(
-                    "password not match".to_string(),
-                )),
+        // always run argon2 exactly once, so neither the error nor the timing
+        // tells whether the email is registered
+        let start = Instant::now();
+        let (verified, rehash) = {
+            let stored = user.as_ref().and_then(|user| {
+                let hash = user.password_hash.as_deref()?;
+                PasswordHash::new(hash)
+                    .inspect_err(|e| warn!("malformed password hash for user {}: {}", user.id, e))
+                    .ok()
+            });
+            let hasher = argon2.argon2()?;
+            match stored {
+                Some(hash) => (
+                    hasher.verify_password(password.as_ref(), &hash).is_ok(),
+                    argon2.needs_rehash(&hash),
+                ),
+                None => {
+                    let _ = hasher.verify_password(password.as_ref(), &argon2.dummy_hash()?);
+                    (false, false)
+                }
             }
-        } else {
-            Err(ChatCoreError::NotFound("user not found".to_string()))
+        };
+        // an outdated hash is cheaper to check, wait out the difference
+        if rehash {
+            let pad = argon2.verify_cost()?.saturating_sub(start.elapsed());
+            tokio::time::sleep(pad).await;
         }
+
+        match user {
+            Some(user) if verified => {
+                if rehash {
+                    if let Err(e) = Self::rehash_password(&user, password, argon2, pool).await {
+                        warn!("rehash password for user {} error: {}", user.id, e);
+                    }
+                }
+                Ok(user)
+            }
+            _ => Err(ChatCoreError::InvalidCredentials),
+        }
+    }
+
+    /// upgrade a hash made with outdated parameters, unlike a password change this
+    /// keeps existing tokens valid
+    async fn rehash_password(
+        user: &User,
+        password: &str,
+        argon2: &Argon2Config,
+        pool: &PgPool,
+    ) -> Result<(), ChatCoreError> {
+        let password_hash = argon2.hash(password)?;
+        query(
+            r#"
+            UPDATE users
+            SET password_hash = $1
+            WHERE id = $2 AND password_hash = $3
+            "#,
+        )
+        .bind(password_hash)
+        .bind(user.id)
+        .bind(&user.password_hash)
+        .execute(pool)
+        .await?;
+        info!("password hash of user {} upgraded", user.id);
+        Ok(())
     }
 
+    /// the user acting in the workspace, `None` unless the user is a member of it
+    pub async fn find_in_workspace(
+        id: i64,
+        ws_id: i64,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, ChatCoreError> {
+        let user: Option<User> = query_as(
+            r#"
+            SELECT u.*
+            FROM users u
+            JOIN workspace_members m ON m.user_id = u.id
+            WHERE u.id = $1 AND m.ws_id = $2 AND u.deactivated_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .bind(ws_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(user.map(|user| Self {
+            ws_id,
+            password_hash: None,
+            ..user
+        }))
+    }
+
+    /// a page of the workspace directory, searched, filtered and sorted as asked
     pub async fn list_users_by_workspace(
+        ws_id: i64,
+        list: ListUsers,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, ChatCoreError> {
+        let limit = list.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(ChatCoreError::BadRequest(format!(
+                "limit must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+        let q = list.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+        // the columns and the operators come from the enums, never from the input
+        let (search, q) = match (q, list.fuzzy) {
+            (None, _) => ("TRUE", None),
+            (Some(q), false) => (
+                "(u.fullname ILIKE $2 || '%' OR u.fullname ILIKE '% ' || $2 || '%' OR u.email ILIKE $2 || '%')",
+                Some(escape_like(q)),
+            ),
+            (Some(q), true) => ("($2 <% u.fullname OR $2 <% u.email)", Some(q.to_string())),
+        };
+        let key = match list.sort {
+            UserSort::Name => "lower(u.fullname)",
+            UserSort::Email => "lower(u.email)",
+            UserSort::Joined => "COALESCE(m.created_at, u.created_at)",
+        };
+        let (cmp, dir) = if list.desc {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
+        if let Some(last_id) = list.last_id {
+            let listed: bool = query_scalar(&format!(
+                "SELECT EXISTS(SELECT 1 {DIRECTORY} AND u.id = $2)"
+            ))
+            .bind(ws_id)
+            .bind(last_id)
+            .fetch_one(pool)
+            .await?;
+            if !listed {
+                return Err(ChatCoreError::BadRequest(format!(
+                    "last_id {} is not in the workspace directory",
+                    last_id
+                )));
+            }
+        }
+
+        let sql = format!(
+            r#"
+            SELECT u.*
+            {DIRECTORY}
+                AND ($2::text IS NULL OR {search})
+                AND ($3::workspace_role IS NULL OR m.role = $3)
+                AND ($4::bool IS NULL OR (u.deactivated_at IS NULL) = $4)
+                AND ($5::bigint IS NULL OR ({key}, u.id) {cmp} (
+                    (SELECT {key} {DIRECTORY} AND u.id = $5),
+                    $5
+                ))
+            ORDER BY {key} {dir}, u.id {dir}
+            LIMIT $6
+            "#
+        );
+        let users: Vec<User> = query_as(&sql)
+            .bind(ws_id)
+            .bind(q)
+            .bind(list.role)
+            .bind(list.active)
+            .bind(list.last_id)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(users
+            .into_iter()
+            .map(|user| Self {
+                ws_id,
+                password_hash: None,
+                ..user
+            })
+            .collect())
+    }
+
+    /// users among `ids` that are members of the workspace
+    pub async fn find_user_by_ids(
+        ids: &[i64],
         ws_id: i64,
         pool: &PgPool,
     ) -> Result<Vec<Self>, ChatCoreError> {
-        let users = query_as(
+        let users: Vec<Self> = query_as(
             r#"
-            SELECT *
-            FROM users
-            WHERE ws_id = $1
+            SELECT u.*
+            FROM users u
+            JOIN workspace_members m ON m.user_id = u.id
+            WHERE u.id = ANY($1) AND m.ws_id = $2
             "#,
         )
+        .bind(ids)
         .bind(ws_id)
         .fetch_all(pool)
         .await?;
 
-        Ok(users)
+        Ok(users
+            .into_iter()
+            .map(|user| User {
+                ws_id,
+                password_hash: None,
+                ..user
+            })
+            .collect())
     }
 
-    pub async fn find_user_by_ids(ids: &[i64], pool: &PgPool) -> Result<Vec<Self>, ChatCoreError> {
-        let users = query_as(
+    /// the avatar url is checked by the caller, it must point at an uploaded image
+    pub async fn update_profile(
+        id: i64,
+        update: UpdateProfile,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        let fullname = match update.fullname.as_deref().map(str::trim) {
+            Some("") => {
+                return Err(ChatCoreError::BadRequest(
+                    "full name can't be empty".to_string(),
+                ))
+            }
+            fullname => fullname.map(str::to_string),
+        };
+        let display_name = profile_field("display name", update.display_name, 64)?;
+        let title = profile_field("title", update.title, 128)?;
+        let timezone = profile_field("timezone", update.timezone, 64)?;
+        if let Some(Some(timezone)) = &timezone {
+            // IANA names, e.g. America/Argentina/Buenos_Aires or Etc/GMT+8
+            let valid = timezone
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "/_-+".contains(c));
+            if !valid {
+                return Err(ChatCoreError::BadRequest(format!(
+                    "invalid timezone {}",
+                    timezone
+                )));
+            }
+        }
+        let pronoun = profile_field("pronoun", update.pronoun, 32)?;
+        let avatar = profile_field("avatar", update.avatar, 1024)?;
+
+        // a field is set to $n if given, NULL if given empty and left as is otherwise
+        let user: User = query_as(
             r#"
-            SELECT *
-            FROM users
-            WHERE id = ANY($1)
+            UPDATE users
+            SET fullname = COALESCE($2, fullname),
+                display_name = CASE WHEN $3 THEN $4 ELSE display_name END,
+                title = CASE WHEN $5 THEN $6 ELSE title END,
+                timezone = CASE WHEN $7 THEN $8 ELSE timezone END,
+                pronoun = CASE WHEN $9 THEN $10 ELSE pronoun END,
+                avatar = CASE WHEN $11 THEN $12 ELSE avatar END,
+                updated_at = NOW()
+            WHERE id = $1 AND deactivated_at IS NULL
+            RETURNING *
             "#,
         )
-        .bind(ids)
+        .bind(id)
+        .bind(fullname)
+        .bind(display_name.is_some())
+        .bind(display_name.flatten())
+        .bind(title.is_some())
+        .bind(title.flatten())
+        .bind(timezone.is_some())
+        .bind(timezone.flatten())
+        .bind(pronoun.is_some())
+        .bind(pronoun.flatten())
+        .bind(avatar.is_some())
+        .bind(avatar.flatten())
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ChatCoreError::NotFound(format!("user {}", id)))?;
+
+        Ok(User {
+            password_hash: None,
+            ..user
+        })
+    }
+
+    /// whether the file is the avatar of someone sharing a workspace with the user.
+    /// Avatars are shown in every workspace of their owner, whichever one stores them
+    pub async fn is_avatar_seen_by(
+        file: &str,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<bool, ChatCoreError> {
+        let seen = query_scalar(
+            r#"
+            SELECT EXISTS(
+                SELECT 1
+                FROM users u
+                JOIN workspace_members owner ON owner.user_id = u.id
+                JOIN workspace_members viewer ON viewer.ws_id = owner.ws_id
+                WHERE u.avatar = $1 AND viewer.user_id = $2
+            )
+            "#,
+        )
+        .bind(file)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(seen)
+    }
+
+    /// a deactivated user the viewer may come across: one who belonged to the workspace,
+    /// or who sent messages to the viewer's chats
+    pub async fn find_deactivated_seen_by(
+        id: i64,
+        ws_id: i64,
+        viewer_id: i64,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, ChatCoreError> {
+        let user: Option<User> = query_as(
+            r#"
+            SELECT u.*
+            FROM users u
+            WHERE u.id = $1 AND u.deactivated_at IS NOT NULL AND (
+                u.ws_id = $2 OR EXISTS(
+                    SELECT 1
+                    FROM messages m
+                    JOIN chats c ON c.id = m.chat_id
+                    WHERE m.sender_id = u.id AND c.ws_id = $2 AND $3 = ANY(c.members)
+                )
+            )
+            "#,
+        )
+        .bind(id)
+        .bind(ws_id)
+        .bind(viewer_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(user.map(|mut user| {
+            user.password_hash.take();
+            user
+        }))
+    }
+
+    /// ids of the users sharing a workspace with the user, the user included
+    pub async fn coworker_ids(id: i64, pool: &PgPool) -> Result<Vec<i64>, ChatCoreError> {
+        let ids: Vec<(i64,)> = query_as(
+            r#"
+            SELECT DISTINCT other.user_id
+            FROM workspace_members m
+            JOIN workspace_members other ON other.ws_id = m.ws_id
+            WHERE m.user_id = $1
+            "#,
+        )
+        .bind(id)
         .fetch_all(pool)
         .await?;
 
-        Ok(users)
+        Ok(ids.into_iter().map(|(id,)| id).collect())
     }
 }
 
+/// match the text literally in a LIKE pattern
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// `None` to keep the field, `Some(None)` to clear it
+fn profile_field(
+    name: &str,
+    value: Option<String>,
+    max_len: usize,
+) -> Result<Option<Option<String>>, ChatCoreError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let value = value.trim();
+    if value.chars().count() > max_len {
+        return Err(ChatCoreError::BadRequest(format!(
+            "{} must be at most {} characters",
+            name, max_len
+        )));
+    }
+    Ok(Some((!value.is_empty()).then(|| value.to_string())))
+}
+
 #[cfg(test)]
 impl User {
     pub fn new(id: i64, ws_id: i64, fullname: String, email: String) -> Self {
@@ -183,6 +613,10 @@ impl User {
             fullname,
             email,
             password_hash: None,
+            token_generation: 0,
+            email_verified_at: None,
+            deactivated_at: None,
+            profile: Default::default(),
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         }
@@ -191,6 +625,7 @@ impl User {
 
 #[cfg(test)]
 mod tests {
+    use crate::models::{CreateWorkspaceInvite, WorkspaceInvite};
     use crate::test_util::get_test_pool;
 
     use super::*;
@@ -209,11 +644,16 @@ mod tests {
             fullname: name.to_string(),
             email: email.to_string(),
             password: pwd.to_string(),
+            invite_code: None,
         };
-        let user = User::create(create_user, &pool).await.unwrap();
+        let user = User::create(create_user, &Argon2Config::default(), &pool)
+            .await
+            .unwrap();
 
         assert_eq!(
-            User::verify_password(email, pwd, &pool).await.unwrap(),
+            User::verify_password(email, pwd, &Argon2Config::default(), &pool)
+                .await
+                .unwrap(),
             user
         );
         let user_get = User::find_user_by_email(email, &pool)
@@ -224,4 +664,259 @@ mod tests {
         assert_eq!(user_get.email, user.email);
         assert_eq!(user_get.password_hash, None);
     }
+
+    #[tokio::test]
+    async fn test_create_user_should_follow_join_policy() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let argon2 = Argon2Config::default();
+        let signup = |ws_name: &str, email: &str, invite_code: Option<String>| CreateUser {
+            ws_name: ws_name.to_string(),
+            fullname: "ann".to_string(),
+            email: email.to_string(),
+            password: "123456".to_string(),
+            invite_code,
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE workspaces
+            SET join_policy = 'allowed_domains', allowed_domains = '{bbc.com}'
+            WHERE id = 1
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("UPDATE workspaces SET join_policy = 'open' WHERE id = 2")
+            .execute(&pool)
+            .await?;
+
+        // bbc admits verified emails of its own domain, cnn is open, fox is invite only
+        let res = User::create(signup("bbc", "ann@BBC.com", None), &argon2, &pool).await;
+        assert!(matches!(res, Err(ChatCoreError::Forbidden(_))));
+        let res = User::create(signup("bbc", "ann@cnn.com", None), &argon2, &pool).await;
+        assert!(matches!(res, Err(ChatCoreError::Forbidden(_))));
+        let user = User::create(signup("cnn", "ann@cnn.com", None), &argon2, &pool).await?;
+        assert_eq!(user.ws_id, 2);
+        let user = User::create(signup("cnn", "ann@BBC.com", None), &argon2, &pool).await?;
+        let res = WorkspaceMember::join("bbc", &user, None, &pool).await;
+        assert!(matches!(res, Err(ChatCoreError::Forbidden(_))));
+        sqlx::query("UPDATE users SET email_verified_at = NOW() WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await?;
+        let user = User::find_user_by_id(user.id, &pool).await?.unwrap();
+        WorkspaceMember::join("bbc", &user, None, &pool).await?;
+        let res = User::create(signup("fox", "ann@fox.com", None), &argon2, &pool).await;
+        assert!(matches!(res, Err(ChatCoreError::Forbidden(_))));
+
+        let invite = WorkspaceInvite::create(
+            3,
+            5,
+            CreateWorkspaceInvite {
+                max_uses: Some(1),
+                expires_in_hours: None,
+            },
+            &pool,
+        )
+        .await?;
+        let res = User::create(
+            signup("bbc", "ann@fox.com", Some(invite.code.clone())),
+            &argon2,
+            &pool,
+        )
+        .await;
+        assert!(res.is_err());
+        // a signup failing after the invite was checked doesn't use it up
+        let mut too_long = signup("fox", "ann@fox.com", Some(invite.code.clone()));
+        too_long.fullname = "a".repeat(256);
+        assert!(User::create(too_long, &argon2, &pool).await.is_err());
+        assert!(User::find_user_by_email("ann@fox.com", &pool)
+            .await?
+            .is_none());
+        let user = User::create(
+            signup("fox", "ann@fox.com", Some(invite.code)),
+            &argon2,
+            &pool,
+        )
+        .await?;
+        assert_eq!(user.ws_id, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_password_should_not_reveal_accounts() {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let argon2 = Argon2Config::default();
+
+        let wrong_password = User::verify_password("alice@bbc.com", "wrong", &argon2, &pool)
+            .await
+            .unwrap_err();
+        let unknown_email = User::verify_password("nobody@bbc.com", "123456", &argon2, &pool)
+            .await
+            .unwrap_err();
+        assert_eq!(wrong_password.to_string(), unknown_email.to_string());
+        assert!(matches!(unknown_email, ChatCoreError::InvalidCredentials));
+
+        // the super admin's stored hash is not a PHC string
+        let malformed = User::verify_password("4qLrX@example.com", "superadmin", &argon2, &pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(malformed, ChatCoreError::InvalidCredentials));
+    }
+
+    #[tokio::test]
+    async fn test_verify_password_should_rehash_outdated_params() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let argon2 = Argon2Config::new(32 * 1024, 3, 1);
+        // a wrong password against the outdated hash takes as long as against a current one
+        let start = Instant::now();
+        let err = User::verify_password("alice@bbc.com", "wrong", &argon2, &pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ChatCoreError::InvalidCredentials));
+        assert!(start.elapsed() >= argon2.verify_cost()?);
+
+        let user = User::verify_password("alice@bbc.com", "123456", &argon2, &pool).await?;
+        assert!(user
+            .password_hash
+            .unwrap()
+            .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+
+        let user = User::verify_password("alice@bbc.com", "123456", &argon2, &pool).await?;
+        assert!(user
+            .password_hash
+            .unwrap()
+            .starts_with("$argon2id$v=19$m=32768,t=3,p=1$"));
+        // tokens stay valid
+        assert_eq!(user.token_generation, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_profile() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let update = UpdateProfile {
+            display_name: Some(" Al ".to_string()),
+            timezone: Some("Europe/Berlin".to_string()),
+            pronoun: Some("she/her".to_string()),
+            ..Default::default()
+        };
+        let user = User::update_profile(2, update, &pool).await?;
+        assert_eq!(user.fullname, "alice");
+        assert_eq!(user.profile.display_name.as_deref(), Some("Al"));
+        assert_eq!(user.profile.timezone.as_deref(), Some("Europe/Berlin"));
+
+        let update = UpdateProfile {
+            display_name: Some("".to_string()),
+            title: Some("engineer".to_string()),
+            ..Default::default()
+        };
+        let user = User::update_profile(2, update, &pool).await?;
+        assert_eq!(user.profile.display_name, None);
+        assert_eq!(user.profile.pronoun.as_deref(), Some("she/her"));
+        assert_eq!(user.profile.title.as_deref(), Some("engineer"));
+
+        let update = UpdateProfile {
+            timezone: Some("Berlin; DROP".to_string()),
+            ..Default::default()
+        };
+        assert!(User::update_profile(2, update, &pool).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_users_by_workspace() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 3")
+            .execute(&pool)
+            .await?;
+        let names = |users: Vec<User>| users.into_iter().map(|u| u.fullname).collect::<Vec<_>>();
+
+        let list = ListUsers {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(names(page.clone()), ["alice", "bob"]);
+        let list = ListUsers {
+            last_id: Some(page[1].id),
+            ..Default::default()
+        };
+        let page = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(names(page), ["charlie", "tyran"]);
+
+        let list = ListUsers {
+            sort: UserSort::Email,
+            desc: true,
+            ..Default::default()
+        };
+        let users = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(names(users), ["tyran", "charlie", "bob", "alice"]);
+
+        let list = ListUsers {
+            q: Some("CHA".to_string()),
+            ..Default::default()
+        };
+        let users = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(names(users), ["charlie"]);
+        let list = ListUsers {
+            q: Some("%".to_string()),
+            ..Default::default()
+        };
+        assert!(User::list_users_by_workspace(1, list, &pool)
+            .await?
+            .is_empty());
+
+        let list = ListUsers {
+            q: Some("charly".to_string()),
+            fuzzy: true,
+            ..Default::default()
+        };
+        let users = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(names(users), ["charlie"]);
+
+        let list = ListUsers {
+            role: Some(WorkspaceRole::Admin),
+            active: Some(true),
+            ..Default::default()
+        };
+        let users = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(names(users), ["bob"]);
+
+        let list = ListUsers {
+            limit: Some(0),
+            ..Default::default()
+        };
+        let err = User::list_users_by_workspace(1, list, &pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ChatCoreError::BadRequest(_)));
+
+        // doe(5) belongs to fox, a cursor outside the workspace is refused
+        let list = ListUsers {
+            last_id: Some(5),
+            ..Default::default()
+        };
+        let err = User::list_users_by_workspace(1, list, &pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ChatCoreError::BadRequest(_)));
+
+        // deactivated users are listed as former members
+        let admin = WorkspaceMember::find(1, 3, &pool).await?.unwrap();
+        admin.deactivate(4, 3, &pool).await?;
+        let list = ListUsers {
+            active: Some(false),
+            ..Default::default()
+        };
+        let users = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(names(users), ["Deactivated user"]);
+        let list = ListUsers {
+            sort: UserSort::Joined,
+            ..Default::default()
+        };
+        let users = User::list_users_by_workspace(1, list, &pool).await?;
+        assert_eq!(users.len(), 4);
+        Ok(())
+    }
 }
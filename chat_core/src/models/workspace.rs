@@ -1,21 +1,54 @@
-use sqlx::{query_as, PgPool};
+use sqlx::{query, query_as, PgConnection, PgPool};
+use tracing::info;
 
 use crate::error::ChatCoreError;
-use crate::models::{CreateWorkspace, User, Workspace};
+use crate::models::{
+    CreateWorkspace, JoinPolicy, UpdateJoinPolicy, Workspace, WorkspaceInvite, WorkspaceMember,
+};
 
 impl Workspace {
-    pub async fn create(create_ws: CreateWorkspace, pool: &PgPool) -> Result<Self, ChatCoreError> {
+    /// the owner becomes its first member, 0 for a workspace awaiting its first user
+    pub async fn create(
+        create_ws: CreateWorkspace,
+        owner_id: i64,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        let mut conn = pool.acquire().await?;
+        Self::insert(&create_ws.name, owner_id, &mut conn).await
+    }
+
+    /// the name is only checked by the unique index, so concurrent creates can't both succeed
+    pub(crate) async fn insert(
+        name: &str,
+        owner_id: i64,
+        conn: &mut PgConnection,
+    ) -> Result<Self, ChatCoreError> {
+        let name = check_name(name)?;
         let ws = query_as(
             r#"
-            INSERT INTO workspaces (name, owner_id)
-            VALUES ($1, $2)
-            RETURNING *
+            WITH ws AS (
+                INSERT INTO workspaces (name, owner_id)
+                VALUES ($1, $2)
+                RETURNING *
+            ), owner AS (
+                INSERT INTO workspace_members (ws_id, user_id, role)
+                SELECT id, owner_id, 'owner'
+                FROM ws
+                WHERE owner_id <> 0
+            )
+            SELECT * FROM ws
             "#,
         )
-        .bind(create_ws.name)
-        .bind(create_ws.owner_id)
-        .fetch_one(pool)
-        .await?;
+        .bind(name)
+        .bind(owner_id)
+        .fetch_one(conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                ChatCoreError::Conflict(format!("workspace {} already exists", name))
+            }
+            e => e.into(),
+        })?;
 
         Ok(ws)
     }
@@ -28,7 +61,7 @@ impl Workspace {
             r#"
             SELECT *
             FROM workspaces
-            WHERE name = $1
+            WHERE name = $1 AND archived_at IS NULL
             "#,
         )
         .bind(name)
@@ -38,42 +71,331 @@ impl Workspace {
         Ok(workspace)
     }
 
-    pub(crate) async fn update_owner(
-        name: &str,
-        email: &str,
+    pub async fn find_workspace_by_id(
+        id: i64,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, ChatCoreError> {
+        let workspace: Option<Workspace> = query_as(
+            r#"
+            SELECT *
+            FROM workspaces
+            WHERE id = $1 AND archived_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(workspace)
+    }
+
+    /// the new owner must already be a member, the previous owner stays on as an admin
+    pub async fn update_owner(
+        id: i64,
+        owner_id: i64,
         pool: &PgPool,
     ) -> Result<Self, ChatCoreError> {
-        let user = User::find_user_by_email(email, pool).await?;
-        if user.is_none() {
-            return Err(ChatCoreError::NotFound("user not found".to_string()));
+        if WorkspaceMember::find(id, owner_id, pool).await?.is_none() {
+            return Err(ChatCoreError::CrossWorkspace(format!(
+                "user {} is not a member of workspace {}",
+                owner_id, id
+            )));
         }
-        let owner_id = user.unwrap().id;
+        let mut tx = pool.begin().await?;
+        query(
+            r#"
+            UPDATE workspace_members
+            SET role = CASE WHEN user_id = $2 THEN 'owner' ELSE 'admin' END::workspace_role
+            WHERE ws_id = $1 AND (user_id = $2 OR role = 'owner')
+            "#,
+        )
+        .bind(id)
+        .bind(owner_id)
+        .execute(&mut *tx)
+        .await?;
         let workspace = query_as(
             r#"
             UPDATE workspaces
-            SET owner_id = $1
-            WHERE name = $2
+            SET owner_id = $2, updated_at = NOW()
+            WHERE id = $1 AND archived_at IS NULL
             RETURNING *
             "#,
         )
+        .bind(id)
         .bind(owner_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ChatCoreError::NotFound(format!("workspace {}", id)))?;
+        tx.commit().await?;
+
+        info!("workspace {} owner updated to {}", id, owner_id);
+        Ok(workspace)
+    }
+
+    pub async fn rename(id: i64, name: &str, pool: &PgPool) -> Result<Self, ChatCoreError> {
+        let name = check_name(name)?;
+        if let Some(ws) = Self::find_workspace_by_name(name, pool).await? {
+            if ws.id == id {
+                return Ok(ws);
+            }
+            return Err(ChatCoreError::Conflict(format!(
+                "workspace {} already exists",
+                name
+            )));
+        }
+        let workspace = query_as(
+            r#"
+            UPDATE workspaces
+            SET name = $2, updated_at = NOW()
+            WHERE id = $1 AND archived_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(id)
         .bind(name)
-        .fetch_one(pool)
-        .await?;
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ChatCoreError::NotFound(format!("workspace {}", id)))?;
 
         Ok(workspace)
     }
 
-    pub async fn list_workspaces(pool: &PgPool) -> Result<Vec<Self>, ChatCoreError> {
+    /// delete the chats, messages, invitations and memberships of the workspace and archive it.
+    /// Sessions acting in it are revoked. Accounts are kept: users whose default workspace it
+    /// was move to another workspace they belong to, users without one only get account
+    /// access until they join a workspace. Stored files are left to the caller
+    pub async fn archive(id: i64, pool: &PgPool) -> Result<Self, ChatCoreError> {
+        if id == 0 {
+            return Err(ChatCoreError::Forbidden(
+                "the super admin workspace can't be deleted".to_string(),
+            ));
+        }
+        let mut tx = pool.begin().await?;
+        let workspace: Self = query_as(
+            r#"
+            UPDATE workspaces
+            SET archived_at = NOW(), updated_at = NOW()
+            WHERE id = $1 AND archived_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ChatCoreError::NotFound(format!("workspace {}", id)))?;
+
+        let statements = [
+            "DELETE FROM messages WHERE chat_id IN (SELECT id FROM chats WHERE ws_id = $1)",
+            "DELETE FROM chats WHERE ws_id = $1",
+            "DELETE FROM workspace_invites WHERE ws_id = $1",
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE revoked_at IS NULL AND session_id IN (
+                SELECT s.id
+                FROM sessions s
+                JOIN users u ON u.id = s.user_id
+                WHERE s.ws_id = $1 OR (s.ws_id IS NULL AND u.ws_id = $1)
+            )
+            "#,
+            r#"
+            UPDATE sessions s
+            SET revoked_at = COALESCE(s.revoked_at, NOW()), ws_id = NULL
+            FROM users u
+            WHERE u.id = s.user_id AND (s.ws_id = $1 OR (s.ws_id IS NULL AND u.ws_id = $1))
+            "#,
+            r#"
+            UPDATE users u
+            SET ws_id = m.ws_id
+            FROM (
+                SELECT user_id, MIN(ws_id) AS ws_id
+                FROM workspace_members
+                WHERE ws_id <> $1
+                GROUP BY user_id
+            ) m
+            WHERE u.id = m.user_id AND u.ws_id = $1
+            "#,
+            "DELETE FROM workspace_members WHERE ws_id = $1",
+        ];
+        for statement in statements {
+            query(statement).bind(id).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        info!("workspace {} archived", workspace.name);
+        Ok(workspace)
+    }
+
+    /// workspaces the user is a member of
+    pub async fn list_for_user(user_id: i64, pool: &PgPool) -> Result<Vec<Self>, ChatCoreError> {
         let workspaces = query_as(
             r#"
-            SELECT *
-            FROM workspaces
+            SELECT w.*
+            FROM workspaces w
+            JOIN workspace_members m ON m.ws_id = w.id
+            WHERE m.user_id = $1
+            ORDER BY w.id
             "#,
         )
+        .bind(user_id)
         .fetch_all(pool)
         .await?;
 
         Ok(workspaces)
     }
+
+    pub async fn update_join_policy(
+        id: i64,
+        update: UpdateJoinPolicy,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        let domains: Vec<String> = update
+            .allowed_domains
+            .iter()
+            .map(|d| d.trim().trim_start_matches('@').to_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        if update.join_policy == JoinPolicy::AllowedDomains && domains.is_empty() {
+            return Err(ChatCoreError::BadRequest(
+                "allowed_domains can't be empty".to_string(),
+            ));
+        }
+
+        let workspace = query_as(
+            r#"
+            UPDATE workspaces
+            SET join_policy = $1, allowed_domains = $2, updated_at = NOW()
+            WHERE id = $3
+            RETURNING *
+            "#,
+        )
+        .bind(update.join_policy)
+        .bind(domains)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        workspace.ok_or_else(|| ChatCoreError::NotFound(format!("workspace {}", id)))
+    }
+
+    /// let a new member in, with an invite or an email the join policy admits. Run it in
+    /// the transaction adding the member, so the invite is only used up if that succeeds
+    pub(crate) async fn admit(
+        &self,
+        email: &str,
+        email_verified: bool,
+        invite_code: Option<&str>,
+        conn: &mut PgConnection,
+    ) -> Result<(), ChatCoreError> {
+        match invite_code {
+            Some(code) => {
+                WorkspaceInvite::redeem(code, self.id, conn).await?;
+                Ok(())
+            }
+            None if !self.admits(email) => Err(ChatCoreError::Forbidden(format!(
+                "workspace {} requires an invitation",
+                self.name
+            ))),
+            // anyone can type in an address of the domain
+            None if self.join_policy == JoinPolicy::AllowedDomains && !email_verified => {
+                Err(ChatCoreError::Forbidden(format!(
+                    "verify your email to join workspace {}",
+                    self.name
+                )))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// whether the join policy lets the email in without an invite
+    pub fn admits(&self, email: &str) -> bool {
+        match self.join_policy {
+            JoinPolicy::Open => true,
+            JoinPolicy::InviteOnly => false,
+            JoinPolicy::AllowedDomains => email
+                .rsplit_once('@')
+                .map(|(_, domain)| domain.to_lowercase())
+                .is_some_and(|domain| self.allowed_domains.contains(&domain)),
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<&str, ChatCoreError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        return Err(ChatCoreError::BadRequest(
+            "workspace name must be 1 to 64 characters".to_string(),
+        ));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Chat, Session, SessionInfo, User, WorkspaceRole};
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_create_and_transfer_workspace() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let create = || CreateWorkspace {
+            name: "abc".to_string(),
+        };
+        let ws = Workspace::create(create(), 1, &pool).await?;
+        assert_eq!(ws.owner_id, 1);
+        let owner = WorkspaceMember::find(ws.id, 1, &pool).await?.unwrap();
+        assert_eq!(owner.role, WorkspaceRole::Owner);
+        let err = Workspace::create(create(), 2, &pool).await.unwrap_err();
+        assert!(matches!(err, ChatCoreError::Conflict(_)));
+        for name in ["  ", &"a".repeat(65)] {
+            let create = CreateWorkspace {
+                name: name.to_string(),
+            };
+            let err = Workspace::create(create, 2, &pool).await.unwrap_err();
+            assert!(matches!(err, ChatCoreError::BadRequest(_)));
+        }
+
+        let err = Workspace::update_owner(ws.id, 2, &pool).await.unwrap_err();
+        assert!(matches!(err, ChatCoreError::CrossWorkspace(_)));
+        WorkspaceMember::add(ws.id, 2, WorkspaceRole::Member, &mut *pool.acquire().await?).await?;
+        let ws = Workspace::update_owner(ws.id, 2, &pool).await?;
+        assert_eq!(ws.owner_id, 2);
+        let previous = WorkspaceMember::find(ws.id, 1, &pool).await?.unwrap();
+        assert_eq!(previous.role, WorkspaceRole::Admin);
+
+        let err = Workspace::rename(ws.id, "bbc", &pool).await.unwrap_err();
+        assert!(matches!(err, ChatCoreError::Conflict(_)));
+        let ws = Workspace::rename(ws.id, " abcd ", &pool).await?;
+        assert_eq!(ws.name, "abcd");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive_workspace() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        // tyran also belongs to fox
+        WorkspaceMember::add(3, 1, WorkspaceRole::Member, &mut *pool.acquire().await?).await?;
+        let session = Session::create(2, &SessionInfo::default(), &pool).await?;
+
+        let ws = Workspace::archive(1, &pool).await?;
+        assert_eq!(ws.name, "bbc");
+        assert!(Workspace::find_workspace_by_id(1, &pool).await?.is_none());
+        assert!(Chat::list_chats_in_workspace(1, &pool).await?.is_empty());
+        assert!(WorkspaceMember::list(1, &pool).await?.is_empty());
+        let tyran = User::find_user_by_id(1, &pool).await?.unwrap();
+        assert_eq!(tyran.ws_id, 3);
+        // accounts are kept
+        let alice = User::find_user_by_id(2, &pool).await?.unwrap();
+        assert_eq!(alice.ws_id, 1);
+        let session = Session::find(session.id, &pool).await?.unwrap();
+        assert!(session.revoked_at.is_some());
+
+        // the name is free again
+        let ws = Workspace::create(CreateWorkspace { name: ws.name }, 2, &pool).await?;
+        assert_ne!(ws.id, 1);
+        assert!(Workspace::archive(1, &pool).await.is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,162 @@
+use chrono::{Duration, Utc};
+use sqlx::{query, query_as, PgConnection, PgPool};
+
+use crate::error::ChatCoreError;
+use crate::models::{CreateWorkspaceInvite, NewWorkspaceInvite, WorkspaceInvite};
+use crate::utils::token::{generate_token, hash_token};
+
+const DEFAULT_INVITE_HOURS: i64 = 7 * 24;
+const MAX_INVITE_HOURS: i64 = 30 * 24;
+
+impl WorkspaceInvite {
+    pub async fn create(
+        ws_id: i64,
+        created_by: i64,
+        input: CreateWorkspaceInvite,
+        pool: &PgPool,
+    ) -> Result<NewWorkspaceInvite, ChatCoreError> {
+        if input.max_uses.is_some_and(|n| n < 1) {
+            return Err(ChatCoreError::BadRequest(
+                "max_uses must be at least 1".to_string(),
+            ));
+        }
+        let hours = input.expires_in_hours.unwrap_or(DEFAULT_INVITE_HOURS);
+        if !(1..=MAX_INVITE_HOURS).contains(&hours) {
+            return Err(ChatCoreError::BadRequest(format!(
+                "expires_in_hours must be between 1 and {}",
+                MAX_INVITE_HOURS
+            )));
+        }
+
+        let code = generate_token();
+        let info = query_as(
+            r#"
+            INSERT INTO workspace_invites (ws_id, created_by, code_hash, max_uses, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(ws_id)
+        .bind(created_by)
+        .bind(hash_token(&code))
+        .bind(input.max_uses)
+        .bind(Utc::now() + Duration::hours(hours))
+        .fetch_one(pool)
+        .await?;
+
+        Ok(NewWorkspaceInvite { code, info })
+    }
+
+    /// invites that weren't revoked, newest first
+    pub async fn list(ws_id: i64, pool: &PgPool) -> Result<Vec<Self>, ChatCoreError> {
+        let invites = query_as(
+            r#"
+            SELECT *
+            FROM workspace_invites
+            WHERE ws_id = $1 AND revoked_at IS NULL
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(invites)
+    }
+
+    pub async fn revoke(id: i64, ws_id: i64, pool: &PgPool) -> Result<(), ChatCoreError> {
+        let revoked = query(
+            r#"
+            UPDATE workspace_invites
+            SET revoked_at = NOW()
+            WHERE id = $1 AND ws_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .bind(ws_id)
+        .execute(pool)
+        .await?;
+        if revoked.rows_affected() == 0 {
+            return Err(ChatCoreError::NotFound(format!("invite {}", id)));
+        }
+
+        Ok(())
+    }
+
+    /// use up one seat of the invite, it must belong to the workspace being joined
+    pub(crate) async fn redeem(
+        code: &str,
+        ws_id: i64,
+        conn: &mut PgConnection,
+    ) -> Result<Self, ChatCoreError> {
+        let invite: Option<WorkspaceInvite> = query_as(
+            r#"
+            UPDATE workspace_invites
+            SET uses = uses + 1
+            WHERE code_hash = $1 AND ws_id = $2 AND revoked_at IS NULL AND expires_at > NOW()
+                AND (max_uses IS NULL OR uses < max_uses)
+            RETURNING *
+            "#,
+        )
+        .bind(hash_token(code))
+        .bind(ws_id)
+        .fetch_optional(conn)
+        .await?;
+
+        invite.ok_or_else(|| ChatCoreError::Forbidden("invalid or expired invitation".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_redeem_invite() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let single = CreateWorkspaceInvite {
+            max_uses: Some(1),
+            expires_in_hours: None,
+        };
+        let invite = WorkspaceInvite::create(3, 5, single.clone(), &pool).await?;
+        let mut conn = pool.acquire().await?;
+
+        // invites only work for their own workspace
+        assert!(WorkspaceInvite::redeem(&invite.code, 1, &mut conn)
+            .await
+            .is_err());
+        let redeemed = WorkspaceInvite::redeem(&invite.code, 3, &mut conn).await?;
+        assert_eq!(redeemed.uses, 1);
+        assert!(WorkspaceInvite::redeem(&invite.code, 3, &mut conn)
+            .await
+            .is_err());
+
+        let multi = WorkspaceInvite::create(
+            3,
+            5,
+            CreateWorkspaceInvite {
+                max_uses: None,
+                expires_in_hours: Some(1),
+            },
+            &pool,
+        )
+        .await?;
+        for _ in 0..3 {
+            WorkspaceInvite::redeem(&multi.code, 3, &mut conn).await?;
+        }
+        WorkspaceInvite::revoke(multi.info.id, 3, &pool).await?;
+        assert!(WorkspaceInvite::redeem(&multi.code, 3, &mut conn)
+            .await
+            .is_err());
+        assert_eq!(WorkspaceInvite::list(3, &pool).await?.len(), 1);
+
+        let invalid = CreateWorkspaceInvite {
+            max_uses: Some(0),
+            ..single
+        };
+        assert!(WorkspaceInvite::create(3, 5, invalid, &pool).await.is_err());
+        Ok(())
+    }
+}
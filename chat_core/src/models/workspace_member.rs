@@ -0,0 +1,509 @@
+use std::fmt;
+
+use sqlx::{query, query_as, PgConnection, PgPool};
+use tracing::info;
+
+use crate::error::ChatCoreError;
+use crate::models::{Permission, User, Workspace, WorkspaceMember, WorkspaceRole};
+
+impl WorkspaceRole {
+    fn rank(self) -> u8 {
+        match self {
+            WorkspaceRole::Owner => 3,
+            WorkspaceRole::Admin => 2,
+            WorkspaceRole::Member => 1,
+            WorkspaceRole::Guest => 0,
+        }
+    }
+
+    pub fn can(self, permission: Permission) -> bool {
+        self.at_least(permission.min_role())
+    }
+
+    pub fn at_least(self, other: WorkspaceRole) -> bool {
+        self.rank() >= other.rank()
+    }
+
+    /// a role can only manage roles below it, so admins can't promote to or demote admins
+    pub fn outranks(self, other: WorkspaceRole) -> bool {
+        self.rank() > other.rank()
+    }
+}
+
+impl fmt::Display for WorkspaceRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WorkspaceRole::Owner => "owner",
+            WorkspaceRole::Admin => "admin",
+            WorkspaceRole::Member => "member",
+            WorkspaceRole::Guest => "guest",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Permission {
+    pub fn min_role(self) -> WorkspaceRole {
+        match self {
+            Permission::CreateWorkspace
+            | Permission::ManageWorkspace
+            | Permission::ManageMembers => WorkspaceRole::Admin,
+            Permission::ViewWorkspace
+            | Permission::CreateChat
+            | Permission::ManageChat
+            | Permission::UploadFile => WorkspaceRole::Member,
+            Permission::ViewChat | Permission::SendMessage => WorkspaceRole::Guest,
+            Permission::TransferOwnership | Permission::DeleteWorkspace => WorkspaceRole::Owner,
+        }
+    }
+}
+
+impl WorkspaceMember {
+    pub async fn find(
+        ws_id: i64,
+        user_id: i64,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, ChatCoreError> {
+        let member = query_as(
+            r#"
+            SELECT *
+            FROM workspace_members
+            WHERE ws_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(member)
+    }
+
+    pub async fn list(ws_id: i64, pool: &PgPool) -> Result<Vec<Self>, ChatCoreError> {
+        let members = query_as(
+            r#"
+            SELECT *
+            FROM workspace_members
+            WHERE ws_id = $1
+            ORDER BY user_id
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(members)
+    }
+
+    pub(crate) async fn add(
+        ws_id: i64,
+        user_id: i64,
+        role: WorkspaceRole,
+        conn: &mut PgConnection,
+    ) -> Result<Self, ChatCoreError> {
+        let member = query_as(
+            r#"
+            INSERT INTO workspace_members (ws_id, user_id, role)
+            VALUES ($1, $2, $3)
+            RETURNING *
+            "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .bind(role)
+        .fetch_one(conn)
+        .await?;
+
+        Ok(member)
+    }
+
+    /// join an existing workspace as a member, under the same rules as signup.
+    /// Users no longer in their default workspace get the joined one as default
+    pub async fn join(
+        ws_name: &str,
+        user: &User,
+        invite_code: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        let ws = Workspace::find_workspace_by_name(ws_name, pool)
+            .await?
+            .ok_or_else(|| ChatCoreError::NotFound(format!("workspace {}", ws_name)))?;
+        if Self::find(ws.id, user.id, pool).await?.is_some() {
+            return Err(ChatCoreError::Conflict(format!(
+                "already a member of {}",
+                ws.name
+            )));
+        }
+        let mut tx = pool.begin().await?;
+        ws.admit(
+            &user.email,
+            user.email_verified_at.is_some(),
+            invite_code,
+            &mut tx,
+        )
+        .await?;
+        let member = Self::add(ws.id, user.id, WorkspaceRole::Member, &mut tx).await?;
+        query(
+            r#"
+            UPDATE users u
+            SET ws_id = $2
+            WHERE id = $1 AND NOT EXISTS (
+                SELECT 1 FROM workspace_members m WHERE m.user_id = u.id AND m.ws_id = u.ws_id
+            )
+            "#,
+        )
+        .bind(user.id)
+        .bind(ws.id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(member)
+    }
+
+    /// change the role of another member, ownership is never granted this way
+    pub async fn update_role(
+        &self,
+        user_id: i64,
+        role: WorkspaceRole,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        if role == WorkspaceRole::Owner {
+            return Err(ChatCoreError::BadRequest(
+                "ownership can only be transferred by the owner".to_string(),
+            ));
+        }
+        let target = Self::find(self.ws_id, user_id, pool)
+            .await?
+            .ok_or_else(|| ChatCoreError::NotFound(format!("member {}", user_id)))?;
+        if !self.role.outranks(target.role) || !self.role.outranks(role) {
+            return Err(ChatCoreError::Forbidden(format!(
+                "{} can't change a {} to {}",
+                self.role, target.role, role
+            )));
+        }
+
+        let member = query_as(
+            r#"
+            UPDATE workspace_members
+            SET role = $1
+            WHERE ws_id = $2 AND user_id = $3
+            RETURNING *
+            "#,
+        )
+        .bind(role)
+        .bind(self.ws_id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(member)
+    }
+
+    /// offboard a member, or oneself: chats and the workspace they own go to the successor.
+    /// Users of other workspaces only leave this one, its chats and the sessions acting in it.
+    /// Otherwise they leave every chat, their sessions and tokens are revoked and the account
+    /// is anonymized so their messages read as sent by a deactivated user
+    pub async fn deactivate(
+        &self,
+        user_id: i64,
+        successor_id: i64,
+        pool: &PgPool,
+    ) -> Result<User, ChatCoreError> {
+        let mut tx = pool.begin().await?;
+        // the memberships are locked so the checks still hold when the changes commit
+        let memberships: Vec<Self> = query_as(
+            r#"
+            SELECT *
+            FROM workspace_members
+            WHERE user_id = $1 OR (ws_id = $2 AND user_id = $3)
+            FOR UPDATE
+            "#,
+        )
+        .bind(user_id)
+        .bind(self.ws_id)
+        .bind(successor_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        let target = memberships
+            .iter()
+            .find(|m| m.ws_id == self.ws_id && m.user_id == user_id)
+            .ok_or_else(|| ChatCoreError::NotFound(format!("member {}", user_id)))?;
+        if user_id != self.user_id && !self.role.outranks(target.role) {
+            return Err(ChatCoreError::Forbidden(format!(
+                "{} can't deactivate a {}",
+                self.role, target.role
+            )));
+        }
+        if successor_id == user_id
+            || !memberships
+                .iter()
+                .any(|m| m.ws_id == self.ws_id && m.user_id == successor_id)
+        {
+            return Err(ChatCoreError::BadRequest(format!(
+                "successor {} must be another member of the workspace",
+                successor_id
+            )));
+        }
+        let target_role = target.role;
+        let last_membership = !memberships
+            .iter()
+            .any(|m| m.user_id == user_id && m.ws_id != self.ws_id);
+
+        query(
+            r#"
+            UPDATE chats
+            SET owner_id = $2,
+                members = CASE WHEN $2 = ANY(members) THEN members ELSE members || $2 END
+            WHERE ws_id = $3 AND owner_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(successor_id)
+        .bind(self.ws_id)
+        .execute(&mut *tx)
+        .await?;
+        if target_role == WorkspaceRole::Owner {
+            query("UPDATE workspace_members SET role = 'owner' WHERE ws_id = $1 AND user_id = $2")
+                .bind(self.ws_id)
+                .bind(successor_id)
+                .execute(&mut *tx)
+                .await?;
+            query("UPDATE workspaces SET owner_id = $2, updated_at = NOW() WHERE id = $1")
+                .bind(self.ws_id)
+                .bind(successor_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if !last_membership {
+            let statements = [
+                "UPDATE chats SET members = array_remove(members, $1) WHERE ws_id = $2 AND $1 = ANY(members)",
+                r#"
+                UPDATE refresh_tokens
+                SET revoked_at = NOW()
+                WHERE revoked_at IS NULL AND session_id IN (
+                    SELECT s.id
+                    FROM sessions s
+                    JOIN users u ON u.id = s.user_id
+                    WHERE s.user_id = $1 AND (s.ws_id = $2 OR (s.ws_id IS NULL AND u.ws_id = $2))
+                )
+                "#,
+                r#"
+                UPDATE sessions s
+                SET revoked_at = COALESCE(s.revoked_at, NOW()), ws_id = NULL
+                FROM users u
+                WHERE u.id = s.user_id AND s.user_id = $1
+                    AND (s.ws_id = $2 OR (s.ws_id IS NULL AND u.ws_id = $2))
+                "#,
+                "DELETE FROM workspace_members WHERE user_id = $1 AND ws_id = $2",
+                r#"
+                UPDATE users
+                SET ws_id = (SELECT MIN(ws_id) FROM workspace_members WHERE user_id = $1),
+                    updated_at = NOW()
+                WHERE id = $1 AND ws_id = $2
+                "#,
+            ];
+            for statement in statements {
+                query(statement)
+                    .bind(user_id)
+                    .bind(self.ws_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            let user = query_as("SELECT * FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_one(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            info!(
+                "user {} removed from workspace {}, successor {}",
+                user_id, self.ws_id, successor_id
+            );
+            return Ok(user);
+        }
+
+        let statements = [
+            "UPDATE chats SET members = array_remove(members, $1) WHERE $1 = ANY(members)",
+            "DELETE FROM workspace_members WHERE user_id = $1",
+            "UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            "UPDATE personal_access_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            "DELETE FROM user_identities WHERE user_id = $1",
+        ];
+        for statement in statements {
+            query(statement).bind(user_id).execute(&mut *tx).await?;
+        }
+        let user = query_as(
+            r#"
+            UPDATE users
+            SET fullname = 'Deactivated user',
+                deactivated_email_hash = encode(sha256(convert_to(lower(email), 'UTF8')), 'hex'),
+                email = 'deactivated-' || id || '@invalid',
+                display_name = NULL,
+                title = NULL,
+                pronoun = NULL,
+                avatar = NULL,
+                password_hash = NULL,
+                token_generation = token_generation + 1,
+                deactivated_at = NOW(),
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        info!("user {} deactivated, successor {}", user_id, successor_id);
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Chat, CreateUser};
+    use crate::test_util::get_test_pool;
+    use crate::utils::password::Argon2Config;
+
+    use super::*;
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(WorkspaceRole::Owner.can(Permission::ManageMembers));
+        assert!(WorkspaceRole::Admin.can(Permission::ManageWorkspace));
+        assert!(!WorkspaceRole::Member.can(Permission::ManageWorkspace));
+        assert!(WorkspaceRole::Member.can(Permission::CreateChat));
+        assert!(!WorkspaceRole::Guest.can(Permission::CreateChat));
+        assert!(WorkspaceRole::Guest.can(Permission::SendMessage));
+    }
+
+    #[tokio::test]
+    async fn test_update_member_role() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id IN (1, 2)")
+            .execute(&pool)
+            .await?;
+        let admin = WorkspaceMember::find(1, 1, &pool).await?.unwrap();
+        assert_eq!(admin.role, WorkspaceRole::Admin);
+
+        let guest = admin.update_role(3, WorkspaceRole::Guest, &pool).await?;
+        assert_eq!(guest.role, WorkspaceRole::Guest);
+
+        // admins can't promote to or demote other admins, nobody is made owner
+        assert!(admin
+            .update_role(3, WorkspaceRole::Admin, &pool)
+            .await
+            .is_err());
+        assert!(admin
+            .update_role(2, WorkspaceRole::Member, &pool)
+            .await
+            .is_err());
+        assert!(admin
+            .update_role(3, WorkspaceRole::Owner, &pool)
+            .await
+            .is_err());
+        // members of other workspaces can't be found
+        assert!(admin
+            .update_role(5, WorkspaceRole::Guest, &pool)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deactivate_member() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        for statement in [
+            "UPDATE workspace_members SET role = 'owner' WHERE user_id = 1",
+            "UPDATE workspace_members SET role = 'admin' WHERE user_id = 2",
+            "UPDATE workspaces SET owner_id = 1 WHERE id = 1",
+        ] {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        let admin = WorkspaceMember::find(1, 2, &pool).await?.unwrap();
+        // admins can't offboard the owner, members of other workspaces can't be found
+        assert!(admin.deactivate(1, 3, &pool).await.is_err());
+        assert!(admin.deactivate(5, 3, &pool).await.is_err());
+        assert!(admin.deactivate(3, 3, &pool).await.is_err());
+
+        // bob(3) owns general_ch(3), which also lists doe and eve of workspace 3
+        let bob = admin.deactivate(3, 4, &pool).await?;
+        assert_eq!(bob.fullname, "Deactivated user");
+        assert!(bob.deactivated_at.is_some());
+        assert!(User::find_user_by_email("bob@bbc.com", &pool)
+            .await?
+            .is_none());
+        assert!(User::find_in_workspace(3, 1, &pool).await?.is_none());
+        let chats = Chat::list_chats_in_workspace(1, &pool).await?;
+        assert!(chats.iter().all(|chat| !chat.members.contains(&3)));
+        let general = chats.iter().find(|chat| chat.id == 3).unwrap();
+        assert_eq!(general.owner_id, Some(4));
+
+        // the address stays reserved, in any case
+        let signup = CreateUser {
+            ws_name: "acme".to_string(),
+            fullname: "bob".to_string(),
+            email: "Bob@bbc.com".to_string(),
+            password: "123456".to_string(),
+            invite_code: None,
+        };
+        let err = User::create(signup, &Argon2Config::default(), &pool)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ChatCoreError::EmailAlreadyExists(_)));
+
+        // the owner hands over the workspace when leaving
+        let owner = WorkspaceMember::find(1, 1, &pool).await?.unwrap();
+        owner.deactivate(1, 2, &pool).await?;
+        let ws = Workspace::find_workspace_by_id(1, &pool).await?.unwrap();
+        assert_eq!(ws.owner_id, 2);
+        let successor = WorkspaceMember::find(1, 2, &pool).await?.unwrap();
+        assert_eq!(successor.role, WorkspaceRole::Owner);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deactivate_member_of_other_workspaces() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        // alice(2) also belongs to workspace 3, one session acts in each workspace
+        for statement in [
+            "UPDATE workspace_members SET role = 'admin' WHERE user_id IN (1, 5)",
+            "INSERT INTO workspace_members (ws_id, user_id, role) VALUES (3, 2, 'member')",
+            "INSERT INTO sessions (user_id) VALUES (2)",
+            "INSERT INTO sessions (user_id, ws_id) VALUES (2, 3)",
+        ] {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        let admin = WorkspaceMember::find(1, 1, &pool).await?.unwrap();
+        let alice = admin.deactivate(2, 3, &pool).await?;
+        assert!(alice.deactivated_at.is_none());
+        assert_eq!(alice.email, "alice@bbc.com");
+        assert_eq!(alice.ws_id, 3);
+        assert!(User::find_in_workspace(2, 1, &pool).await?.is_none());
+        assert!(User::find_in_workspace(2, 3, &pool).await?.is_some());
+
+        // general_ch(3) of workspace 1 is left, the session acting in workspace 3 is kept
+        let chats = Chat::list_chats_in_workspace(1, &pool).await?;
+        assert!(chats.iter().all(|chat| !chat.members.contains(&2)));
+        let revoked: Vec<Option<i64>> = sqlx::query_scalar(
+            "SELECT ws_id FROM sessions WHERE user_id = 2 AND revoked_at IS NOT NULL",
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(revoked, [None]);
+        let active: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM sessions WHERE user_id = 2 AND revoked_at IS NULL AND ws_id = 3",
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(active, 1);
+
+        // leaving the last workspace deactivates the account
+        let admin = WorkspaceMember::find(3, 5, &pool).await?.unwrap();
+        let alice = admin.deactivate(2, 6, &pool).await?;
+        assert!(alice.deactivated_at.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use sqlx::{query_as, PgPool};
+
+use crate::error::ChatCoreError;
+use crate::models::{UpdateWorkspaceSettings, WorkspaceSettings};
+
+/// upper bound of the upload size a workspace can allow, in bytes
+pub const MAX_UPLOAD_SIZE: usize = 100 * 1024 * 1024;
+const MAX_GROUP_SIZE: i32 = 1000;
+const MAX_RETENTION_DAYS: i32 = 36500;
+
+impl WorkspaceSettings {
+    /// the settings of the workspace, created with the defaults on first read
+    pub async fn get(ws_id: i64, pool: &PgPool) -> Result<Self, ChatCoreError> {
+        let settings = query_as(
+            r#"
+            SELECT *
+            FROM workspace_settings
+            WHERE ws_id = $1
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(settings) = settings {
+            return Ok(settings);
+        }
+
+        // a concurrent first read may insert the row first, the no-op update still returns it
+        let settings = query_as(
+            r#"
+            INSERT INTO workspace_settings (ws_id)
+            VALUES ($1)
+            ON CONFLICT (ws_id) DO UPDATE SET ws_id = EXCLUDED.ws_id
+            RETURNING *
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(settings)
+    }
+
+    pub async fn update(
+        ws_id: i64,
+        update: UpdateWorkspaceSettings,
+        pool: &PgPool,
+    ) -> Result<Self, ChatCoreError> {
+        if let Some(size) = update.max_group_size {
+            if !(3..=MAX_GROUP_SIZE).contains(&size) {
+                return Err(ChatCoreError::BadRequest(format!(
+                    "max group size must be between 3 and {}",
+                    MAX_GROUP_SIZE
+                )));
+            }
+        }
+        if let Some(size) = update.max_upload_size {
+            if size < 1 || size > MAX_UPLOAD_SIZE as i64 {
+                return Err(ChatCoreError::BadRequest(format!(
+                    "max upload size must be between 1 and {} bytes",
+                    MAX_UPLOAD_SIZE
+                )));
+            }
+        }
+        if let Some(days) = update.message_retention_days {
+            if !(0..=MAX_RETENTION_DAYS).contains(&days) {
+                return Err(ChatCoreError::BadRequest(format!(
+                    "message retention must be between 0 and {} days",
+                    MAX_RETENTION_DAYS
+                )));
+            }
+        }
+        let extensions = update.allowed_extensions.map(|extensions| {
+            let mut extensions: Vec<String> = extensions
+                .iter()
+                .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                .filter(|ext| !ext.is_empty())
+                .collect();
+            extensions.sort();
+            extensions.dedup();
+            extensions
+        });
+
+        Self::get(ws_id, pool).await?;
+        let settings = query_as(
+            r#"
+            UPDATE workspace_settings
+            SET max_group_size = COALESCE($2, max_group_size),
+                max_upload_size = COALESCE($3, max_upload_size),
+                allowed_extensions = COALESCE($4, allowed_extensions),
+                message_retention_days = CASE
+                    WHEN $5::int IS NULL THEN message_retention_days
+                    ELSE NULLIF($5, 0)
+                END,
+                public_channel_role = COALESCE($6, public_channel_role),
+                updated_at = NOW()
+            WHERE ws_id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(ws_id)
+        .bind(update.max_group_size)
+        .bind(update.max_upload_size)
+        .bind(extensions)
+        .bind(update.message_retention_days)
+        .bind(update.public_channel_role)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(settings)
+    }
+
+    /// whether a file of that name may be uploaded
+    pub fn allows_file(&self, name: &str) -> bool {
+        if self.allowed_extensions.is_empty() {
+            return true;
+        }
+        match name.rsplit_once('.') {
+            Some((_, ext)) => self.allowed_extensions.contains(&ext.to_lowercase()),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Messages, WorkspaceRole};
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_workspace_settings() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let settings = WorkspaceSettings::get(1, &pool).await?;
+        assert_eq!(settings.max_group_size, 8);
+        assert!(settings.allows_file("a.txt"));
+        assert_eq!(settings, WorkspaceSettings::get(1, &pool).await?);
+
+        let update = UpdateWorkspaceSettings {
+            allowed_extensions: Some(vec![".PNG".to_string(), "jpg".to_string()]),
+            message_retention_days: Some(30),
+            public_channel_role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        let settings = WorkspaceSettings::update(1, update, &pool).await?;
+        assert_eq!(settings.allowed_extensions, ["jpg", "png"]);
+        assert_eq!(settings.message_retention_days, Some(30));
+        assert!(settings.allows_file("cat.png"));
+        assert!(!settings.allows_file("run.sh"));
+
+        // the first file is still attached to a message of another chat
+        let shared = "files/1/339/807/e635afdd8a1c2b0a2d1d7a4e3d1e8a8e46e1d6.png";
+        let expired = "files/1/0a2/d1d/7a4e3d1e8a8e46e1d6339807e635afdd8a1c2b.png";
+        for (chat_id, files) in [(1, vec![shared, expired]), (2, vec![shared])] {
+            sqlx::query("INSERT INTO messages (chat_id, sender_id, content, file) VALUES ($1, 1, 'file', $2)")
+                .bind(chat_id)
+                .bind(files)
+                .execute(&pool)
+                .await?;
+        }
+        sqlx::query(
+            "UPDATE messages SET created_at = NOW() - interval '31 days' WHERE chat_id = 1",
+        )
+        .execute(&pool)
+        .await?;
+        let (purged, files) = Messages::purge_expired(&pool).await?;
+        assert_eq!(purged, 5);
+        assert_eq!(files, [expired]);
+
+        let update = UpdateWorkspaceSettings {
+            message_retention_days: Some(0),
+            ..Default::default()
+        };
+        let settings = WorkspaceSettings::update(1, update, &pool).await?;
+        assert_eq!(settings.message_retention_days, None);
+        assert_eq!(settings.public_channel_role, WorkspaceRole::Admin);
+
+        let update = UpdateWorkspaceSettings {
+            max_group_size: Some(2),
+            ..Default::default()
+        };
+        assert!(WorkspaceSettings::update(1, update, &pool).await.is_err());
+
+        let update = UpdateWorkspaceSettings {
+            message_retention_days: Some(100_000_000),
+            ..Default::default()
+        };
+        assert!(WorkspaceSettings::update(1, update, &pool).await.is_err());
+        let (purged, _) = Messages::purge_expired(&pool).await?;
+        assert_eq!(purged, 0);
+        Ok(())
+    }
+}
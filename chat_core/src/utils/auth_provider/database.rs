@@ -0,0 +1,28 @@
+use sqlx::PgPool;
+
+use crate::models::User;
+use crate::utils::auth_provider::{AuthFuture, AuthProvider};
+use crate::utils::password::Argon2Config;
+
+/// argon2 hashes in `users.password_hash`, upgraded to the configured parameters on sign-in
+#[derive(Default)]
+pub struct DatabaseProvider {
+    argon2: Argon2Config,
+}
+
+impl DatabaseProvider {
+    pub fn new(argon2: Argon2Config) -> Self {
+        Self { argon2 }
+    }
+}
+
+impl AuthProvider for DatabaseProvider {
+    fn authenticate<'a>(
+        &'a self,
+        email: &'a str,
+        password: &'a str,
+        pool: &'a PgPool,
+    ) -> AuthFuture<'a> {
+        Box::pin(User::verify_password(email, password, &self.argon2, pool))
+    }
+}
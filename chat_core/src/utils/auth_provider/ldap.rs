@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use ldap3::{dn_escape, LdapConnAsync, LdapConnSettings};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::error::ChatCoreError;
+use crate::models::User;
+use crate::utils::auth_provider::{AuthFuture, AuthProvider};
+
+// LDAP result code for a failed bind
+const INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LdapConfig {
+    /// e.g. `ldaps://ldap.example.com:636`, or `ldap://...` with `starttls`
+    pub url: String,
+    /// dn to bind as, `{email}` and `{username}` (local part of the email) are substituted,
+    /// e.g. `uid={username},ou=people,dc=example,dc=com`
+    pub bind_dn: String,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// upgrade an `ldap://` connection with StartTLS before binding
+    #[serde(default)]
+    pub starttls: bool,
+    /// allow binding over plain `ldap://`, passwords are sent in the clear
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+fn default_timeout_secs() -> u64 {
+    5
+}
+
+impl LdapConfig {
+    /// the connection must be encrypted unless explicitly allowed not to be
+    pub fn validate(&self) -> Result<(), ChatCoreError> {
+        let url = self.url.to_ascii_lowercase();
+        if url.starts_with("ldaps://") {
+            if self.starttls {
+                return Err(ChatCoreError::AuthProviderError(
+                    "starttls can't be used with ldaps://".to_string(),
+                ));
+            }
+            return Ok(());
+        }
+        if !url.starts_with("ldap://") {
+            return Err(ChatCoreError::AuthProviderError(format!(
+                "invalid ldap url {}",
+                self.url
+            )));
+        }
+        if !self.starttls && !self.allow_insecure {
+            return Err(ChatCoreError::AuthProviderError(format!(
+                "{} is not encrypted, use ldaps://, starttls or allow_insecure",
+                self.url
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// binds to the directory as the user, the user must exist in the workspace
+pub struct LdapProvider {
+    config: LdapConfig,
+}
+
+impl LdapProvider {
+    pub fn new(config: LdapConfig) -> Result<Self, ChatCoreError> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    fn bind_dn(&self, email: &str) -> String {
+        let username = email.split('@').next().unwrap_or_default();
+        self.config
+            .bind_dn
+            .replace("{email}", &dn_escape(email))
+            .replace("{username}", &dn_escape(username))
+    }
+
+    async fn bind(&self, email: &str, password: &str) -> Result<bool, ChatCoreError> {
+        let ldap_error = |e: ldap3::LdapError| ChatCoreError::AuthProviderError(e.to_string());
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(self.config.timeout_secs))
+            .set_starttls(self.config.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .map_err(ldap_error)?;
+        ldap3::drive!(conn);
+
+        let res = ldap
+            .simple_bind(&self.bind_dn(email), password)
+            .await
+            .map_err(ldap_error)?;
+        let _ = ldap.unbind().await;
+        match res.rc {
+            0 => Ok(true),
+            INVALID_CREDENTIALS => Ok(false),
+            _ => Err(ldap_error(res.into())),
+        }
+    }
+}
+
+impl AuthProvider for LdapProvider {
+    fn authenticate<'a>(
+        &'a self,
+        email: &'a str,
+        password: &'a str,
+        pool: &'a PgPool,
+    ) -> AuthFuture<'a> {
+        Box::pin(async move {
+            // an empty password is an unauthenticated bind, which most servers accept
+            if password.is_empty() {
+                return Err(ChatCoreError::InvalidCredentials);
+            }
+            let Some(user) = User::find_user_by_email(email, pool).await? else {
+                return Err(ChatCoreError::InvalidCredentials);
+            };
+            if !self.bind(email, password).await? {
+                warn!("ldap bind failed for {}", email);
+                return Err(ChatCoreError::InvalidCredentials);
+            }
+            Ok(user)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(url: &str) -> LdapConfig {
+        LdapConfig {
+            url: url.to_string(),
+            bind_dn: "uid={username},ou=people,dc=example,dc=com".to_string(),
+            timeout_secs: 1,
+            starttls: false,
+            allow_insecure: false,
+        }
+    }
+
+    #[test]
+    fn test_ldap_bind_dn() {
+        let provider = LdapProvider::new(config("ldaps://localhost:636")).unwrap();
+        assert_eq!(
+            provider.bind_dn("alice@example.com"),
+            "uid=alice,ou=people,dc=example,dc=com"
+        );
+        // dn special characters can't inject another rdn
+        assert_eq!(
+            provider.bind_dn("a,ou=admins@example.com"),
+            "uid=a\\2cou\\3dadmins,ou=people,dc=example,dc=com"
+        );
+    }
+
+    #[test]
+    fn test_ldap_requires_encryption() {
+        assert!(LdapProvider::new(config("ldaps://localhost:636")).is_ok());
+        assert!(LdapProvider::new(config("ldap://localhost:389")).is_err());
+        assert!(LdapProvider::new(config("http://localhost:389")).is_err());
+        let starttls = LdapConfig {
+            starttls: true,
+            ..config("ldap://localhost:389")
+        };
+        assert!(LdapProvider::new(starttls).is_ok());
+        let insecure = LdapConfig {
+            allow_insecure: true,
+            ..config("ldap://localhost:389")
+        };
+        assert!(LdapProvider::new(insecure).is_ok());
+        let both = LdapConfig {
+            starttls: true,
+            ..config("ldaps://localhost:636")
+        };
+        assert!(LdapProvider::new(both).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pub use database::DatabaseProvider;
+pub use ldap::{LdapConfig, LdapProvider};
+
+use crate::error::ChatCoreError;
+use crate::models::{User, Workspace};
+use crate::utils::password::Argon2Config;
+
+mod database;
+mod ldap;
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = Result<User, ChatCoreError>> + Send + 'a>>;
+
+/// checks the credentials of a user. Implementations must return
+/// `ChatCoreError::InvalidCredentials` for unknown users and wrong passwords alike
+pub trait AuthProvider: Send + Sync {
+    fn authenticate<'a>(
+        &'a self,
+        email: &'a str,
+        password: &'a str,
+        pool: &'a PgPool,
+    ) -> AuthFuture<'a>;
+}
+
+/// how users of a workspace are authenticated, workspaces not configured use `database`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthProviderConfig {
+    Database,
+    Ldap(LdapConfig),
+}
+
+impl AuthProviderConfig {
+    pub fn provider(&self, argon2: &Argon2Config) -> Result<Box<dyn AuthProvider>, ChatCoreError> {
+        Ok(match self {
+            Self::Database => Box::new(DatabaseProvider::new(argon2.clone())),
+            Self::Ldap(config) => Box::new(LdapProvider::new(config.clone())?),
+        })
+    }
+}
+
+/// the provider of each workspace, keyed by workspace name
+pub struct AuthProviders {
+    default: Box<dyn AuthProvider>,
+    workspaces: HashMap<String, Box<dyn AuthProvider>>,
+}
+
+impl Default for AuthProviders {
+    fn default() -> Self {
+        Self {
+            default: Box::new(DatabaseProvider::default()),
+            workspaces: HashMap::new(),
+        }
+    }
+}
+
+impl AuthProviders {
+    pub fn new(
+        config: &HashMap<String, AuthProviderConfig>,
+        argon2: &Argon2Config,
+    ) -> Result<Self, ChatCoreError> {
+        let workspaces = config
+            .iter()
+            .map(|(ws, config)| Ok((ws.clone(), config.provider(argon2)?)))
+            .collect::<Result<_, ChatCoreError>>()?;
+        Ok(Self {
+            default: Box::new(DatabaseProvider::new(argon2.clone())),
+            workspaces,
+        })
+    }
+
+    pub fn with_provider(mut self, ws_name: &str, provider: Box<dyn AuthProvider>) -> Self {
+        self.workspaces.insert(ws_name.to_string(), provider);
+        self
+    }
+
+    /// authenticate with the provider of the user's workspace, unknown users go to the
+    /// default provider so they cost the same as known ones
+    pub async fn authenticate(
+        &self,
+        email: &str,
+        password: &str,
+        pool: &PgPool,
+    ) -> Result<User, ChatCoreError> {
+        let provider = match User::find_user_by_email(email, pool).await? {
+            Some(user) if !self.workspaces.is_empty() => {
+                Workspace::find_workspace_by_id(user.ws_id, pool)
+                    .await?
+                    .and_then(|ws| self.workspaces.get(&ws.name))
+                    .unwrap_or(&self.default)
+            }
+            _ => &self.default,
+        };
+        let user = provider.authenticate(email, password, pool).await?;
+        user.ensure_active()?;
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    /// accepts any password for users in the database
+    struct AnyPassword;
+
+    impl AuthProvider for AnyPassword {
+        fn authenticate<'a>(
+            &'a self,
+            email: &'a str,
+            _password: &'a str,
+            pool: &'a PgPool,
+        ) -> AuthFuture<'a> {
+            Box::pin(async move {
+                User::find_user_by_email(email, pool)
+                    .await?
+                    .ok_or(ChatCoreError::InvalidCredentials)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_provider_per_workspace() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let providers = AuthProviders::default().with_provider("fox", Box::new(AnyPassword));
+
+        // bbc uses the database
+        assert!(providers
+            .authenticate("alice@bbc.com", "123456", &pool)
+            .await
+            .is_ok());
+        assert!(providers
+            .authenticate("alice@bbc.com", "x", &pool)
+            .await
+            .is_err());
+        // fox uses the custom provider
+        let user = providers.authenticate("eve@fox.com", "x", &pool).await?;
+        assert_eq!(user.ws_id, 3);
+        assert!(matches!(
+            providers.authenticate("nobody@fox.com", "x", &pool).await,
+            Err(ChatCoreError::InvalidCredentials)
+        ));
+
+        let config: HashMap<String, AuthProviderConfig> = serde_json::from_str(
+            r#"{"fox": {"type": "ldap", "url": "ldap://localhost:389", "bind_dn": "uid={username},dc=fox,dc=com"}, "bbc": {"type": "database"}}"#,
+        )?;
+        assert!(matches!(config["fox"], AuthProviderConfig::Ldap(_)));
+        assert!(matches!(config["bbc"], AuthProviderConfig::Database));
+        Ok(())
+    }
+}
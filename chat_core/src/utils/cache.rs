@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use moka::sync::Cache;
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::error::ChatCoreError;
+use crate::models::User;
+
+const USER_CACHE_TTL: Duration = Duration::from_secs(30);
+const USER_CACHE_CAPACITY: u64 = 10_000;
+
+/// short-lived in-process cache of the users resolved from access tokens,
+/// so profile changes are picked up within seconds without a query per request.
+/// Users are cached per workspace they act in
+#[derive(Clone)]
+pub struct UserCache {
+    inner: Cache<(i64, i64), User>,
+}
+
+impl Default for UserCache {
+    fn default() -> Self {
+        Self::new(USER_CACHE_TTL)
+    }
+}
+
+impl UserCache {
+    pub fn new(ttl: Duration) -> Self {
+        let inner = Cache::builder()
+            .max_capacity(USER_CACHE_CAPACITY)
+            .time_to_live(ttl)
+            .support_invalidation_closures()
+            .build();
+        Self { inner }
+    }
+
+    /// the user as a member of the workspace, `None` if the user isn't one
+    pub async fn get(
+        &self,
+        id: i64,
+        ws_id: i64,
+        pool: &PgPool,
+    ) -> Result<Option<User>, ChatCoreError> {
+        if let Some(user) = self.inner.get(&(id, ws_id)) {
+            return Ok(Some(user));
+        }
+        let user = User::find_in_workspace(id, ws_id, pool).await?;
+        if let Some(user) = &user {
+            self.inner.insert((id, ws_id), user.clone());
+        }
+        Ok(user)
+    }
+
+    /// drop a cached user, in every workspace, after it has been changed
+    pub fn invalidate(&self, id: i64) {
+        if let Err(e) = self
+            .inner
+            .invalidate_entries_if(move |(user_id, _), _| *user_id == id)
+        {
+            warn!("invalidate user {} error: {}", id, e);
+        }
+    }
+
+    /// drop every user cached in the workspace, e.g. once it is deleted
+    pub fn invalidate_workspace(&self, ws_id: i64) {
+        if let Err(e) = self
+            .inner
+            .invalidate_entries_if(move |(_, ws), _| *ws == ws_id)
+        {
+            warn!("invalidate workspace {} error: {}", ws_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::get_test_pool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_user_cache() -> anyhow::Result<()> {
+        let (pool, _tdb) = get_test_pool(None).await;
+        let cache = UserCache::default();
+        let user = cache.get(1, 1, &pool).await?.unwrap();
+        assert_eq!(user.email, "tyran@bbc.com");
+        assert!(cache.get(100, 1, &pool).await?.is_none());
+        // not a member of fox
+        assert!(cache.get(1, 3, &pool).await?.is_none());
+
+        sqlx::query("UPDATE users SET fullname = 'tyran2' WHERE id = 1")
+            .execute(&pool)
+            .await?;
+        let user = cache.get(1, 1, &pool).await?.unwrap();
+        assert_eq!(user.fullname, "tyran");
+
+        cache.invalidate(1);
+        let user = cache.get(1, 1, &pool).await?.unwrap();
+        assert_eq!(user.fullname, "tyran2");
+        Ok(())
+    }
+}
@@ -1,24 +1,85 @@
 use std::collections::HashSet;
 
-use jwt_simple::algorithms::{ECDSAP256KeyPairLike, ECDSAP256PublicKeyLike, ES256KeyPair};
+use chrono::DateTime;
+use jwt_simple::algorithms::{
+    ECDSAP256KeyPairLike, ECDSAP256PublicKeyLike, ES256KeyPair, ES256PublicKey,
+};
 use jwt_simple::claims::Claims;
 use jwt_simple::common::VerificationOptions;
 use jwt_simple::prelude::Duration;
+use jwt_simple::reexports::ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder};
+use jwt_simple::token::Token;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 
 use crate::error::ChatCoreError;
-use crate::models::User;
+use crate::models::{AccessToken, User};
 
-const JWT_DURATION: u64 = 7 * 24 * 60 * 60;
+// access tokens are short-lived, clients renew them with a refresh token
+const JWT_DURATION: u64 = 15 * 60;
 const JWT_ISSUER: &str = "chat_server";
 const JWT_AUDIENCE: &str = "chat_client";
+/// scope of tokens issued to signed-in users, grants everything the user can do
+pub const FULL_ACCESS_SCOPE: &str = "*";
+/// scopes of tokens issued to users left without a workspace, enough to join one
+pub const ACCOUNT_SCOPES: [&str; 2] = ["account:read", "account:write"];
+
+/// signs tokens with the active key, and verifies tokens signed by the active key
+/// or by any retired key still accepted during a key rotation
 pub struct JwtSigner {
     pub(crate) kpair: ES256KeyPair,
+    pub(crate) verifier: JwtVerifier,
+}
+
+/// verify-only counterpart of `JwtSigner`, holds public keys only so it can't mint tokens
+pub struct JwtVerifier {
+    // the active key comes first
+    pub(crate) keys: Vec<ES256PublicKey>,
+}
+
+/// custom claims, the user id is carried in `sub`. Anything else about the user is
+/// resolved from database when the token is verified
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims {
+    ws_id: i64,
+    #[serde(default)]
+    scopes: Vec<String>,
+    // user's token generation at issue time
+    #[serde(default)]
+    gen: i32,
+    // the session the token belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<i64>,
+}
+
+/// a JSON Web Key set, as published at `/.well-known/jwks.json`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub use_: String,
+    pub kid: String,
+    pub x: String,
+    pub y: String,
 }
 
 #[allow(unused)]
 impl JwtSigner {
     pub fn new(kpair: ES256KeyPair) -> Self {
-        Self { kpair }
+        let kid = key_id(&kpair.public_key());
+        let kpair = kpair.with_key_id(&kid);
+        let verifier = JwtVerifier {
+            keys: vec![kpair.public_key().with_key_id(&kid)],
+        };
+        Self { kpair, verifier }
     }
 
     pub fn load(path: &str) -> Result<Self, ChatCoreError> {
@@ -27,16 +88,129 @@ impl JwtSigner {
         Ok(Self::new(kpair))
     }
 
-    pub fn sign(&self, user: impl Into<User>) -> Result<String, ChatCoreError> {
-        let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION))
+    /// accept tokens signed by previous keys (public key PEMs) until they are dropped from config
+    pub fn with_retired_keys(mut self, pems: &[String]) -> Result<Self, ChatCoreError> {
+        self.verifier = self.verifier.with_retired_keys(pems)?;
+        Ok(self)
+    }
+
+    pub fn sign(&self, user: &User) -> Result<String, ChatCoreError> {
+        self.sign_session(user, None)
+    }
+
+    /// sign a token of a session, it stops working once the session is revoked
+    pub fn sign_session(
+        &self,
+        user: &User,
+        session_id: Option<i64>,
+    ) -> Result<String, ChatCoreError> {
+        self.sign_with_scopes(user, session_id, &[FULL_ACCESS_SCOPE])
+    }
+
+    /// sign a token of a session limited to the account routes, for users whose default
+    /// workspace is gone
+    pub fn sign_account_session(
+        &self,
+        user: &User,
+        session_id: Option<i64>,
+    ) -> Result<String, ChatCoreError> {
+        self.sign_with_scopes(user, session_id, &ACCOUNT_SCOPES)
+    }
+
+    fn sign_with_scopes(
+        &self,
+        user: &User,
+        session_id: Option<i64>,
+        scopes: &[&str],
+    ) -> Result<String, ChatCoreError> {
+        let custom = TokenClaims {
+            ws_id: user.ws_id,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            gen: user.token_generation,
+            sid: session_id,
+        };
+        let claims = Claims::with_custom_claims(custom, Duration::from_secs(JWT_DURATION))
+            .with_subject(user.id)
             .with_issuer(JWT_ISSUER)
-            .with_audience(JWT_AUDIENCE);
+            .with_audience(JWT_AUDIENCE)
+            .with_jwt_id(Uuid::now_v7().to_string());
         let token = self.kpair.sign(claims)?;
 
         Ok(token)
     }
 
-    pub fn verify(&self, token: &str) -> Result<User, ChatCoreError> {
+    pub fn verify(&self, token: &str) -> Result<AccessToken, ChatCoreError> {
+        self.verifier.verify(token)
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        self.verifier.jwks()
+    }
+}
+
+impl JwtVerifier {
+    /// build a verifier from the public key (PEM) of the active signing key
+    pub fn from_pem(pem: &str) -> Result<Self, ChatCoreError> {
+        let pk = ES256PublicKey::from_pem(pem)?;
+        let kid = key_id(&pk);
+        Ok(Self {
+            keys: vec![pk.with_key_id(&kid)],
+        })
+    }
+
+    pub fn from_jwks(jwks: &Jwks) -> Result<Self, ChatCoreError> {
+        let keys = jwks
+            .keys
+            .iter()
+            .filter(|k| k.kty == "EC" && k.crv == "P-256")
+            .map(ES256PublicKey::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err(ChatCoreError::JwksError("no usable key found".to_string()));
+        }
+        Ok(Self { keys })
+    }
+
+    /// load a JWKS from an http(s) url (e.g. chat_server's `/.well-known/jwks.json`) or a file
+    pub async fn load_jwks(source: &str) -> Result<Self, ChatCoreError> {
+        let jwks: Jwks = if source.starts_with("http://") || source.starts_with("https://") {
+            reqwest::get(source)
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(|e| ChatCoreError::JwksError(e.to_string()))?
+                .json()
+                .await
+                .map_err(|e| ChatCoreError::JwksError(e.to_string()))?
+        } else {
+            let content = std::fs::read_to_string(source)
+                .map_err(|e| ChatCoreError::JwksError(e.to_string()))?;
+            serde_json::from_str(&content).map_err(|e| ChatCoreError::JwksError(e.to_string()))?
+        };
+        Self::from_jwks(&jwks)
+    }
+
+    pub fn with_retired_keys(mut self, pems: &[String]) -> Result<Self, ChatCoreError> {
+        for pem in pems {
+            let pk = ES256PublicKey::from_pem(pem)?;
+            let kid = key_id(&pk);
+            self.keys.push(pk.with_key_id(&kid));
+        }
+        Ok(self)
+    }
+
+    /// whether the token was signed by one of the keys, tokens without kid use the active key
+    pub fn knows_key(&self, token: &str) -> bool {
+        match Token::decode_metadata(token).map(|m| m.key_id().map(str::to_string)) {
+            Ok(Some(kid)) => self
+                .keys
+                .iter()
+                .any(|k| k.key_id().as_deref() == Some(kid.as_str())),
+            Ok(None) => true,
+            Err(_) => false,
+        }
+    }
+
+    pub fn verify(&self, token: &str) -> Result<AccessToken, ChatCoreError> {
         let allowed_issuers = HashSet::from([JWT_ISSUER.to_string()]);
         let allowed_audiences = HashSet::from([JWT_AUDIENCE.to_string()]);
         let opts = VerificationOptions {
@@ -45,9 +219,143 @@ impl JwtSigner {
             max_validity: Some(Duration::from_secs(JWT_DURATION)),
             ..Default::default()
         };
-        let pub_key = self.kpair.public_key();
-        let claims = pub_key.verify_token::<User>(token, Some(opts))?;
 
-        Ok(claims.custom)
+        // tokens without kid are verified by the active key
+        let metadata = Token::decode_metadata(token)?;
+        let pub_key = match metadata.key_id() {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|k| k.key_id().as_deref() == Some(kid))
+                .ok_or_else(|| ChatCoreError::Unauthorized(format!("unknown key id {}", kid)))?,
+            None => &self.keys[0],
+        };
+        let claims = pub_key.verify_token::<TokenClaims>(token, Some(opts))?;
+
+        let user_id = claims.subject.and_then(|sub| sub.parse().ok());
+        let expires_at = claims
+            .expires_at
+            .and_then(|exp| DateTime::from_timestamp(exp.as_secs() as i64, 0));
+        match (claims.jwt_id, user_id, expires_at) {
+            (Some(jti), Some(user_id), Some(expires_at)) => Ok(AccessToken {
+                jti,
+                user_id,
+                ws_id: claims.custom.ws_id,
+                scopes: claims.custom.scopes,
+                generation: claims.custom.gen,
+                session_id: claims.custom.sid,
+                expires_at,
+            }),
+            _ => Err(ChatCoreError::Unauthorized("invalid token".to_string())),
+        }
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        Jwks {
+            keys: self.keys.iter().map(Jwk::from).collect(),
+        }
+    }
+}
+
+impl From<&ES256PublicKey> for Jwk {
+    fn from(pk: &ES256PublicKey) -> Self {
+        // uncompressed SEC1 point: 0x04 || x || y
+        let point = pk.public_key().to_bytes_uncompressed();
+        let encode = |b: &[u8]| Base64UrlSafeNoPadding::encode_to_string(b).unwrap_or_default();
+        Self {
+            kty: "EC".to_string(),
+            crv: "P-256".to_string(),
+            alg: "ES256".to_string(),
+            use_: "sig".to_string(),
+            kid: pk.key_id().clone().unwrap_or_else(|| key_id(pk)),
+            x: encode(&point[1..33]),
+            y: encode(&point[33..65]),
+        }
+    }
+}
+
+impl TryFrom<&Jwk> for ES256PublicKey {
+    type Error = ChatCoreError;
+
+    fn try_from(jwk: &Jwk) -> Result<Self, Self::Error> {
+        let decode = |s: &str| {
+            Base64UrlSafeNoPadding::decode_to_vec(s, None)
+                .map_err(|e| ChatCoreError::JwksError(format!("invalid key {}: {}", jwk.kid, e)))
+        };
+        let mut point = vec![0x04];
+        point.extend(decode(&jwk.x)?);
+        point.extend(decode(&jwk.y)?);
+        Ok(ES256PublicKey::from_bytes(&point)?.with_key_id(&jwk.kid))
+    }
+}
+
+/// key id is the base64url encoded sha256 of the compressed public key
+fn key_id(pk: &ES256PublicKey) -> String {
+    let hash = Sha256::digest(pk.to_bytes());
+    Base64UrlSafeNoPadding::encode_to_string(hash).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jwt_key_rotation() -> anyhow::Result<()> {
+        let old = JwtSigner::load("./fixtures/pkcs8.pem")?;
+        let user = User::new(1, 0, "lign".to_string(), "testlign@gmail.com".to_string());
+        let token = old.sign(&user)?;
+        let metadata = Token::decode_metadata(&token)?;
+        assert_eq!(metadata.key_id(), old.kpair.key_id().as_deref());
+
+        // the new key can't verify the token until the old key is retired into it
+        let new = JwtSigner::new(ES256KeyPair::generate());
+        assert!(!new.verifier.knows_key(&token));
+        assert!(new.verify(&token).is_err());
+
+        let old_pk = std::fs::read_to_string("./fixtures/dk.pem")?;
+        let new = new.with_retired_keys(&[old_pk])?;
+        let access = new.verify(&token)?;
+        assert_eq!(access.user_id, user.id);
+
+        let new_token = new.sign(&user)?;
+        assert!(old.verify(&new_token).is_err());
+        assert!(new.verify(&new_token).is_ok());
+
+        let jwks = new.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        assert_eq!(jwks.keys[1], old.jwks().keys[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jwt_verifier() -> anyhow::Result<()> {
+        let signer = JwtSigner::load("./fixtures/pkcs8.pem")?;
+        let user = User::new(1, 0, "lign".to_string(), "testlign@gmail.com".to_string());
+        let token = signer.sign(&user)?;
+
+        let pem = std::fs::read_to_string("./fixtures/dk.pem")?;
+        let verifier = JwtVerifier::from_pem(&pem)?;
+        let access = verifier.verify(&token)?;
+        assert_eq!(access.user_id, user.id);
+        assert_eq!(access.ws_id, user.ws_id);
+        assert_eq!(access.scopes, vec![FULL_ACCESS_SCOPE.to_string()]);
+        assert_eq!(access.session_id, None);
+
+        let token = signer.sign_session(&user, Some(42))?;
+        assert_eq!(verifier.verify(&token)?.session_id, Some(42));
+        assert!(!verifier.verify(&token)?.is_account_only());
+
+        let token = signer.sign_account_session(&user, Some(42))?;
+        let access = verifier.verify(&token)?;
+        assert!(access.is_account_only());
+        assert!(access.has_scope("account:write"));
+        assert!(!access.has_scope("chats:read"));
+
+        let verifier = JwtVerifier::from_jwks(&signer.jwks())?;
+        assert!(verifier.knows_key(&token));
+        let access = verifier.verify(&token)?;
+        assert_eq!(access.user_id, user.id);
+        assert_eq!(verifier.jwks(), signer.jwks());
+        Ok(())
     }
 }
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::error::ChatCoreError;
+use crate::utils::mailer::{Mail, MailFuture, Mailer};
+
+/// appends every mail as a json line to a file instead of delivering it
+pub struct FileMailer {
+    path: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// all mails written so far, oldest first
+    pub async fn read_all(&self) -> Result<Vec<Mail>, ChatCoreError> {
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(ChatCoreError::MailError(e.to_string())),
+        };
+        content
+            .lines()
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| ChatCoreError::MailError(e.to_string()))
+            })
+            .collect()
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: Mail) -> MailFuture<'_> {
+        Box::pin(async move {
+            info!("mail to {}: {}", mail.to, mail.subject);
+            let mut line = serde_json::to_string(&mail)
+                .map_err(|e| ChatCoreError::MailError(e.to_string()))?;
+            line.push('\n');
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| ChatCoreError::MailError(e.to_string()))?;
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .map_err(|e| ChatCoreError::MailError(e.to_string()))?;
+            file.write_all(line.as_bytes())
+                .await
+                .map_err(|e| ChatCoreError::MailError(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_mailer() -> anyhow::Result<()> {
+        let path = format!("/tmp/chat_core/mails/{}.jsonl", uuid::Uuid::now_v7());
+        let mailer = FileMailer::new(&path);
+        assert!(mailer.read_all().await?.is_empty());
+
+        let mail = Mail {
+            to: "alice@bbc.com".to_string(),
+            subject: "hello".to_string(),
+            body: "hello world".to_string(),
+        };
+        mailer.send(mail.clone()).await?;
+        mailer.send(mail.clone()).await?;
+        assert_eq!(mailer.read_all().await?, vec![mail.clone(), mail]);
+        Ok(())
+    }
+}
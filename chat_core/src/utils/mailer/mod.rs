@@ -0,0 +1,43 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use serde::{Deserialize, Serialize};
+
+pub use file::FileMailer;
+pub use smtp::{SmtpConfig, SmtpMailer};
+
+use crate::error::ChatCoreError;
+
+mod file;
+mod smtp;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub type MailFuture<'a> = Pin<Box<dyn Future<Output = Result<(), ChatCoreError>> + Send + 'a>>;
+
+/// delivers transactional mails (password reset, verification etc.)
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: Mail) -> MailFuture<'_>;
+}
+
+/// how mails are delivered, `file` is meant for development and tests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MailConfig {
+    Smtp { smtp: SmtpConfig },
+    File { file: String },
+}
+
+impl MailConfig {
+    pub fn mailer(&self) -> Result<Box<dyn Mailer>, ChatCoreError> {
+        match self {
+            Self::Smtp { smtp } => Ok(Box::new(SmtpMailer::new(smtp)?)),
+            Self::File { file } => Ok(Box::new(FileMailer::new(file))),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::error::ChatCoreError;
+use crate::utils::mailer::{Mail, MailFuture, Mailer};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &SmtpConfig) -> Result<Self, ChatCoreError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .map_err(|e| ChatCoreError::MailError(e.to_string()))?
+            .port(config.port)
+            .credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ))
+            .build();
+        Ok(Self {
+            transport,
+            from: config.from.clone(),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: Mail) -> MailFuture<'_> {
+        Box::pin(async move {
+            let message = Message::builder()
+                .from(
+                    self.from
+                        .parse()
+                        .map_err(|e| ChatCoreError::MailError(format!("invalid from: {}", e)))?,
+                )
+                .to(mail
+                    .to
+                    .parse()
+                    .map_err(|e| ChatCoreError::MailError(format!("invalid to: {}", e)))?)
+                .subject(mail.subject)
+                .header(ContentType::TEXT_PLAIN)
+                .body(mail.body)
+                .map_err(|e| ChatCoreError::MailError(e.to_string()))?;
+            self.transport
+                .send(message)
+                .await
+                .map_err(|e| ChatCoreError::MailError(e.to_string()))?;
+            info!("mail sent to {}", mail.to);
+            Ok(())
+        })
+    }
+}
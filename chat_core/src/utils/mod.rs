@@ -1 +1,8 @@
+pub mod auth_provider;
+pub mod cache;
 pub mod jwt;
+pub mod mailer;
+pub mod oidc;
+pub mod password;
+pub mod token;
+pub mod totp;
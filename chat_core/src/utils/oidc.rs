@@ -0,0 +1,342 @@
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+use jwt_simple::algorithms::{
+    ECDSAP256PublicKeyLike, ES256PublicKey, RS256PublicKey, RSAPublicKeyLike,
+};
+use jwt_simple::claims::JWTClaims;
+use jwt_simple::common::VerificationOptions;
+use jwt_simple::reexports::ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder};
+use jwt_simple::token::Token;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::error::ChatCoreError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    /// issuer url of the identity provider, `/.well-known/openid-configuration` is appended
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// our callback, e.g. `https://chat.example.com/api/oidc/callback`
+    pub redirect_uri: String,
+    /// users signing in for the first time are created in this workspace
+    pub workspace: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// signing keys published by the identity provider, only the fields we need
+#[derive(Debug, Deserialize)]
+struct ProviderJwks {
+    keys: Vec<ProviderJwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderJwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+enum ProviderKey {
+    Es256(ES256PublicKey),
+    Rs256(Box<RS256PublicKey>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IdTokenCustomClaims {
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// identity asserted by a validated ID token
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+/// relying party side of the authorization-code + PKCE flow
+pub struct OidcClient {
+    pub config: OidcConfig,
+    metadata: ProviderMetadata,
+    keys: RwLock<Vec<ProviderKey>>,
+    http: reqwest::Client,
+}
+
+impl OidcClient {
+    /// fetch the provider metadata and signing keys
+    pub async fn discover(config: OidcConfig) -> Result<Self, ChatCoreError> {
+        let http = reqwest::Client::new();
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = get_json(&http, &url).await?;
+        if metadata.issuer.trim_end_matches('/') != config.issuer.trim_end_matches('/') {
+            return Err(ChatCoreError::OidcError(format!(
+                "issuer mismatch: configured {}, discovered {}",
+                config.issuer, metadata.issuer
+            )));
+        }
+        let keys = fetch_keys(&http, &metadata.jwks_uri).await?;
+        info!("oidc provider {} discovered", metadata.issuer);
+
+        Ok(Self {
+            config,
+            metadata,
+            keys: RwLock::new(keys),
+            http,
+        })
+    }
+
+    /// where to send the browser, `state` and `nonce` are echoed back by the provider
+    pub fn authorization_url(&self, state: &str, nonce: &str, code_verifier: &str) -> String {
+        let scope = self.config.scopes.join(" ");
+        let challenge = pkce_challenge(code_verifier);
+        let params = [
+            ("response_type", "code"),
+            ("client_id", self.config.client_id.as_str()),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("scope", scope.as_str()),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+        match Url::parse_with_params(&self.metadata.authorization_endpoint, params) {
+            Ok(url) => url.to_string(),
+            Err(_) => self.metadata.authorization_endpoint.clone(),
+        }
+    }
+
+    /// exchange the authorization code at the token endpoint and validate the returned ID token
+    pub async fn authenticate(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<OidcIdentity, ChatCoreError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let res: TokenResponse = self
+            .http
+            .post(&self.metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| ChatCoreError::OidcError(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| ChatCoreError::OidcError(e.to_string()))?;
+
+        self.verify_id_token(&res.id_token, nonce).await
+    }
+
+    async fn verify_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<OidcIdentity, ChatCoreError> {
+        let metadata = Token::decode_metadata(id_token)?;
+        let kid = metadata.key_id().map(|kid| kid.to_string());
+        // the provider may have rotated its keys since discovery
+        if !self.has_key(kid.as_deref()) {
+            let keys = fetch_keys(&self.http, &self.metadata.jwks_uri).await?;
+            *self.keys.write().expect("oidc keys lock poisoned") = keys;
+        }
+
+        let opts = VerificationOptions {
+            allowed_issuers: Some(HashSet::from([self.metadata.issuer.clone()])),
+            allowed_audiences: Some(HashSet::from([self.config.client_id.clone()])),
+            required_nonce: Some(nonce.to_string()),
+            ..Default::default()
+        };
+        let claims = {
+            let keys = self.keys.read().expect("oidc keys lock poisoned");
+            let key = keys
+                .iter()
+                .find(|key| kid.is_none() || key.key_id() == kid.as_deref())
+                .ok_or_else(|| {
+                    ChatCoreError::Unauthorized(format!("unknown id token key {:?}", kid))
+                })?;
+            key.verify(id_token, opts)
+                .map_err(|e| ChatCoreError::Unauthorized(format!("invalid id token: {}", e)))?
+        };
+
+        let subject = claims
+            .subject
+            .ok_or_else(|| ChatCoreError::Unauthorized("id token has no subject".to_string()))?;
+        Ok(OidcIdentity {
+            issuer: self.metadata.issuer.clone(),
+            subject,
+            email: claims.custom.email,
+            email_verified: claims.custom.email_verified.unwrap_or(false),
+            name: claims.custom.name,
+        })
+    }
+
+    fn has_key(&self, kid: Option<&str>) -> bool {
+        let keys = self.keys.read().expect("oidc keys lock poisoned");
+        match kid {
+            Some(kid) => keys.iter().any(|key| key.key_id() == Some(kid)),
+            None => !keys.is_empty(),
+        }
+    }
+}
+
+impl ProviderKey {
+    fn key_id(&self) -> Option<&str> {
+        match self {
+            ProviderKey::Es256(key) => key.key_id().as_deref(),
+            ProviderKey::Rs256(key) => key.key_id().as_deref(),
+        }
+    }
+
+    fn verify(
+        &self,
+        token: &str,
+        opts: VerificationOptions,
+    ) -> Result<JWTClaims<IdTokenCustomClaims>, jwt_simple::Error> {
+        match self {
+            ProviderKey::Es256(key) => key.verify_token(token, Some(opts)),
+            ProviderKey::Rs256(key) => key.verify_token(token, Some(opts)),
+        }
+    }
+}
+
+impl TryFrom<&ProviderJwk> for ProviderKey {
+    type Error = ChatCoreError;
+
+    fn try_from(jwk: &ProviderJwk) -> Result<Self, Self::Error> {
+        let decode = |s: &Option<String>| {
+            let s = s
+                .as_deref()
+                .ok_or_else(|| ChatCoreError::JwksError(format!("incomplete {} key", jwk.kty)))?;
+            Base64UrlSafeNoPadding::decode_to_vec(s, None)
+                .map_err(|e| ChatCoreError::JwksError(e.to_string()))
+        };
+        let key = match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+            ("EC", Some("P-256")) => {
+                let mut point = vec![0x04];
+                point.extend(decode(&jwk.x)?);
+                point.extend(decode(&jwk.y)?);
+                let key = ES256PublicKey::from_bytes(&point)?;
+                ProviderKey::Es256(match &jwk.kid {
+                    Some(kid) => key.with_key_id(kid),
+                    None => key,
+                })
+            }
+            ("RSA", _) => {
+                let key = RS256PublicKey::from_components(&decode(&jwk.n)?, &decode(&jwk.e)?)?;
+                ProviderKey::Rs256(Box::new(match &jwk.kid {
+                    Some(kid) => key.with_key_id(kid),
+                    None => key,
+                }))
+            }
+            (kty, _) => {
+                return Err(ChatCoreError::JwksError(format!(
+                    "unsupported key type {}",
+                    kty
+                )))
+            }
+        };
+        Ok(key)
+    }
+}
+
+/// S256 code challenge of a PKCE code verifier
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    let hash = Sha256::digest(code_verifier.as_bytes());
+    Base64UrlSafeNoPadding::encode_to_string(hash).unwrap_or_default()
+}
+
+async fn fetch_keys(http: &reqwest::Client, url: &str) -> Result<Vec<ProviderKey>, ChatCoreError> {
+    let jwks: ProviderJwks = get_json(http, url).await?;
+    // keys we can't use (e.g. encryption keys) are skipped
+    Ok(jwks
+        .keys
+        .iter()
+        .filter_map(|jwk| ProviderKey::try_from(jwk).ok())
+        .collect())
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    http: &reqwest::Client,
+    url: &str,
+) -> Result<T, ChatCoreError> {
+    http.get(url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| ChatCoreError::OidcError(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| ChatCoreError::OidcError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge() {
+        // RFC 7636 appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ChatCoreError;
+
+/// Argon2id cost parameters for new password hashes, raise them as hardware improves.
+/// Hashes made with other parameters are upgraded on the next successful sign-in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Argon2Config {
+    #[serde(default = "default_memory_kib")]
+    pub memory_kib: u32,
+    #[serde(default = "default_iterations")]
+    pub iterations: u32,
+    #[serde(default = "default_parallelism")]
+    pub parallelism: u32,
+    // hash checked against when there is no usable stored hash, costs as much as a real one
+    #[serde(skip)]
+    dummy_hash: OnceLock<String>,
+    // how long checking a password against a hash with these parameters takes
+    #[serde(skip)]
+    verify_cost: OnceLock<Duration>,
+}
+
+fn default_memory_kib() -> u32 {
+    Params::DEFAULT_M_COST
+}
+
+fn default_iterations() -> u32 {
+    Params::DEFAULT_T_COST
+}
+
+fn default_parallelism() -> u32 {
+    Params::DEFAULT_P_COST
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Self::new(
+            default_memory_kib(),
+            default_iterations(),
+            default_parallelism(),
+        )
+    }
+}
+
+impl Argon2Config {
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        Self {
+            memory_kib,
+            iterations,
+            parallelism,
+            dummy_hash: OnceLock::new(),
+            verify_cost: OnceLock::new(),
+        }
+    }
+
+    pub fn argon2(&self) -> Result<Argon2<'static>, ChatCoreError> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(argon2::password_hash::Error::from)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// hash the password to a PHC string ($argon2id$v=19$...)
+    pub fn hash(&self, password: &str) -> Result<String, ChatCoreError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = self.argon2()?.hash_password(password.as_bytes(), &salt)?;
+        Ok(hash.to_string())
+    }
+
+    /// whether the hash was made with another algorithm, version or cost parameters
+    pub fn needs_rehash(&self, hash: &PasswordHash) -> bool {
+        let Ok(params) = Params::try_from(hash) else {
+            return true;
+        };
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.memory_kib
+            || params.t_cost() != self.iterations
+            || params.p_cost() != self.parallelism
+    }
+
+    pub fn dummy_hash(&self) -> Result<PasswordHash<'_>, ChatCoreError> {
+        if self.dummy_hash.get().is_none() {
+            let hash = self.hash("dummy password")?;
+            let _ = self.dummy_hash.set(hash);
+        }
+        let hash = self
+            .dummy_hash
+            .get()
+            .map(String::as_str)
+            .unwrap_or_default();
+        Ok(PasswordHash::new(hash)?)
+    }
+
+    /// time to check a password against a current hash, checks against outdated (cheaper)
+    /// hashes are padded up to it so they don't stand out from unknown accounts
+    pub fn verify_cost(&self) -> Result<Duration, ChatCoreError> {
+        if let Some(cost) = self.verify_cost.get() {
+            return Ok(*cost);
+        }
+        let hash = self.dummy_hash()?;
+        let start = Instant::now();
+        let _ = self.argon2()?.verify_password(b"password", &hash);
+        Ok(*self.verify_cost.get_or_init(|| start.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_argon2_needs_rehash() -> anyhow::Result<()> {
+        let weak = Argon2Config::default();
+        let strong = Argon2Config::new(32 * 1024, 3, 1);
+
+        let hash = weak.hash("123456")?;
+        let hash = PasswordHash::new(&hash).unwrap();
+        assert!(!weak.needs_rehash(&hash));
+        assert!(strong.needs_rehash(&hash));
+
+        let hash = strong.hash("123456")?;
+        assert!(hash.starts_with("$argon2id$v=19$m=32768,t=3,p=1$"));
+        assert!(!strong.needs_rehash(&PasswordHash::new(&hash).unwrap()));
+
+        let config: Argon2Config = serde_json::from_str(r#"{"memory_kib": 65536}"#)?;
+        assert_eq!(config.iterations, Params::DEFAULT_T_COST);
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+const TOKEN_BYTES: usize = 32;
+const SALT_BYTES: usize = 16;
+
+/// generate a random opaque token (hex encoded), used for refresh tokens etc.
+pub fn generate_token() -> String {
+    let mut buf = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    to_hex(&buf)
+}
+
+/// only the sha256 hash of an opaque token is stored in database
+pub fn hash_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    to_hex(&hasher.finalize())
+}
+
+/// random salt (hex encoded) for `hash_salted`
+pub fn generate_salt() -> String {
+    let mut buf = [0u8; SALT_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    to_hex(&buf)
+}
+
+/// sha256 of the salt and the secret, for short secrets that could be looked up in a table
+pub fn hash_salted(salt: &str, secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(secret.as_bytes());
+    to_hex(&hasher.finalize())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_hash_token() {
+        let token = generate_token();
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+        assert_ne!(token, generate_token());
+
+        let hash = hash_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token(&token));
+
+        let salt = generate_salt();
+        assert_eq!(salt.len(), SALT_BYTES * 2);
+        assert_eq!(hash_salted(&salt, &token), hash_salted(&salt, &token));
+        assert_ne!(
+            hash_salted(&salt, &token),
+            hash_salted(&generate_salt(), &token)
+        );
+    }
+}
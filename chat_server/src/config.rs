@@ -1,14 +1,21 @@
+use std::collections::HashMap;
 use std::fs::File;
 
 use anyhow::bail;
 use serde::{Deserialize, Serialize};
 
+use chat_core::utils::auth_provider::AuthProviderConfig;
+use chat_core::utils::mailer::MailConfig;
+use chat_core::utils::oidc::OidcConfig;
+use chat_core::utils::password::Argon2Config;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub db_url: String,
     pub base_url: String,
     pub auth: AuthConfig,
+    pub mail: MailConfig,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
@@ -18,6 +25,32 @@ pub struct ServerConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub sk: String,
+    /// public keys (PEM) of rotated-out signing keys, still accepted for verification
+    #[serde(default)]
+    pub retired_pks: Vec<String>,
+    /// what users can do before they verify their email
+    #[serde(default)]
+    pub unverified_email: UnverifiedEmailPolicy,
+    /// sign in through an OpenID Connect provider, disabled if not set
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    /// password checking per workspace name, workspaces not listed use the database
+    #[serde(default)]
+    pub providers: HashMap<String, AuthProviderConfig>,
+    /// cost of new password hashes
+    #[serde(default)]
+    pub argon2: Argon2Config,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnverifiedEmailPolicy {
+    /// no restriction
+    Allow,
+    /// only account management (sign out, change password, resend verification) is allowed,
+    /// workspace content (chats, messages, files, users) is off limits
+    #[default]
+    Restricted,
 }
 
 impl AppConfig {
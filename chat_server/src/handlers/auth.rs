@@ -1,46 +1,169 @@
 use axum::extract::State;
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
-use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
-use chat_core::models::{CreateUser, SigninUser, User};
+use chat_core::models::{
+    AccessToken, CreateUser, MfaChallenge, RefreshToken, RefreshTokenRequest, Session, SessionInfo,
+    SigninThrottle, SigninUser, User, UserTotp, WorkspaceMember,
+};
+
+use chat_core::error::ChatCoreError;
 
 use crate::error::AppError;
+use crate::handlers::{send_verification_mail, MfaPending};
 use crate::ChatState;
 
 pub(crate) async fn signin_handler(
     State(state): State<ChatState>,
+    info: SessionInfo,
     Json(SigninUser { email, password }): Json<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
-    match User::verify_password(&email, &password, &state.pool).await {
-        Ok(user) => {
-            let token = state.jwt_signer.sign(user)?;
-            info!("user {} signed in", email);
-            Ok((StatusCode::OK, Json(AuthToken { token })).into_response())
-        }
+    let ip = info.ip.clone();
+    SigninThrottle::check(&email, ip.as_deref(), &state.pool).await?;
+    match state
+        .auth_providers
+        .authenticate(&email, &password, &state.pool)
+        .await
+    {
+        Ok(user) => complete_signin(&state, &user, &info).await,
         Err(err) => {
             warn!("error: {}", err);
+            if matches!(err, ChatCoreError::InvalidCredentials) {
+                SigninThrottle::record_failure(&email, ip.as_deref(), &state.pool).await?;
+            }
             Err(AppError::from(err))
         }
     }
 }
 
+/// the first factor passed: issue the tokens, or a pending token if TOTP is enabled.
+/// The account's failed attempts are only cleared once every factor passed
+pub(crate) async fn complete_signin(
+    state: &ChatState,
+    user: &User,
+    info: &SessionInfo,
+) -> Result<Response, AppError> {
+    user.ensure_active()?;
+    if UserTotp::is_enabled(user.id, &state.pool).await? {
+        let mfa_token = MfaChallenge::create(user.id, &state.pool).await?;
+        info!("user {} passed the first factor, mfa pending", user.email);
+        return Ok((StatusCode::OK, Json(MfaPending { mfa_token })).into_response());
+    }
+    SigninThrottle::record_success(&user.email, &state.pool).await?;
+    let token = AuthToken::issue(state, user, info).await?;
+    info!("user {} signed in", user.email);
+    Ok((StatusCode::OK, Json(token)).into_response())
+}
+
 pub(crate) async fn signup_handler(
     State(state): State<ChatState>,
+    info: SessionInfo,
     Json(create_user): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let email = &create_user.email.clone();
-    let user = User::create(create_user, &state.pool).await?;
-    let token = state.jwt_signer.sign(user)?;
+    let user = User::create(create_user, &state.config.auth.argon2, &state.pool).await?;
+    send_verification_mail(&state, &user).await?;
+    let token = AuthToken::issue(&state, &user, &info).await?;
     info!("user {} signed up", email);
-    Ok((StatusCode::CREATED, Json(AuthToken { token })))
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+pub(crate) async fn refresh_handler(
+    State(state): State<ChatState>,
+    Json(RefreshTokenRequest { refresh_token }): Json<RefreshTokenRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let (rotated, refresh_token) = RefreshToken::rotate(&refresh_token, &state.pool).await?;
+    let mut switched = None;
+    if let Some(session_id) = rotated.session_id {
+        Session::touch(session_id, &state.pool).await?;
+        switched = Session::find(session_id, &state.pool)
+            .await?
+            .and_then(|session| session.ws_id);
+    }
+    // stay in the workspace the session switched to, unless the user left it
+    let user = match switched {
+        Some(ws_id) => User::find_in_workspace(rotated.user_id, ws_id, &state.pool).await?,
+        None => None,
+    };
+    let user = match user {
+        Some(user) => user,
+        None => User::find_user_by_id(rotated.user_id, &state.pool)
+            .await?
+            .ok_or_else(|| ChatCoreError::NotFound("user".to_string()))?,
+    };
+    let token = AuthToken::sign(&state, &user, rotated.session_id).await?;
+    Ok(Json(AuthToken {
+        token,
+        refresh_token,
+    }))
+}
+
+pub(crate) async fn signout_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Extension(access): Extension<AccessToken>,
+    Json(RefreshTokenRequest { refresh_token }): Json<RefreshTokenRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    access.revoke(&state.pool).await?;
+    RefreshToken::revoke(&refresh_token, user.id, &state.pool).await?;
+    if let Some(session_id) = access.session_id {
+        Session::revoke(session_id, user.id, &state.pool).await?;
+    }
+    info!("user {} signed out", user.email);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn jwks_handler(State(state): State<ChatState>) -> impl IntoResponse {
+    Json(state.jwt_signer.jwks())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct AuthToken {
-    token: String,
+    pub(crate) token: String,
+    pub(crate) refresh_token: String,
+}
+
+impl AuthToken {
+    /// start a new session for the user
+    pub(crate) async fn issue(
+        state: &ChatState,
+        user: &User,
+        info: &SessionInfo,
+    ) -> Result<Self, AppError> {
+        let session = Session::create(user.id, info, &state.pool).await?;
+        Self::for_session(state, user, Some(session.id)).await
+    }
+
+    /// a new pair of tokens for an existing session
+    pub(crate) async fn for_session(
+        state: &ChatState,
+        user: &User,
+        session_id: Option<i64>,
+    ) -> Result<Self, AppError> {
+        let refresh_token = RefreshToken::create(user.id, session_id, &state.pool).await?;
+        let token = Self::sign(state, user, session_id).await?;
+        Ok(Self {
+            token,
+            refresh_token,
+        })
+    }
+
+    /// users no longer in their default workspace, e.g. it was archived, only get
+    /// account access until they join another one
+    async fn sign(
+        state: &ChatState,
+        user: &User,
+        session_id: Option<i64>,
+    ) -> Result<String, AppError> {
+        let token = match WorkspaceMember::find(user.ws_id, user.id, &state.pool).await? {
+            Some(_) => state.jwt_signer.sign_session(user, session_id)?,
+            None => state.jwt_signer.sign_account_session(user, session_id)?,
+        };
+        Ok(token)
+    }
 }
 
 #[cfg(test)]
@@ -48,6 +171,7 @@ mod tests {
     use anyhow::Result;
     use http_body_util::BodyExt;
 
+    use chat_core::middlewares::jwt::JwtVerify;
     use chat_core::utils::jwt::JwtSigner;
 
     use super::*;
@@ -61,14 +185,19 @@ mod tests {
             fullname: "lign".to_string(),
             email: "testlign@gmail.com".to_string(),
             password_hash: None,
+            token_generation: 0,
+            email_verified_at: None,
+            deactivated_at: None,
+            profile: Default::default(),
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
-        let token = signer.sign(user.clone()).unwrap();
+        let token = signer.sign(&user).unwrap();
         eprintln!("token: {}", token);
 
         let res = signer.verify(&token).unwrap();
-        assert_eq!(res, user);
+        assert_eq!(res.user_id, user.id);
+        assert_eq!(res.ws_id, user.ws_id);
     }
 
     #[tokio::test]
@@ -79,8 +208,9 @@ mod tests {
             fullname: "lign".to_string(),
             email: "testlign@gmail.com".to_string(),
             password: "password123".to_string(),
+            invite_code: None,
         };
-        let res = signup_handler(State(state), Json(create_user))
+        let res = signup_handler(State(state), SessionInfo::default(), Json(create_user))
             .await?
             .into_response();
         assert_eq!(res.status(), StatusCode::CREATED);
@@ -98,13 +228,16 @@ mod tests {
             fullname: "lign".to_string(),
             email: "testlign@gmail.com".to_string(),
             password: "password123".to_string(),
+            invite_code: None,
         };
-        User::create(create_user, &state.pool).await.unwrap();
+        User::create(create_user, &state.config.auth.argon2, &state.pool)
+            .await
+            .unwrap();
         let signin_user = SigninUser {
             email: "testlign@gmail.com".to_string(),
             password: "password123".to_string(),
         };
-        let res = signin_handler(State(state), Json(signin_user))
+        let res = signin_handler(State(state), SessionInfo::default(), Json(signin_user))
             .await
             .into_response();
         assert_eq!(res.status(), StatusCode::OK);
@@ -112,4 +245,96 @@ mod tests {
         let body = serde_json::from_slice::<AuthToken>(&body).unwrap();
         assert_ne!(body.token, "");
     }
+
+    #[tokio::test]
+    async fn test_signin_handler_should_throttle_failures() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let addr = || SessionInfo {
+            ip: Some("127.0.0.1".to_string()),
+            ..Default::default()
+        };
+        let signin = |password: &str| {
+            Json(SigninUser {
+                email: "alice@bbc.com".to_string(),
+                password: password.to_string(),
+            })
+        };
+
+        let mut statuses = vec![];
+        for _ in 0..4 {
+            let res = signin_handler(State(state.clone()), addr(), signin("wrong"))
+                .await
+                .into_response();
+            statuses.push(res.status());
+        }
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::UNAUTHORIZED,
+                StatusCode::UNAUTHORIZED,
+                StatusCode::UNAUTHORIZED,
+                StatusCode::TOO_MANY_REQUESTS
+            ]
+        );
+
+        // even the right password is rejected while backing off
+        let res = signin_handler(State(state.clone()), addr(), signin("123456"))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_signout_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let user = User::find_user_by_email("alice@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let token = AuthToken::issue(&state, &user, &SessionInfo::default()).await?;
+
+        let res = refresh_handler(
+            State(state.clone()),
+            Json(RefreshTokenRequest {
+                refresh_token: token.refresh_token.clone(),
+            }),
+        )
+        .await?
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let refreshed = serde_json::from_slice::<AuthToken>(&body)?;
+        assert_ne!(refreshed.refresh_token, token.refresh_token);
+        // the refreshed token stays in the same session
+        let access = state.jwt_signer.verify(&refreshed.token)?;
+        assert_eq!(
+            access.session_id,
+            state.jwt_signer.verify(&token.token)?.session_id
+        );
+
+        // a refresh token can only be used once
+        let res = refresh_handler(
+            State(state.clone()),
+            Json(RefreshTokenRequest {
+                refresh_token: token.refresh_token,
+            }),
+        )
+        .await;
+        assert!(res.is_err());
+
+        let res = signout_handler(
+            State(state.clone()),
+            Extension(user.clone()),
+            Extension(access.clone()),
+            Json(RefreshTokenRequest {
+                refresh_token: refreshed.refresh_token,
+            }),
+        )
+        .await?
+        .into_response();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(state.is_revoked(&access).await?);
+        assert!(Session::list(user.id, None, &state.pool).await?.is_empty());
+        Ok(())
+    }
 }
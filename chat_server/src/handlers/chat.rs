@@ -3,23 +3,31 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
 
-use chat_core::models::{Chat, CreateChat, User};
+use chat_core::models::{Chat, CreateChat, Permission, User};
 
 use crate::error::AppError;
+use crate::middlewares::{permission as perm, Require};
 use crate::models::UpdateChat;
 use crate::ChatState;
 
+/// guests only see the chats they were added to
 pub(crate) async fn list_chat_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    Require { member, .. }: Require<perm::ViewChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = Chat::list_chats_in_workspace(user.ws_id, &state.pool).await?;
+    let chat = if member.role.can(Permission::ViewWorkspace) {
+        Chat::list_chats_in_workspace(user.ws_id, &state.pool).await?
+    } else {
+        Chat::list_chats_of_member(user.ws_id, user.id, &state.pool).await?
+    };
     Ok(Json(chat))
 }
 
 pub(crate) async fn create_chat_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    _: Require<perm::CreateChat>,
     Json(create_chat): Json<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
     let chat = Chat::create(create_chat, user.ws_id, user.id, &state.pool).await?;
@@ -29,18 +37,56 @@ pub(crate) async fn create_chat_handler(
 pub(crate) async fn delete_chat_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    _: Require<perm::ManageChat>,
     Path(id): Path<i64>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = Chat::delete(id, user.id, &state.pool).await?;
+    let chat = Chat::delete(id, user.ws_id, user.id, &state.pool).await?;
     Ok(Json(chat))
 }
 
 pub(crate) async fn update_chat_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    _: Require<perm::ManageChat>,
     Path(id): Path<i64>,
     Json(update_chat): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = Chat::update_owner(id, user.id, update_chat.new_owner_id, &state.pool).await?;
+    let chat = Chat::update_owner(
+        id,
+        user.ws_id,
+        user.id,
+        update_chat.new_owner_id,
+        &state.pool,
+    )
+    .await?;
     Ok(Json(chat))
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_guests_only_list_their_chats() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        sqlx::query("UPDATE workspace_members SET role = 'guest' WHERE user_id = 4")
+            .execute(&state.pool)
+            .await?;
+        let mut counts = vec![];
+        for id in [3, 4] {
+            let user = User::find_in_workspace(id, 1, &state.pool).await?.unwrap();
+            let require = Require::check(&user, &state).await?;
+            let res = list_chat_handler(State(state.clone()), Extension(user), require)
+                .await?
+                .into_response();
+            let body = res.into_body().collect().await?.to_bytes();
+            counts.push(serde_json::from_slice::<Vec<Chat>>(&body)?.len());
+        }
+        // bob sees every chat of bbc, charlie only group_chat and general_ch
+        assert_eq!(counts, [5, 2]);
+        Ok(())
+    }
+}
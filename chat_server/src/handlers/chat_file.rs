@@ -8,43 +8,79 @@ use axum::{Extension, Json};
 use tokio::fs;
 use tokio_util::io::ReaderStream;
 
-use chat_core::User;
+use chat_core::error::ChatCoreError;
+use chat_core::models::{Messages, Permission};
+use chat_core::{User, WorkspaceSettings};
 
 use crate::error::AppError;
+use crate::middlewares::{permission as perm, Require};
 use crate::models::ChatFile;
 use crate::ChatState;
 
 pub(crate) async fn upload_file_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    _: Require<perm::UploadFile>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, AppError> {
     let base_url = state.config.base_url.clone();
     let ws_id = user.ws_id;
+    let settings = WorkspaceSettings::get(ws_id, &state.pool).await?;
 
     let mut urls = Vec::new();
 
-    while let Some(field) = multipart.next_field().await? {
+    while let Some(mut field) = multipart.next_field().await? {
         let name = field.file_name().unwrap().to_string();
-        let content = field.bytes().await?;
-        let chat_file = ChatFile::create(&name, content.as_ref(), ws_id, &base_url).await?;
+        if !settings.allows_file(&name) {
+            return Err(ChatCoreError::BadRequest(format!(
+                "{} is not an allowed file type, allowed: {}",
+                name,
+                settings.allowed_extensions.join(", ")
+            ))
+            .into());
+        }
+        // stop reading as soon as the file outgrows the workspace limit
+        let mut content = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            if (content.len() + chunk.len()) as i64 > settings.max_upload_size {
+                return Err(ChatCoreError::PayloadTooLarge(format!(
+                    "{} is larger than {} bytes",
+                    name, settings.max_upload_size
+                ))
+                .into());
+            }
+            content.extend_from_slice(&chunk);
+        }
+        let chat_file = ChatFile::create(&name, &content, ws_id, &base_url).await?;
         let url = chat_file.hash_to_path(ws_id);
         urls.push(url);
     }
     Ok((StatusCode::CREATED, Json(urls)))
 }
 
+/// guests only get the files shared in their chats, avatars of coworkers are
+/// served whichever workspace stores them
 pub(crate) async fn download_file_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    Require { member, .. }: Require<perm::ViewChat>,
     Path(url): Path<String>,
 ) -> Result<impl IntoResponse, AppError> {
     let chat_file = ChatFile::from_str(&url)?;
-    if user.ws_id != chat_file.ws_id {
+    let path = chat_file.hash_to_path(chat_file.ws_id);
+    let allowed = if User::is_avatar_seen_by(&path, user.id, &state.pool).await? {
+        true
+    } else if user.ws_id != chat_file.ws_id {
+        false
+    } else {
+        member.role.can(Permission::ViewWorkspace)
+            || Messages::is_file_shared_with(&path, user.ws_id, user.id, &state.pool).await?
+    };
+    if !allowed {
         return Err(AppError::Forbidden("you don't have permission".to_string()));
     }
 
-    let url = chat_file.local_path(&state.config.base_url, user.ws_id);
+    let url = chat_file.local_path(&state.config.base_url, chat_file.ws_id);
     let file = fs::File::open(&url).await?;
     let stream = ReaderStream::new(file);
 
@@ -55,3 +91,87 @@ pub(crate) async fn download_file_handler(
 
     Ok((headers, body))
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use tower::ServiceExt;
+
+    use chat_core::models::UpdateWorkspaceSettings;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upload_stops_at_the_workspace_limit() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let update = UpdateWorkspaceSettings {
+            max_upload_size: Some(16),
+            ..Default::default()
+        };
+        WorkspaceSettings::update(1, update, &state.pool).await?;
+        let alice = User::find_in_workspace(2, 1, &state.pool).await?.unwrap();
+        let token = state.jwt_signer.sign(&alice)?;
+        let app = crate::get_router(state).await;
+
+        for (size, status) in [
+            (16, StatusCode::CREATED),
+            (17, StatusCode::PAYLOAD_TOO_LARGE),
+        ] {
+            let body = format!(
+                "--X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n{}\r\n--X--\r\n",
+                "a".repeat(size)
+            );
+            let res = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/files")
+                        .header("Authorization", format!("Bearer {}", token))
+                        .header("Content-Type", "multipart/form-data; boundary=X")
+                        .body(Body::from(body))?,
+                )
+                .await?;
+            assert_eq!(res.status(), status);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_guests_only_download_files_of_their_chats() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        // charlie is a guest, in group_chat(1) but not in private_ch(2)
+        sqlx::query("UPDATE workspace_members SET role = 'guest' WHERE user_id = 4")
+            .execute(&state.pool)
+            .await?;
+        let charlie = User::find_in_workspace(4, 1, &state.pool).await?.unwrap();
+        let base_url = &state.config.base_url;
+        let shared = ChatFile::create("shared.txt", b"shared", 1, base_url).await?;
+        let private = ChatFile::create("private.txt", b"private", 1, base_url).await?;
+        for (chat_id, file) in [(1, &shared), (2, &private)] {
+            sqlx::query("INSERT INTO messages (chat_id, sender_id, content, file) VALUES ($1, 1, 'file', $2)")
+                .bind(chat_id)
+                .bind(vec![file.hash_to_path(1)])
+                .execute(&state.pool)
+                .await?;
+        }
+
+        let download = |file: &ChatFile| {
+            let state = state.clone();
+            let charlie = charlie.clone();
+            let url = file.hash_to_path(1);
+            async move {
+                let require = Require::check(&charlie, &state).await?;
+                download_file_handler(State(state), Extension(charlie), require, Path(url)).await
+            }
+        };
+        assert!(download(&shared).await.is_ok());
+        assert!(matches!(
+            download(&private).await,
+            Err(AppError::Forbidden(_))
+        ));
+        Ok(())
+    }
+}
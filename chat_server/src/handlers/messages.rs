@@ -8,24 +8,33 @@ use axum::{Extension, Json};
 use serde_json::json;
 use tracing::warn;
 
+use chat_core::error::ChatCoreError;
 use chat_core::models::{CreateMessage, ListMessages, Messages, User};
 
 use crate::error::AppError;
+use crate::middlewares::{permission as perm, Require};
 use crate::models::ChatFile;
 use crate::ChatState;
 
 pub(crate) async fn send_message_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    _: Require<perm::SendMessage>,
     Path(id): Path<i64>,
     Json(mut create_message): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
     let mut non_exists_file = Vec::new();
+    let mut foreign_file = Vec::new();
 
     let chat_file = create_message
         .file
         .iter()
         .filter_map(|s| match ChatFile::from_str(s) {
+            Ok(chat_file) if chat_file.ws_id != user.ws_id => {
+                warn!("file {} belongs to workspace {}", s, chat_file.ws_id);
+                foreign_file.push(s.clone());
+                None
+            }
             Ok(chat_file) => {
                 if !chat_file.exists(&state.config.base_url, user.ws_id) {
                     warn!("file {} not found", s);
@@ -51,9 +60,17 @@ pub(crate) async fn send_message_handler(
     //     }
     // }
 
+    if !foreign_file.is_empty() {
+        return Err(ChatCoreError::CrossWorkspace(format!(
+            "files {:?} are not in workspace {}",
+            foreign_file, user.ws_id
+        ))
+        .into());
+    }
+
     let _ = mem::replace(&mut create_message.file, chat_file);
 
-    let _message = Messages::create(create_message, user.id, id, &state.pool).await?;
+    let _message = Messages::create(create_message, user.ws_id, user.id, id, &state.pool).await?;
 
     let ret = json!({"non_exists_file": non_exists_file});
     Ok((StatusCode::CREATED, Json(ret)))
@@ -75,9 +92,12 @@ pub(crate) async fn send_message_handler(
 )]
 pub(crate) async fn list_messages_handler(
     State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ViewChat>,
     Path(id): Path<i64>,
     Query(list_messages): Query<ListMessages>,
 ) -> Result<impl IntoResponse, AppError> {
-    let messages = Messages::list_messages_in_chat(list_messages, id, &state.pool).await?;
+    let messages =
+        Messages::list_messages_in_chat(list_messages, user.ws_id, id, &state.pool).await?;
     Ok(Json(messages))
 }
@@ -0,0 +1,158 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use chat_core::error::ChatCoreError;
+use chat_core::models::{
+    MfaChallenge, MfaCode, MfaSignin, RecoveryCodes, SessionInfo, SigninThrottle, User, UserTotp,
+};
+
+use crate::error::AppError;
+use crate::handlers::AuthToken;
+use crate::ChatState;
+
+pub(crate) async fn enroll_totp_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let enrollment = UserTotp::enroll(&user, &state.pool).await?;
+    Ok(Json(enrollment))
+}
+
+/// the recovery codes are only ever shown in this response
+pub(crate) async fn confirm_totp_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Json(MfaCode { code }): Json<MfaCode>,
+) -> Result<impl IntoResponse, AppError> {
+    let recovery_codes = UserTotp::confirm(user.id, &code, &state.pool).await?;
+    info!("user {} enabled two-factor authentication", user.email);
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+pub(crate) async fn disable_totp_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Json(MfaCode { code }): Json<MfaCode>,
+) -> Result<impl IntoResponse, AppError> {
+    UserTotp::disable(user.id, &code, &state.pool).await?;
+    info!("user {} disabled two-factor authentication", user.email);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// second step of the sign-in, trades the pending token and a code for real tokens.
+/// Wrong codes count as failed sign-ins of the account, like wrong passwords
+pub(crate) async fn signin_mfa_handler(
+    State(state): State<ChatState>,
+    info: SessionInfo,
+    Json(MfaSignin { mfa_token, code }): Json<MfaSignin>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = MfaChallenge::pending_user(&mfa_token, &state.pool).await?;
+    let user = User::find_user_by_id(user_id, &state.pool)
+        .await?
+        .ok_or_else(|| ChatCoreError::NotFound("user".to_string()))?;
+    user.ensure_active()?;
+    let ip = info.ip.as_deref();
+    SigninThrottle::check(&user.email, ip, &state.pool).await?;
+    if let Err(e) = MfaChallenge::verify(&mfa_token, &code, &state.pool).await {
+        SigninThrottle::record_failure(&user.email, ip, &state.pool).await?;
+        return Err(e.into());
+    }
+    SigninThrottle::record_success(&user.email, &state.pool).await?;
+    info!("user {} signed in", user.email);
+    let token = AuthToken::issue(&state, &user, &info).await?;
+    Ok(Json(token))
+}
+
+/// returned by signin instead of `AuthToken` when the user has two-factor authentication enabled
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct MfaPending {
+    pub(crate) mfa_token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use chat_core::models::SigninUser;
+    use chat_core::utils::totp;
+
+    use super::*;
+    use crate::handlers::signin_handler;
+
+    #[tokio::test]
+    async fn test_two_step_signin() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let user = User::find_user_by_email("alice@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let enrollment = UserTotp::enroll(&user, &state.pool).await?;
+        let now = chrono::Utc::now().timestamp();
+        let code = totp::generate_code(&enrollment.secret, now).unwrap();
+        let res = confirm_totp_handler(
+            State(state.clone()),
+            Extension(user.clone()),
+            Json(MfaCode { code }),
+        )
+        .await?
+        .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let RecoveryCodes { recovery_codes } = serde_json::from_slice(&body)?;
+
+        let signin = SigninUser {
+            email: "alice@bbc.com".to_string(),
+            password: "123456".to_string(),
+        };
+        let res = signin_handler(State(state.clone()), SessionInfo::default(), Json(signin))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let MfaPending { mfa_token } = serde_json::from_slice(&body)?;
+
+        let res = signin_mfa_handler(
+            State(state.clone()),
+            SessionInfo::default(),
+            Json(MfaSignin {
+                mfa_token: mfa_token.clone(),
+                code: "not a code".to_string(),
+            }),
+        )
+        .await;
+        assert!(res.is_err());
+        // the wrong code counts against the account, the right password didn't clear it
+        let failures: (i32,) = sqlx::query_as(
+            "SELECT failures FROM signin_throttles WHERE scope = 'account' AND key = $1",
+        )
+        .bind(&user.email)
+        .fetch_one(&state.pool)
+        .await?;
+        assert_eq!(failures.0, 1);
+
+        let res = signin_mfa_handler(
+            State(state.clone()),
+            SessionInfo::default(),
+            Json(MfaSignin {
+                mfa_token,
+                code: recovery_codes[0].clone(),
+            }),
+        )
+        .await?
+        .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let token: AuthToken = serde_json::from_slice(&body)?;
+        assert_eq!(state.jwt_signer.verify(&token.token)?.user_id, user.id);
+        SigninThrottle::check(&user.email, None, &state.pool).await?;
+        let cleared: Option<(i32,)> =
+            sqlx::query_as("SELECT failures FROM signin_throttles WHERE key = $1")
+                .bind(&user.email)
+                .fetch_optional(&state.pool)
+                .await?;
+        assert!(cleared.is_none());
+        Ok(())
+    }
+}
@@ -2,10 +2,24 @@ pub(crate) use auth::*;
 pub(crate) use chat::*;
 pub(crate) use chat_file::*;
 pub(crate) use messages::*;
+pub(crate) use mfa::*;
+pub(crate) use oidc::*;
+pub(crate) use password::*;
+pub(crate) use profile::*;
+pub(crate) use session::*;
+pub(crate) use token::*;
+pub(crate) use verification::*;
 pub(crate) use workspace::*;
 
 mod auth;
 mod chat;
 mod chat_file;
 mod messages;
+mod mfa;
+mod oidc;
+mod password;
+mod profile;
+mod session;
+mod token;
+mod verification;
 mod workspace;
@@ -0,0 +1,269 @@
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Redirect};
+use tracing::info;
+
+use chat_core::error::ChatCoreError;
+use chat_core::models::{OidcCallback, OidcLogin, SessionInfo, SigninThrottle, UserIdentity};
+
+use crate::error::AppError;
+use crate::handlers::complete_signin;
+use crate::ChatState;
+
+/// redirect the browser to the identity provider
+pub(crate) async fn oidc_login_handler(
+    State(state): State<ChatState>,
+) -> Result<impl IntoResponse, AppError> {
+    let oidc = state
+        .oidc
+        .as_ref()
+        .ok_or_else(|| ChatCoreError::NotFound("oidc provider".to_string()))?;
+    let (login_state, login) = OidcLogin::create(&state.pool).await?;
+    let url = oidc.authorization_url(&login_state, &login.nonce, &login.code_verifier);
+    Ok(Redirect::to(&url))
+}
+
+/// the provider redirects back here, the signed-in user goes through the same second factor
+/// and lockout as a password sign-in
+pub(crate) async fn oidc_callback_handler(
+    State(state): State<ChatState>,
+    info: SessionInfo,
+    Query(callback): Query<OidcCallback>,
+) -> Result<impl IntoResponse, AppError> {
+    let oidc = state
+        .oidc
+        .as_ref()
+        .ok_or_else(|| ChatCoreError::NotFound("oidc provider".to_string()))?;
+    let login = OidcLogin::take(&callback.state, &state.pool).await?;
+    let code = match (callback.code, callback.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            return Err(ChatCoreError::Unauthorized(format!(
+                "authorization failed: {}",
+                error.unwrap_or_else(|| "no code".to_string())
+            ))
+            .into())
+        }
+    };
+
+    let ip = info.ip.clone();
+    let identity = oidc
+        .authenticate(&code, &login.code_verifier, &login.nonce)
+        .await?;
+    let user = UserIdentity::resolve(&identity, &oidc.config.workspace, &state.pool).await?;
+    SigninThrottle::check(&user.email, ip.as_deref(), &state.pool).await?;
+    info!(
+        "user {} authenticated through {}",
+        user.email, identity.issuer
+    );
+    complete_signin(&state, &user, &info).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+    use axum::extract::Form;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use http_body_util::BodyExt;
+    use jwt_simple::prelude::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use chat_core::models::{User, UserTotp};
+    use chat_core::utils::jwt::JwtSigner;
+    use chat_core::utils::oidc::{pkce_challenge, OidcConfig};
+    use chat_core::utils::totp;
+
+    use super::*;
+    use crate::handlers::{AuthToken, MfaPending};
+    use crate::AppConfig;
+
+    const CLIENT_ID: &str = "chat";
+
+    /// authorization codes issued by the mock provider: code => (nonce, code challenge)
+    #[derive(Clone)]
+    struct MockIdp {
+        issuer: String,
+        key: Arc<ES256KeyPair>,
+        codes: Arc<Mutex<HashMap<String, (String, String)>>>,
+    }
+
+    async fn start_mock_idp() -> Result<MockIdp> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let issuer = format!("http://{}", listener.local_addr()?);
+        let jwks = JwtSigner::load("./fixtures/pkcs8.pem")?.jwks();
+        let key = ES256KeyPair::from_pem(include_str!("../../fixtures/pkcs8.pem"))?
+            .with_key_id(&jwks.keys[0].kid);
+        let idp = MockIdp {
+            issuer,
+            key: Arc::new(key),
+            codes: Default::default(),
+        };
+
+        let discovery = json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        });
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { axum::Json(discovery) }),
+            )
+            .route("/jwks", get(move || async move { axum::Json(jwks) }))
+            .route("/token", post(mock_token_handler))
+            .with_state(idp.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(idp)
+    }
+
+    async fn mock_token_handler(
+        State(idp): State<MockIdp>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> impl IntoResponse {
+        let code = form.get("code").cloned().unwrap_or_default();
+        let Some((nonce, challenge)) = idp.codes.lock().unwrap().remove(&code) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+        if pkce_challenge(&verifier) != challenge {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        let claims = Claims::with_custom_claims(
+            json!({ "email": "zoe@acme.com", "email_verified": true, "name": "Zoe" }),
+            Duration::from_mins(5),
+        )
+        .with_issuer(&idp.issuer)
+        .with_audience(CLIENT_ID)
+        .with_subject("zoe")
+        .with_nonce(nonce);
+        let id_token = idp.key.sign(claims).unwrap();
+        axum::Json(
+            json!({ "access_token": "opaque", "token_type": "Bearer", "id_token": id_token }),
+        )
+        .into_response()
+    }
+
+    /// act as the browser: follow the redirect, "sign in" at the provider, come back with a code
+    async fn authorize(state: &ChatState, idp: &MockIdp, code: &str) -> Result<OidcCallback> {
+        let res = oidc_login_handler(State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = res.headers()["location"].to_str()?.to_string();
+        assert!(location.starts_with(&format!("{}/authorize?", idp.issuer)));
+        let params: HashMap<&str, &str> = location
+            .split_once('?')
+            .unwrap()
+            .1
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .collect();
+        assert_eq!(params["code_challenge_method"], "S256");
+        idp.codes.lock().unwrap().insert(
+            code.to_string(),
+            (
+                params["nonce"].to_string(),
+                params["code_challenge"].to_string(),
+            ),
+        );
+        Ok(OidcCallback {
+            state: params["state"].to_string(),
+            code: Some(code.to_string()),
+            error: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_with_mock_idp() -> Result<()> {
+        let idp = start_mock_idp().await?;
+        let mut config = AppConfig::load()?;
+        config.auth.oidc = Some(OidcConfig {
+            issuer: idp.issuer.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:6688/api/oidc/callback".to_string(),
+            workspace: "acme".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+        });
+        let (state, _tdb) = ChatState::new_for_test_with_config(config).await;
+
+        let callback = authorize(&state, &idp, "code-1").await?;
+        let res = oidc_callback_handler(
+            State(state.clone()),
+            SessionInfo::default(),
+            Query(callback.clone()),
+        )
+        .await?
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let token: AuthToken = serde_json::from_slice(&body)?;
+        let access = state.jwt_signer.verify(&token.token)?;
+        let user = User::find_user_by_id(access.user_id, &state.pool)
+            .await?
+            .unwrap();
+        assert_eq!(user.email, "zoe@acme.com");
+        assert!(user.email_verified_at.is_some());
+
+        // the state can't be replayed
+        let res = oidc_callback_handler(
+            State(state.clone()),
+            SessionInfo::default(),
+            Query(callback),
+        )
+        .await;
+        assert!(res.is_err());
+
+        // signing in again maps to the same user
+        let callback = authorize(&state, &idp, "code-2").await?;
+        let res = oidc_callback_handler(
+            State(state.clone()),
+            SessionInfo::default(),
+            Query(callback),
+        )
+        .await?
+        .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let token: AuthToken = serde_json::from_slice(&body)?;
+        assert_eq!(state.jwt_signer.verify(&token.token)?.user_id, user.id);
+
+        // with TOTP enabled the provider only passes the first factor
+        let enrollment = UserTotp::enroll(&user, &state.pool).await?;
+        let code = totp::generate_code(&enrollment.secret, chrono::Utc::now().timestamp()).unwrap();
+        UserTotp::confirm(user.id, &code, &state.pool).await?;
+        let callback = authorize(&state, &idp, "code-3").await?;
+        let res = oidc_callback_handler(
+            State(state.clone()),
+            SessionInfo::default(),
+            Query(callback),
+        )
+        .await?
+        .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let pending: MfaPending = serde_json::from_slice(&body)?;
+        assert!(!pending.mfa_token.is_empty());
+
+        // nor does it get around a locked account
+        sqlx::query(
+            "INSERT INTO signin_throttles (scope, key, failures, locked, blocked_until) VALUES ('account', $1, 10, true, NOW() + interval '1 hour')",
+        )
+        .bind(&user.email)
+        .execute(&state.pool)
+        .await?;
+        let callback = authorize(&state, &idp, "code-4").await?;
+        let res = oidc_callback_handler(
+            State(state.clone()),
+            SessionInfo::default(),
+            Query(callback),
+        )
+        .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+}
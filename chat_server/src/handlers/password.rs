@@ -0,0 +1,227 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use tracing::{info, warn};
+
+use chat_core::models::{
+    AccessToken, ChangePassword, ForgotPassword, PasswordResetToken, ResetPassword, Session,
+    SessionInfo, User,
+};
+use chat_core::utils::mailer::Mail;
+
+use crate::error::AppError;
+use crate::handlers::AuthToken;
+use crate::ChatState;
+
+/// always accepted, whether the email is registered or not is never revealed.
+/// The token is issued and mailed in the background so the response takes as long either way
+pub(crate) async fn forgot_password_handler(
+    State(state): State<ChatState>,
+    Json(ForgotPassword { email }): Json<ForgotPassword>,
+) -> impl IntoResponse {
+    tokio::spawn(async move {
+        if let Err(e) = send_reset_mail(&state, &email).await {
+            warn!("send reset mail to {} error: {}", email, e);
+        }
+    });
+    StatusCode::ACCEPTED
+}
+
+async fn send_reset_mail(state: &ChatState, email: &str) -> Result<(), AppError> {
+    let Some(user) = User::find_user_by_email(email, &state.pool).await? else {
+        info!("password reset requested for unknown email {}", email);
+        return Ok(());
+    };
+    let token = PasswordResetToken::create(user.id, &state.pool).await?;
+    let mail = Mail {
+        to: user.email,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Use the following token to reset your password, it expires in 1 hour:\n\n{}",
+            token
+        ),
+    };
+    state.mailer.send(mail).await?;
+    Ok(())
+}
+
+pub(crate) async fn reset_password_handler(
+    State(state): State<ChatState>,
+    Json(ResetPassword { token, password }): Json<ResetPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = PasswordResetToken::reset_password(
+        &token,
+        &password,
+        &state.config.auth.argon2,
+        &state.pool,
+    )
+    .await?;
+    state.users.invalidate(user.id);
+    info!("user {} reset password", user.email);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// every other session is signed out, the caller gets a fresh pair of tokens
+pub(crate) async fn change_password_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Extension(access): Extension<AccessToken>,
+    info: SessionInfo,
+    Json(change): Json<ChangePassword>,
+) -> Result<impl IntoResponse, AppError> {
+    let argon2 = &state.config.auth.argon2;
+    User::verify_password(&user.email, &change.current_password, argon2, &state.pool).await?;
+    let user = User::update_password(user.id, &change.new_password, argon2, &state.pool).await?;
+    Session::revoke_all(user.id, access.session_id, &state.pool).await?;
+    state.users.invalidate(user.id);
+    info!("user {} changed password", user.email);
+
+    // a token without a session (issued before sessions existed) starts a new one
+    let token = match access.session_id {
+        Some(session_id) => AuthToken::for_session(&state, &user, Some(session_id)).await?,
+        None => AuthToken::issue(&state, &user, &info).await?,
+    };
+    Ok(Json(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use chat_core::middlewares::jwt::jwt_verify;
+    use chat_core::models::RefreshToken;
+    use chat_core::utils::mailer::{FileMailer, MailConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reset_password_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let MailConfig::File { file } = &state.config.mail else {
+            panic!("file mailer expected in test config");
+        };
+        let sent = FileMailer::new(file)
+            .read_all()
+            .await?
+            .iter()
+            .filter(|m| m.to == "bob@bbc.com")
+            .count();
+        let res = forgot_password_handler(
+            State(state.clone()),
+            Json(ForgotPassword {
+                email: "bob@bbc.com".to_string(),
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        // unknown emails look the same
+        let res = forgot_password_handler(
+            State(state.clone()),
+            Json(ForgotPassword {
+                email: "nobody@bbc.com".to_string(),
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        // the mail is sent in the background
+        let mut mail = None;
+        for _ in 0..50 {
+            let mut mails = FileMailer::new(file).read_all().await?;
+            mails.retain(|m| m.to == "bob@bbc.com");
+            if mails.len() > sent {
+                mail = mails.pop();
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let mail = mail.expect("reset mail should be sent");
+        let token = mail.body.lines().last().unwrap().to_string();
+
+        let reset = |token: String| {
+            reset_password_handler(
+                State(state.clone()),
+                Json(ResetPassword {
+                    token,
+                    password: "new_password".to_string(),
+                }),
+            )
+        };
+        let res = reset(token.clone()).await?.into_response();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(reset(token).await.is_err());
+
+        User::verify_password(
+            "bob@bbc.com",
+            "new_password",
+            &state.config.auth.argon2,
+            &state.pool,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_change_password_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let app = Router::new()
+            .route("/", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(state.clone(), jwt_verify::<ChatState>));
+        let user = User::find_user_by_email("charlie@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let old = AuthToken::issue(&state, &user, &SessionInfo::default()).await?;
+        let current = AuthToken::issue(&state, &user, &SessionInfo::default()).await?;
+        let access = state.jwt_signer.verify(&current.token)?;
+
+        let change = |current: &str| {
+            change_password_handler(
+                State(state.clone()),
+                Extension(user.clone()),
+                Extension(access.clone()),
+                SessionInfo::default(),
+                Json(ChangePassword {
+                    current_password: current.to_string(),
+                    new_password: "new_password".to_string(),
+                }),
+            )
+        };
+        assert!(change("bad_password").await.is_err());
+        let res = change("123456").await?.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // tokens issued before the change are rejected
+        let res = app
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header("Authorization", format!("Bearer {}", old.token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(RefreshToken::rotate(&old.refresh_token, &state.pool)
+            .await
+            .is_err());
+        // the kept session only refreshes with the new refresh token
+        assert!(RefreshToken::rotate(&current.refresh_token, &state.pool)
+            .await
+            .is_err());
+
+        // only the session that changed the password is left
+        let sessions = Session::list(user.id, None, &state.pool).await?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(Some(sessions[0].id), access.session_id);
+        Ok(())
+    }
+}
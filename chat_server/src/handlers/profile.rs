@@ -0,0 +1,222 @@
+use std::str::FromStr;
+
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use tracing::info;
+
+use chat_core::error::ChatCoreError;
+use chat_core::models::{UpdateProfile, User};
+
+use crate::error::AppError;
+use crate::models::ChatFile;
+use crate::ChatState;
+
+const AVATAR_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me",
+    responses(
+        (status = 200, description = "Profile of the signed-in user", body = User)
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_me_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let me = User::find_in_workspace(user.id, user.ws_id, &state.pool)
+        .await?
+        .ok_or_else(|| ChatCoreError::NotFound(format!("user {}", user.id)))?;
+
+    Ok(Json(me))
+}
+
+/// members of the user's workspaces are told through the notify server
+#[utoipa::path(
+    patch,
+    path = "/api/users/me",
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, description = "Update the profile of the signed-in user", body = User),
+        (status = 400, description = "Invalid field, or the avatar is not an uploaded image")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_me_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Json(update): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(avatar) = update.avatar.as_deref().filter(|a| !a.is_empty()) {
+        check_avatar(avatar, &user, &state)?;
+    }
+    let me = User::update_profile(user.id, update, &state.pool).await?;
+    state.users.invalidate(user.id);
+    info!("user {} updated the profile", user.email);
+
+    Ok(Json(User {
+        ws_id: user.ws_id,
+        ..me
+    }))
+}
+
+/// members of the caller's workspace, and deactivated users who belonged to it or
+/// sent messages to the caller's chats
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}",
+    params(
+        ("id" = i64, Path, description = "User id")
+    ),
+    responses(
+        (status = 200, description = "Profile of a user", body = User),
+        (status = 404, description = "No such user in the workspace")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_user_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let found = match User::find_in_workspace(id, user.ws_id, &state.pool).await? {
+        Some(found) => Some(found),
+        None => User::find_deactivated_seen_by(id, user.ws_id, user.id, &state.pool).await?,
+    };
+    let found = found.ok_or_else(|| ChatCoreError::NotFound(format!("user {}", id)))?;
+
+    Ok(Json(User {
+        password_hash: None,
+        ..found
+    }))
+}
+
+/// an image uploaded to the user's workspace
+fn check_avatar(avatar: &str, user: &User, state: &ChatState) -> Result<(), AppError> {
+    let file = ChatFile::from_str(avatar)?;
+    let is_image = file
+        .ext
+        .as_deref()
+        .is_some_and(|ext| AVATAR_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    if file.ws_id != user.ws_id || !is_image || !file.exists(&state.config.base_url, user.ws_id) {
+        return Err(ChatCoreError::BadRequest(format!(
+            "avatar {} is not an image uploaded to workspace {}",
+            avatar, user.ws_id
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::http::StatusCode;
+    use http_body_util::BodyExt;
+
+    use chat_core::models::WorkspaceMember;
+
+    use crate::handlers::download_file_handler;
+    use crate::middlewares::Require;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_profile_handlers() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let alice = User::find_in_workspace(2, 1, &state.pool).await?.unwrap();
+        let bob = User::find_in_workspace(3, 1, &state.pool).await?.unwrap();
+        let doe = User::find_in_workspace(5, 3, &state.pool).await?.unwrap();
+
+        let avatar = ChatFile::create("me.png", b"png", 1, &state.config.base_url).await?;
+        let update = |avatar: String| UpdateProfile {
+            display_name: Some("Al".to_string()),
+            timezone: Some("Europe/London".to_string()),
+            avatar: Some(avatar),
+            ..Default::default()
+        };
+        let res = update_me_handler(
+            State(state.clone()),
+            Extension(alice.clone()),
+            Json(update(avatar.hash_to_path(3))),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = update_me_handler(
+            State(state.clone()),
+            Extension(alice.clone()),
+            Json(update(avatar.hash_to_path(1))),
+        )
+        .await?
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = get_user_handler(State(state.clone()), Extension(bob.clone()), Path(2))
+            .await?
+            .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let found: User = serde_json::from_slice(&body)?;
+        assert_eq!(found.profile.display_name.as_deref(), Some("Al"));
+        assert_eq!(found.profile.avatar, Some(avatar.hash_to_path(1)));
+
+        let res = get_user_handler(State(state.clone()), Extension(doe.clone()), Path(2))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = update_me_handler(
+            State(state.clone()),
+            Extension(alice.clone()),
+            Json(update("files/1/ab.png".to_string())),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // once alice also joins fox its members see her avatar, stored in bbc
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES (3, 2)")
+            .execute(&state.pool)
+            .await?;
+        let require = Require::check(&doe, &state).await?;
+        let res = download_file_handler(
+            State(state.clone()),
+            Extension(doe.clone()),
+            require,
+            Path(avatar.hash_to_path(1)),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        // deactivated users are only found by those who may have come across them
+        sqlx::query("UPDATE users SET title = 'intern' WHERE id = 4")
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let admin = WorkspaceMember::find(1, 1, &state.pool).await?.unwrap();
+        admin.deactivate(4, 1, &state.pool).await?;
+        let res = get_user_handler(State(state.clone()), Extension(doe), Path(4))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = get_user_handler(State(state.clone()), Extension(bob), Path(4))
+            .await?
+            .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let found: User = serde_json::from_slice(&body)?;
+        assert_eq!(found.fullname, "Deactivated user");
+        assert_eq!(found.profile.title, None);
+        Ok(())
+    }
+}
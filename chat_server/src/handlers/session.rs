@@ -0,0 +1,116 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use tracing::info;
+
+use chat_core::models::{AccessToken, Session, User};
+
+use crate::error::AppError;
+use crate::ChatState;
+
+/// the session of the request is flagged as `current`
+pub(crate) async fn list_sessions_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Extension(access): Extension<AccessToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let sessions = Session::list(user.id, access.session_id, &state.pool).await?;
+    Ok(Json(sessions))
+}
+
+/// sign out a device, its tokens stop working and its event stream is closed
+pub(crate) async fn revoke_session_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Session::revoke(id, user.id, &state.pool).await?;
+    info!("user {} revoked session {}", user.email, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use axum::Router;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use chat_core::middlewares::jwt::jwt_verify;
+    use chat_core::models::SessionInfo;
+
+    use crate::handlers::AuthToken;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_revoke_session_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let app = Router::new()
+            .route("/", get(list_sessions_handler))
+            .layer(from_fn_with_state(state.clone(), jwt_verify::<ChatState>))
+            .with_state(state.clone());
+        let user = User::find_user_by_email("bob@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let laptop = AuthToken::issue(
+            &state,
+            &user,
+            &SessionInfo {
+                device_name: Some("laptop".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let phone = AuthToken::issue(&state, &user, &SessionInfo::default()).await?;
+        let list = |token: String| {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let res = list(phone.token.clone()).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let sessions: Vec<Session> = serde_json::from_slice(&body)?;
+        assert_eq!(sessions.len(), 2);
+        let laptop_session = sessions
+            .iter()
+            .find(|s| s.device_name.as_deref() == Some("laptop"))
+            .unwrap();
+        assert!(!laptop_session.current);
+
+        let res = revoke_session_handler(
+            State(state.clone()),
+            Extension(user.clone()),
+            Path(laptop_session.id),
+        )
+        .await?
+        .into_response();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        // the access token of the revoked session is rejected right away
+        let res = list(laptop.token).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = list(phone.token).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // sessions of other users can't be revoked
+        let other = User::find_user_by_email("alice@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let res =
+            revoke_session_handler(State(state), Extension(other), Path(sessions[0].id)).await;
+        assert!(res.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use tracing::info;
+
+use chat_core::models::{CreatePersonalAccessToken, PersonalAccessToken, User};
+
+use crate::error::AppError;
+use crate::ChatState;
+
+pub(crate) async fn list_tokens_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = PersonalAccessToken::list(user.id, &state.pool).await?;
+    Ok(Json(tokens))
+}
+
+/// the plain token is only in this response, it can't be shown again
+pub(crate) async fn create_token_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Json(input): Json<CreatePersonalAccessToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = PersonalAccessToken::create(&user, input, &state.pool).await?;
+    info!("user {} created api key {}", user.email, token.info.id);
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+pub(crate) async fn revoke_token_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    PersonalAccessToken::revoke(id, user.id, &state.pool).await?;
+    info!("user {} revoked api key {}", user.email, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use chat_core::models::NewPersonalAccessToken;
+
+    use super::*;
+    use crate::get_router;
+
+    #[tokio::test]
+    async fn test_personal_token_scopes() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let user = User::find_user_by_email("alice@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let input = CreatePersonalAccessToken {
+            name: "bot".to_string(),
+            scopes: vec!["chats:read".to_string()],
+            expires_in_days: None,
+        };
+        let res = create_token_handler(State(state.clone()), Extension(user), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = res.into_body().collect().await?.to_bytes();
+        let NewPersonalAccessToken { token, .. } = serde_json::from_slice(&body)?;
+
+        let app = get_router(state).await;
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"content":"hi","files":[]}"#))
+        };
+
+        let res = app.clone().oneshot(request("GET", "/api/chat")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        // sending messages needs messages:write
+        let res = app.clone().oneshot(request("POST", "/api/chat/1")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // api keys can't manage api keys
+        let res = app.clone().oneshot(request("GET", "/api/tokens")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
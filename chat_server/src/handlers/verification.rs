@@ -0,0 +1,141 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use tracing::{info, warn};
+
+use chat_core::models::{EmailVerificationToken, User, VerifyEmail};
+use chat_core::utils::mailer::Mail;
+
+use crate::error::AppError;
+use crate::ChatState;
+
+pub(crate) async fn verify_email_handler(
+    State(state): State<ChatState>,
+    Json(VerifyEmail { token }): Json<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = EmailVerificationToken::verify(&token, &state.pool).await?;
+    state.users.invalidate(user.id);
+    info!("user {} verified email", user.email);
+    Ok(Json(user))
+}
+
+pub(crate) async fn resend_verification_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    if user.email_verified_at.is_some() {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    send_verification_mail(&state, &user).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// a failed delivery doesn't fail the caller, the user can ask for another mail
+pub(crate) async fn send_verification_mail(state: &ChatState, user: &User) -> Result<(), AppError> {
+    let token = EmailVerificationToken::create(user.id, &state.pool).await?;
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Verify your email".to_string(),
+        body: format!(
+            "Use the following token to verify your email, it expires in 48 hours:\n\n{}",
+            token
+        ),
+    };
+    if let Err(e) = state.mailer.send(mail).await {
+        warn!("send verification mail to {} error: {}", user.email, e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use chat_core::utils::mailer::{FileMailer, MailConfig};
+
+    use crate::get_router;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_verify_email_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        sqlx::query("UPDATE workspaces SET join_policy = 'open' WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let app = get_router(state.clone()).await;
+        let res = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/signup")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        r#"{"ws_name":"bbc","fullname":"ann","email":"ann@bbc.com","password":"123456"}"#,
+                    ))?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = res.into_body().collect().await?.to_bytes();
+        let token = serde_json::from_slice::<serde_json::Value>(&body)?["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // unverified users can't access the workspace yet
+        let list_users = || {
+            Request::builder()
+                .uri("/api/users")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+        let res = app.clone().oneshot(list_users()?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // nor join other workspaces
+        sqlx::query("UPDATE workspaces SET join_policy = 'open' WHERE id = 3")
+            .execute(&state.pool)
+            .await?;
+        let join_fox = || {
+            Request::builder()
+                .method("POST")
+                .uri("/api/workspaces/join")
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"ws_name":"fox"}"#))
+        };
+        let res = app.clone().oneshot(join_fox()?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let MailConfig::File { file } = &state.config.mail else {
+            panic!("file mailer expected in test config");
+        };
+        let mail = FileMailer::new(file)
+            .read_all()
+            .await?
+            .into_iter()
+            .rfind(|m| m.to == "ann@bbc.com")
+            .expect("verification mail should be sent");
+        let verification = mail.body.lines().last().unwrap().to_string();
+        let res = verify_email_handler(
+            State(state.clone()),
+            Json(VerifyEmail {
+                token: verification,
+            }),
+        )
+        .await?
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app.clone().oneshot(list_users()?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(join_fox()?).await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        Ok(())
+    }
+}
@@ -1,18 +1,31 @@
-use axum::extract::State;
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
+use tracing::info;
 
-use chat_core::models::{CreateWorkspace, User, Workspace};
+use chat_core::error::ChatCoreError;
+use chat_core::models::{
+    AccessToken, CreateWorkspace, CreateWorkspaceInvite, DeactivateUser, JoinWorkspace, ListUsers,
+    Session, SessionInfo, SigninLockout, UpdateJoinPolicy, UpdateMemberRole, UpdateWorkspace,
+    UpdateWorkspaceSettings, User, Workspace, WorkspaceInvite, WorkspaceMember, WorkspaceSettings,
+};
 
 use crate::error::AppError;
-use crate::ChatState;
+use crate::handlers::AuthToken;
+use crate::middlewares::{permission as perm, Require};
+use crate::models::ChatFile;
+use crate::{ChatState, UnverifiedEmailPolicy};
 
 #[utoipa::path(
     get,
     path = "/api/users",
+    params(
+        ListUsers
+    ),
     responses(
-        (status = 200, description = "List users in the workspace", body = [User])
+        (status = 200, description = "A page of the users in the workspace", body = [User]),
+        (status = 400, description = "Invalid page size")
     ),
     security(
         ("token" = [])
@@ -21,8 +34,10 @@ use crate::ChatState;
 pub(crate) async fn list_users_handler(
     State(state): State<ChatState>,
     Extension(user): Extension<User>,
+    _: Require<perm::ViewWorkspace>,
+    Query(list): Query<ListUsers>,
 ) -> Result<impl IntoResponse, AppError> {
-    let users = User::list_users_by_workspace(user.ws_id, &state.pool).await?;
+    let users = User::list_users_by_workspace(user.ws_id, list, &state.pool).await?;
 
     Ok(Json(users))
 }
@@ -31,7 +46,7 @@ pub(crate) async fn list_users_handler(
     get,
     path = "/api/workspaces",
     responses(
-        (status = 200, description = "List all workspaces", body = [Workspace])
+        (status = 200, description = "List the workspaces the user is a member of", body = [Workspace])
     ),
     security(
         ("token" = [])
@@ -39,8 +54,9 @@ pub(crate) async fn list_users_handler(
 )]
 pub(crate) async fn list_workspace_handler(
     State(state): State<ChatState>,
+    Extension(user): Extension<User>,
 ) -> Result<impl IntoResponse, AppError> {
-    let ws = Workspace::list_workspaces(&state.pool).await?;
+    let ws = Workspace::list_for_user(user.id, &state.pool).await?;
 
     Ok(Json(ws))
 }
@@ -48,8 +64,10 @@ pub(crate) async fn list_workspace_handler(
 #[utoipa::path(
     post,
     path = "/api/workspaces",
+    request_body = CreateWorkspace,
     responses(
-        (status = 200, description = "Create a new workspace", body = Workspace)
+        (status = 201, description = "Create a new workspace owned by the caller", body = Workspace),
+        (status = 409, description = "The name is taken")
     ),
     security(
         ("token" = [])
@@ -57,10 +75,929 @@ pub(crate) async fn list_workspace_handler(
 )]
 pub(crate) async fn create_workspace_handler(
     State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::CreateWorkspace>,
     Json(create_workspace): Json<CreateWorkspace>,
 ) -> Result<impl IntoResponse, AppError> {
-    let ws = Workspace::create(create_workspace, &state.pool).await?;
-    let ws = serde_json::to_string(&ws)?;
+    let ws = Workspace::create(create_workspace, user.id, &state.pool).await?;
+    state.users.invalidate(user.id);
+    info!("user {} created workspace {}", user.email, ws.name);
+
+    Ok((StatusCode::CREATED, Json(ws)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/workspaces/{id}",
+    params(
+        ("id" = i64, Path, description = "Workspace id, the one the token acts in")
+    ),
+    request_body = UpdateWorkspace,
+    responses(
+        (status = 200, description = "Rename the workspace or transfer its ownership", body = Workspace),
+        (status = 403, description = "Only admins may rename and only the owner may transfer the workspace")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+    Path(id): Path<i64>,
+    Json(update): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    same_workspace(&user, id)?;
+    if update.owner_id.is_some() {
+        Require::<perm::TransferOwnership>::check(&user, &state).await?;
+    }
+    let mut ws = None;
+    if let Some(name) = update.name {
+        ws = Some(Workspace::rename(id, &name, &state.pool).await?);
+    }
+    if let Some(owner_id) = update.owner_id {
+        ws = Some(Workspace::update_owner(id, owner_id, &state.pool).await?);
+        info!(
+            "user {} transferred workspace {} to {}",
+            user.email, id, owner_id
+        );
+    }
+    let ws = match ws {
+        Some(ws) => ws,
+        None => Workspace::find_workspace_by_id(id, &state.pool)
+            .await?
+            .ok_or_else(|| ChatCoreError::NotFound(format!("workspace {}", id)))?,
+    };
+
+    Ok(Json(ws))
+}
+
+/// archives the workspace, see `Workspace::archive` for what is kept
+#[utoipa::path(
+    delete,
+    path = "/api/workspaces/{id}",
+    params(
+        ("id" = i64, Path, description = "Workspace id, the one the token acts in")
+    ),
+    responses(
+        (status = 204, description = "Delete the chats, messages, invitations, memberships and files of the workspace"),
+        (status = 403, description = "Only the owner may delete the workspace")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_workspace_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::DeleteWorkspace>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    same_workspace(&user, id)?;
+    let ws = Workspace::archive(id, &state.pool).await?;
+    ChatFile::remove_workspace(&state.config.base_url, id).await?;
+    state.users.invalidate_workspace(id);
+    info!("user {} deleted workspace {}", user.email, ws.name);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/{id}/settings",
+    params(
+        ("id" = i64, Path, description = "Workspace id, the one the token acts in")
+    ),
+    responses(
+        (status = 200, description = "Limits and policies of the workspace", body = WorkspaceSettings),
+        (status = 403, description = "Only workspace admins may see the settings")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_settings_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    same_workspace(&user, id)?;
+    let settings = WorkspaceSettings::get(id, &state.pool).await?;
+
+    Ok(Json(settings))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/workspaces/{id}/settings",
+    params(
+        ("id" = i64, Path, description = "Workspace id, the one the token acts in")
+    ),
+    request_body = UpdateWorkspaceSettings,
+    responses(
+        (status = 200, description = "Change limits and policies of the workspace", body = WorkspaceSettings),
+        (status = 403, description = "Only workspace admins may change the settings")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_settings_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+    Path(id): Path<i64>,
+    Json(update): Json<UpdateWorkspaceSettings>,
+) -> Result<impl IntoResponse, AppError> {
+    same_workspace(&user, id)?;
+    let settings = WorkspaceSettings::update(id, update, &state.pool).await?;
+    info!(
+        "user {} updated the settings of workspace {}",
+        user.email, id
+    );
+
+    Ok(Json(settings))
+}
+
+/// tokens act in a single workspace, managing another one takes switching to it first
+fn same_workspace(user: &User, id: i64) -> Result<(), AppError> {
+    if user.ws_id != id {
+        return Err(ChatCoreError::CrossWorkspace(format!(
+            "the token acts in workspace {}, not {}",
+            user.ws_id, id
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/lockouts",
+    responses(
+        (status = 200, description = "List sign-in lockouts of accounts in the workspace and of client ips", body = [SigninLockout]),
+        (status = 403, description = "Only workspace admins may list lockouts")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_lockouts_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let lockouts = SigninLockout::list_in_workspace(user.ws_id, &state.pool).await?;
+
+    Ok(Json(lockouts))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/invites",
+    responses(
+        (status = 200, description = "List open invitations of the workspace", body = [WorkspaceInvite]),
+        (status = 403, description = "Only workspace admins may manage invitations")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_invites_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = WorkspaceInvite::list(user.ws_id, &state.pool).await?;
+
+    Ok(Json(invites))
+}
+
+/// the invitation code is only in this response, it can't be shown again
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/invites",
+    request_body = CreateWorkspaceInvite,
+    responses(
+        (status = 201, description = "Create an invitation code", body = NewWorkspaceInvite),
+        (status = 403, description = "Only workspace admins may manage invitations")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_invite_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+    Json(input): Json<CreateWorkspaceInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = WorkspaceInvite::create(user.ws_id, user.id, input, &state.pool).await?;
+    info!("user {} created invite {}", user.email, invite.info.id);
+
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspaces/invites/{id}",
+    params(
+        ("id" = i64, Path, description = "Invitation id")
+    ),
+    responses(
+        (status = 204, description = "Revoke an invitation"),
+        (status = 403, description = "Only workspace admins may manage invitations")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_invite_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    WorkspaceInvite::revoke(id, user.ws_id, &state.pool).await?;
+    info!("user {} revoked invite {}", user.email, id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/workspaces/join-policy",
+    request_body = UpdateJoinPolicy,
+    responses(
+        (status = 200, description = "Set who may join the workspace at signup", body = Workspace),
+        (status = 403, description = "Only workspace admins may change the join policy")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_join_policy_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ManageWorkspace>,
+    Json(update): Json<UpdateJoinPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = Workspace::update_join_policy(user.ws_id, update, &state.pool).await?;
+    info!(
+        "user {} set join policy of {} to {:?}",
+        user.email, ws.name, ws.join_policy
+    );
+
+    Ok(Json(ws))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/members",
+    responses(
+        (status = 200, description = "List members of the workspace with their roles", body = [WorkspaceMember])
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_members_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    _: Require<perm::ViewWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = WorkspaceMember::list(user.ws_id, &state.pool).await?;
+
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/workspaces/members/{id}",
+    params(
+        ("id" = i64, Path, description = "User id of the member")
+    ),
+    request_body = UpdateMemberRole,
+    responses(
+        (status = 200, description = "Change the role of a member", body = WorkspaceMember),
+        (status = 403, description = "Roles can only be changed by a higher role")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_member_role_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Require { member: caller, .. }: Require<perm::ManageMembers>,
+    Path(id): Path<i64>,
+    Json(UpdateMemberRole { role }): Json<UpdateMemberRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = caller.update_role(id, role, &state.pool).await?;
+    info!("user {} made user {} {}", user.email, id, role);
+
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/members/{id}/deactivate",
+    params(
+        ("id" = i64, Path, description = "User id of the member")
+    ),
+    request_body = DeactivateUser,
+    responses(
+        (status = 200, description = "Deactivate a member, their chats and workspace ownership go to the successor. Users of other workspaces only leave this one", body = User),
+        (status = 403, description = "Members can only be deactivated by a higher role")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn deactivate_member_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Require { member: caller, .. }: Require<perm::ManageMembers>,
+    Path(id): Path<i64>,
+    Json(DeactivateUser { successor_id }): Json<DeactivateUser>,
+) -> Result<impl IntoResponse, AppError> {
+    let deactivated = caller.deactivate(id, successor_id, &state.pool).await?;
+    state.users.invalidate(id);
+    info!(
+        "user {} deactivated user {}, successor {}",
+        user.email, id, successor_id
+    );
+
+    Ok(Json(deactivated))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/join",
+    request_body = JoinWorkspace,
+    responses(
+        (status = 201, description = "Join another workspace with the signed-in account", body = WorkspaceMember),
+        (status = 403, description = "The workspace requires an invitation, or the email is not verified")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn join_workspace_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Json(join): Json<JoinWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    // the route is on the account routes, outside of `require_verified_email`
+    if state.config.auth.unverified_email != UnverifiedEmailPolicy::Allow
+        && user.email_verified_at.is_none()
+    {
+        return Err(AppError::Forbidden(format!(
+            "email {} not verified",
+            user.email
+        )));
+    }
+    let member = WorkspaceMember::join(
+        &join.ws_name,
+        &user,
+        join.invite_code.as_deref(),
+        &state.pool,
+    )
+    .await?;
+    state.users.invalidate(user.id);
+    info!("user {} joined {}", user.email, join.ws_name);
+
+    Ok((StatusCode::CREATED, Json(member)))
+}
+
+/// new tokens acting in the workspace, refreshing them stays in it
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/switch",
+    params(
+        ("id" = i64, Path, description = "Workspace id")
+    ),
+    responses(
+        (status = 200, description = "Tokens scoped to the workspace"),
+        (status = 403, description = "The user is not a member of the workspace")
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn switch_workspace_handler(
+    State(state): State<ChatState>,
+    Extension(user): Extension<User>,
+    Extension(access): Extension<AccessToken>,
+    info: SessionInfo,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = User::find_in_workspace(user.id, id, &state.pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::Forbidden(format!(
+                "{} is not a member of workspace {}",
+                user.email, id
+            ))
+        })?;
+    // the workspace is remembered by the session, a token without one (issued before
+    // sessions existed) starts a new session so refreshing stays in the workspace
+    let session_id = match access.session_id {
+        Some(session_id) => session_id,
+        None => Session::create(user.id, &info, &state.pool).await?.id,
+    };
+    Session::switch_workspace(session_id, id, &state.pool).await?;
+    info!("user {} switched to workspace {}", user.email, id);
+    let token = AuthToken::for_session(&state, &user, Some(session_id)).await?;
+
+    Ok(Json(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use chat_core::models::{
+        CreateUser, JoinPolicy, NewWorkspaceInvite, RefreshTokenRequest, WorkspaceRole,
+    };
+
+    use crate::handlers::signup_handler;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_invite_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 5")
+            .execute(&state.pool)
+            .await?;
+        let admin = User::find_user_by_email("doe@fox.com", &state.pool)
+            .await?
+            .unwrap();
+        let member = User::find_user_by_email("eve@fox.com", &state.pool)
+            .await?
+            .unwrap();
+        let input = CreateWorkspaceInvite {
+            max_uses: Some(1),
+            expires_in_hours: Some(24),
+        };
+
+        let res = Require::<perm::ManageWorkspace>::check(&member, &state).await;
+        assert!(matches!(res, Err(AppError::Forbidden(_))));
+
+        let require = Require::check(&admin, &state).await?;
+        let res =
+            create_invite_handler(State(state.clone()), Extension(admin), require, Json(input))
+                .await?
+                .into_response();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = res.into_body().collect().await?.to_bytes();
+        let invite: NewWorkspaceInvite = serde_json::from_slice(&body)?;
+
+        let signup = |invite_code: Option<String>| {
+            signup_handler(
+                State(state.clone()),
+                SessionInfo::default(),
+                Json(CreateUser {
+                    ws_name: "fox".to_string(),
+                    fullname: "ann".to_string(),
+                    email: format!("ann{}@gmail.com", invite_code.is_some()),
+                    password: "123456".to_string(),
+                    invite_code,
+                }),
+            )
+        };
+        let res = signup(None).await.into_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = signup(Some(invite.code)).await.into_response();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_join_policy_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        sqlx::query("UPDATE workspace_members SET role = 'owner' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let owner = User::find_user_by_email("tyran@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let update = |join_policy, allowed_domains: &[&str]| {
+            let (state, owner) = (state.clone(), owner.clone());
+            let update = UpdateJoinPolicy {
+                join_policy,
+                allowed_domains: allowed_domains.iter().map(|d| d.to_string()).collect(),
+            };
+            async move {
+                let require = Require::check(&owner, &state).await?;
+                update_join_policy_handler(State(state), Extension(owner), require, Json(update))
+                    .await
+            }
+        };
+
+        assert!(update(JoinPolicy::AllowedDomains, &[]).await.is_err());
+        let res = update(JoinPolicy::AllowedDomains, &["@BBC.co.uk", " bbc.com "])
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let ws: Workspace = serde_json::from_slice(&body)?;
+        assert_eq!(ws.allowed_domains, ["bbc.co.uk", "bbc.com"]);
+        assert!(ws.admits("ann@bbc.co.uk"));
+        assert!(!ws.admits("ann@evil-bbc.com"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_member_role_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let admin = User::find_user_by_email("tyran@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let update = |id: i64, role| {
+            let (state, admin) = (state.clone(), admin.clone());
+            async move {
+                let require = Require::check(&admin, &state).await?;
+                update_member_role_handler(
+                    State(state),
+                    Extension(admin),
+                    require,
+                    Path(id),
+                    Json(UpdateMemberRole { role }),
+                )
+                .await
+            }
+        };
+
+        let res = update(3, WorkspaceRole::Guest).await?.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let bob = WorkspaceMember::find(1, 3, &state.pool).await?.unwrap();
+        assert_eq!(bob.role, WorkspaceRole::Guest);
+
+        let res = update(3, WorkspaceRole::Admin).await.into_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // the member is in another workspace
+        let res = update(5, WorkspaceRole::Guest).await.into_response();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_switch_workspace_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let app = crate::get_router(state.clone()).await;
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES (3, 2)")
+            .execute(&state.pool)
+            .await?;
+        let alice = User::find_user_by_email("alice@bbc.com", &state.pool)
+            .await?
+            .unwrap();
+        let token = AuthToken::issue(&state, &alice, &SessionInfo::default()).await?;
+        let request = |method: &str, uri: &str, token: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/api/workspaces/2/switch", &token.token)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/api/workspaces/3/switch", &token.token)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let switched: AuthToken = serde_json::from_slice(&body)?;
+        assert_eq!(state.jwt_signer.verify(&switched.token)?.ws_id, 3);
+
+        // workspace-scoped queries follow the token
+        let res = app
+            .clone()
+            .oneshot(request("GET", "/api/users", &switched.token)?)
+            .await?;
+        let body = res.into_body().collect().await?.to_bytes();
+        let users: Vec<User> = serde_json::from_slice(&body)?;
+        let mut emails: Vec<_> = users.iter().map(|u| u.email.as_str()).collect();
+        emails.sort();
+        assert_eq!(emails, ["alice@bbc.com", "doe@fox.com", "eve@fox.com"]);
+
+        // refreshing stays in the workspace
+        let res = crate::handlers::refresh_handler(
+            State(state.clone()),
+            Json(RefreshTokenRequest {
+                refresh_token: switched.refresh_token,
+            }),
+        )
+        .await?
+        .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let refreshed: AuthToken = serde_json::from_slice(&body)?;
+        assert_eq!(state.jwt_signer.verify(&refreshed.token)?.ws_id, 3);
+
+        // so does a token issued without a session, switching starts one
+        let sessionless = state.jwt_signer.sign(&alice)?;
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/api/workspaces/3/switch", &sessionless)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let switched: AuthToken = serde_json::from_slice(&body)?;
+        assert!(state
+            .jwt_signer
+            .verify(&switched.token)?
+            .session_id
+            .is_some());
+        let res = crate::handlers::refresh_handler(
+            State(state.clone()),
+            Json(RefreshTokenRequest {
+                refresh_token: switched.refresh_token,
+            }),
+        )
+        .await?
+        .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let refreshed: AuthToken = serde_json::from_slice(&body)?;
+        assert_eq!(state.jwt_signer.verify(&refreshed.token)?.ws_id, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_and_delete_workspace_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let app = crate::get_router(state.clone()).await;
+        for statement in [
+            "UPDATE workspace_members SET role = 'owner' WHERE user_id = 1",
+            "UPDATE workspace_members SET role = 'admin' WHERE user_id = 2",
+            "UPDATE workspaces SET owner_id = 1 WHERE id = 1",
+        ] {
+            sqlx::query(statement).execute(&state.pool).await?;
+        }
+        let token = |id: i64| {
+            let state = state.clone();
+            async move {
+                let user = User::find_user_by_id(id, &state.pool).await?.unwrap();
+                anyhow::Ok(state.jwt_signer.sign(&user)?)
+            }
+        };
+        let (owner, admin) = (token(1).await?, token(2).await?);
+        // bob only belongs to bbc
+        let bob = User::find_user_by_id(3, &state.pool).await?.unwrap();
+        let signed_in = AuthToken::issue(&state, &bob, &SessionInfo::default()).await?;
+        let request = |method: &str, uri: &str, token: &str, body: serde_json::Value| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+        };
+
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                "/api/workspaces/1",
+                &admin,
+                serde_json::json!({"name": "bbc news"}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                "/api/workspaces/1",
+                &admin,
+                serde_json::json!({"owner_id": 2}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .oneshot(request(
+                "DELETE",
+                "/api/workspaces/1",
+                &admin,
+                serde_json::json!({}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // tokens only manage the workspace they act in
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                "/api/workspaces/3",
+                &owner,
+                serde_json::json!({"name": "x"}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                "/api/workspaces/1",
+                &owner,
+                serde_json::json!({"owner_id": 2}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let ws: Workspace = serde_json::from_slice(&body)?;
+        assert_eq!((ws.name.as_str(), ws.owner_id), ("bbc news", 2));
+
+        let res = app
+            .clone()
+            .oneshot(request(
+                "DELETE",
+                "/api/workspaces/1",
+                &admin,
+                serde_json::json!({}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(Workspace::find_workspace_by_id(1, &state.pool)
+            .await?
+            .is_none());
+
+        // sessions acting in the workspace are revoked
+        let res = app
+            .clone()
+            .oneshot(request(
+                "GET",
+                "/api/users/me",
+                &signed_in.token,
+                serde_json::json!({}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // signing in again only grants the account routes, until bob joins a workspace
+        let signed_in = AuthToken::issue(&state, &bob, &SessionInfo::default()).await?;
+        assert!(state.jwt_signer.verify(&signed_in.token)?.is_account_only());
+        let res = app
+            .clone()
+            .oneshot(request(
+                "GET",
+                "/api/chat",
+                &signed_in.token,
+                serde_json::json!({}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        sqlx::query("UPDATE workspaces SET join_policy = 'open' WHERE id = 3")
+            .execute(&state.pool)
+            .await?;
+        let res = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/api/workspaces/join",
+                &signed_in.token,
+                serde_json::json!({"ws_name": "fox"}),
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = crate::handlers::refresh_handler(
+            State(state.clone()),
+            Json(RefreshTokenRequest {
+                refresh_token: signed_in.refresh_token,
+            }),
+        )
+        .await?
+        .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let refreshed: AuthToken = serde_json::from_slice(&body)?;
+        let access = state.jwt_signer.verify(&refreshed.token)?;
+        assert_eq!(access.ws_id, 3);
+        assert!(!access.is_account_only());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_workspace_settings_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let app = crate::get_router(state.clone()).await;
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let token = |id: i64| {
+            let state = state.clone();
+            async move {
+                let user = User::find_user_by_id(id, &state.pool).await?.unwrap();
+                anyhow::Ok(state.jwt_signer.sign(&user)?)
+            }
+        };
+        let (admin, member) = (token(1).await?, token(2).await?);
+        let request = |method: &str, uri: &str, token: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+        };
+
+        let res = app
+            .clone()
+            .oneshot(request("GET", "/api/workspaces/1/settings", &member).body(Body::empty())?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .oneshot(
+                request("PATCH", "/api/workspaces/1/settings", &admin)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"allowed_extensions": ["png"]}"#))?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let settings: WorkspaceSettings = serde_json::from_slice(&body)?;
+        assert_eq!(settings.allowed_extensions, ["png"]);
+
+        // uploads follow the settings
+        let upload = |name: &str| {
+            request("POST", "/api/files", &member)
+                .header("Content-Type", "multipart/form-data; boundary=X")
+                .body(Body::from(format!(
+                    "--X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\nhello\r\n--X--\r\n",
+                    name
+                )))
+        };
+        let res = app.clone().oneshot(upload("notes.txt")?).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = app.clone().oneshot(upload("cat.png")?).await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deactivate_member_handler() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        let app = crate::get_router(state.clone()).await;
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let token = |id: i64| {
+            let state = state.clone();
+            async move {
+                let user = User::find_user_by_id(id, &state.pool).await?.unwrap();
+                anyhow::Ok(state.jwt_signer.sign(&user)?)
+            }
+        };
+        let (admin, bob) = (token(1).await?, token(3).await?);
+        let request = |method: &str, uri: &str, token: &str, body: &'static str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+        };
+        let deactivate = "/api/workspaces/members/3/deactivate";
+
+        let res = app
+            .clone()
+            .oneshot(request("POST", deactivate, &bob, r#"{"successor_id": 2}"#)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                deactivate,
+                &admin,
+                r#"{"successor_id": 2}"#,
+            )?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let deactivated: User = serde_json::from_slice(&body)?;
+        assert_eq!(deactivated.fullname, "Deactivated user");
 
-    Ok((StatusCode::OK, ws))
+        // tokens issued before are rejected
+        let res = app
+            .clone()
+            .oneshot(request("GET", "/api/chat", &bob, "")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
 }
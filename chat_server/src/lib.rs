@@ -1,22 +1,35 @@
 use std::fmt::Debug;
 use std::ops::Deref;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use axum::extract::DefaultBodyLimit;
 use axum::middleware::from_fn_with_state;
 use axum::response::IntoResponse;
-use axum::routing::{get, patch, post};
+use axum::routing::{delete, get, patch, post, put};
 use axum::Router;
 use jwt_simple::prelude::ES256KeyPair;
 use sqlx::PgPool;
-use tracing::info;
+use tracing::{info, warn};
 
 use chat_core::middlewares::jwt::JwtVerify;
-use chat_core::{middlewares::jwt::jwt_verify, utils::jwt::JwtSigner, User};
-pub use config::AppConfig;
+use chat_core::utils::auth_provider::AuthProviders;
+use chat_core::utils::cache::UserCache;
+use chat_core::utils::mailer::Mailer;
+use chat_core::utils::oidc::OidcClient;
+use chat_core::{
+    middlewares::jwt::jwt_verify, utils::jwt::JwtSigner, AccessToken, Messages,
+    PersonalAccessToken, Session, User, MAX_UPLOAD_SIZE,
+};
+pub use config::{AppConfig, UnverifiedEmailPolicy};
 use handlers::*;
 
 use crate::error::AppError;
-use crate::middlewares::{verify_chat_member, with_middleware};
+use crate::middlewares::{
+    require_scope, require_verified_email, verify_chat_member, with_middleware, ACCOUNT_RESOURCE,
+};
+use crate::models::ChatFile;
 use crate::openapi::OpenApiRouter;
 
 mod config;
@@ -26,6 +39,9 @@ mod middlewares;
 pub mod models;
 pub mod openapi;
 
+const MESSAGE_RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SESSION_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Debug, Clone)]
 pub struct ChatState {
     inner: Arc<ChatStateInner>,
@@ -35,6 +51,10 @@ pub struct ChatStateInner {
     pub(crate) config: AppConfig,
     pub(crate) pool: PgPool,
     pub(crate) jwt_signer: JwtSigner,
+    pub(crate) users: UserCache,
+    pub(crate) mailer: Box<dyn Mailer>,
+    pub(crate) oidc: Option<OidcClient>,
+    pub(crate) auth_providers: AuthProviders,
 }
 
 pub async fn get_router(state: ChatState) -> Router {
@@ -43,28 +63,114 @@ pub async fn get_router(state: ChatState) -> Router {
             "/:id",
             patch(update_chat_handler)
                 .delete(delete_chat_handler)
-                .post(send_message_handler),
+                .route_layer(from_fn_with_state("chats", require_scope)),
+        )
+        .route(
+            "/:id",
+            post(send_message_handler).route_layer(from_fn_with_state("messages", require_scope)),
+        )
+        .route(
+            "/:id/messages",
+            get(list_messages_handler).route_layer(from_fn_with_state("messages", require_scope)),
         )
-        .route("/:id/messages", get(list_messages_handler))
         .layer(from_fn_with_state(state.clone(), verify_chat_member))
-        .route("/", get(list_chat_handler).post(create_chat_handler));
+        .route(
+            "/",
+            get(list_chat_handler)
+                .post(create_chat_handler)
+                .route_layer(from_fn_with_state("chats", require_scope)),
+        );
 
     let api = Router::new()
         .route(
             "/workspaces",
             get(list_workspace_handler).post(create_workspace_handler),
         )
-        .route("/users", get(list_users_handler))
+        .route(
+            "/workspaces/:id",
+            patch(update_workspace_handler).delete(delete_workspace_handler),
+        )
+        .route(
+            "/workspaces/:id/settings",
+            get(get_settings_handler).patch(update_settings_handler),
+        )
+        .route("/workspaces/lockouts", get(list_lockouts_handler))
+        .route(
+            "/workspaces/invites",
+            get(list_invites_handler).post(create_invite_handler),
+        )
+        .route("/workspaces/invites/:id", delete(revoke_invite_handler))
+        .route("/workspaces/join-policy", put(update_join_policy_handler))
+        .route("/workspaces/members", get(list_members_handler))
+        .route("/workspaces/members/:id", patch(update_member_role_handler))
+        .route(
+            "/workspaces/members/:id/deactivate",
+            post(deactivate_member_handler),
+        )
+        .route_layer(from_fn_with_state("workspaces", require_scope))
+        .route(
+            "/users",
+            get(list_users_handler).route_layer(from_fn_with_state("users", require_scope)),
+        )
+        .route(
+            "/users/me",
+            get(get_me_handler)
+                .patch(update_me_handler)
+                .route_layer(from_fn_with_state("users", require_scope)),
+        )
+        .route(
+            "/users/:id",
+            get(get_user_handler).route_layer(from_fn_with_state("users", require_scope)),
+        )
         .nest("/chat", chat)
-        .route("/files", post(upload_file_handler))
-        .route("/download/*url", get(download_file_handler))
+        .route(
+            "/files",
+            post(upload_file_handler)
+                .route_layer(from_fn_with_state("files", require_scope))
+                // the handler stops reading a file once it outgrows the limit of the workspace
+                .layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route(
+            "/download/*url",
+            get(download_file_handler).route_layer(from_fn_with_state("files", require_scope)),
+        )
+        .layer(from_fn_with_state(state.clone(), require_verified_email))
+        .merge(
+            Router::new()
+                .route("/users/me/password", put(change_password_handler))
+                .route(
+                    "/mfa/totp",
+                    post(enroll_totp_handler).delete(disable_totp_handler),
+                )
+                .route("/mfa/totp/confirm", post(confirm_totp_handler))
+                .route(
+                    "/tokens",
+                    get(list_tokens_handler).post(create_token_handler),
+                )
+                .route("/tokens/:id", delete(revoke_token_handler))
+                .route("/sessions", get(list_sessions_handler))
+                .route("/sessions/:id", delete(revoke_session_handler))
+                .route("/workspaces/join", post(join_workspace_handler))
+                .route("/workspaces/:id/switch", post(switch_workspace_handler))
+                .route("/verify-email/resend", post(resend_verification_handler))
+                .route("/signout", post(signout_handler))
+                .route_layer(from_fn_with_state(ACCOUNT_RESOURCE, require_scope)),
+        )
         .layer(from_fn_with_state(state.clone(), jwt_verify::<ChatState>))
         .route("/signup", post(signup_handler))
-        .route("/signin", post(signin_handler));
+        .route("/verify-email", post(verify_email_handler))
+        .route("/signin", post(signin_handler))
+        .route("/signin/mfa", post(signin_mfa_handler))
+        .route("/oidc/login", get(oidc_login_handler))
+        .route("/oidc/callback", get(oidc_callback_handler))
+        .route("/refresh", post(refresh_handler))
+        .route("/password/forgot", post(forgot_password_handler))
+        .route("/password/reset", post(reset_password_handler));
 
     let router = Router::new()
         .add_openapi()
         .route("/", get(index_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
         .nest("/api", api)
         .with_state(state);
 
@@ -73,9 +179,41 @@ pub async fn get_router(state: ChatState) -> Router {
 
 impl JwtVerify for ChatState {
     type Error = AppError;
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
+    fn verify(&self, token: &str) -> Result<AccessToken, Self::Error> {
         self.jwt_signer.verify(token).map_err(AppError::from)
     }
+
+    async fn verify_personal_token(&self, token: &str) -> Result<Option<AccessToken>, Self::Error> {
+        PersonalAccessToken::verify(token, &self.pool)
+            .await
+            .map_err(AppError::from)
+    }
+
+    async fn is_revoked(&self, access: &AccessToken) -> Result<bool, Self::Error> {
+        access.is_revoked(&self.pool).await.map_err(AppError::from)
+    }
+
+    async fn touch_session(&self, access: &AccessToken) -> Result<(), Self::Error> {
+        if let Some(session_id) = access.session_id {
+            Session::touch(session_id, &self.pool).await?;
+        }
+        Ok(())
+    }
+
+    async fn resolve_user(&self, access: &AccessToken) -> Result<Option<User>, Self::Error> {
+        let user = self
+            .users
+            .get(access.user_id, access.ws_id, &self.pool)
+            .await?;
+        if user.is_some() || !access.is_account_only() {
+            return Ok(user);
+        }
+        // account-only tokens act in the default workspace even once the user left it
+        let user = User::find_user_by_id(access.user_id, &self.pool)
+            .await?
+            .filter(|user| user.ws_id == access.ws_id);
+        Ok(user)
+    }
 }
 
 impl Deref for ChatState {
@@ -101,17 +239,84 @@ impl ChatState {
         info!("connected to database: {}", config.db_url);
         let jwt_signer = JwtSigner::new(
             ES256KeyPair::from_pem(&config.auth.sk).expect("Failed to create jwt signer"),
-        );
+        )
+        .with_retired_keys(&config.auth.retired_pks)
+        .expect("Failed to load retired jwt keys");
+        let mailer = config.mail.mailer().expect("Failed to create mailer");
+        config.auth.argon2.argon2().expect("Invalid argon2 params");
+        let oidc = discover_oidc(&config).await;
+        let auth_providers = AuthProviders::new(&config.auth.providers, &config.auth.argon2)
+            .expect("Invalid auth providers");
+        spawn_message_retention(pool.clone(), config.base_url.clone());
+        spawn_session_purge(pool.clone());
         Self {
             inner: Arc::new(ChatStateInner {
                 config,
                 pool,
                 jwt_signer,
+                users: UserCache::default(),
+                mailer,
+                oidc,
+                auth_providers,
             }),
         }
     }
 }
 
+/// delete the messages past the retention of their workspace, once in a while,
+/// along with the files no longer attached to anything
+fn spawn_message_retention(pool: PgPool, base_url: String) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MESSAGE_RETENTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            let files = match Messages::purge_expired(&pool).await {
+                Ok((0, _)) => continue,
+                Ok((purged, files)) => {
+                    info!("purged {} expired messages", purged);
+                    files
+                }
+                Err(e) => {
+                    warn!("purge expired messages error: {}", e);
+                    continue;
+                }
+            };
+            for file in files {
+                let removed = match ChatFile::from_str(&file) {
+                    Ok(chat_file) => chat_file.remove(&base_url).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = removed {
+                    warn!("remove file {} error: {}", file, e);
+                }
+            }
+        }
+    });
+}
+
+fn spawn_session_purge(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SESSION_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match Session::purge_expired(&pool).await {
+                Ok(0) => {}
+                Ok(purged) => info!("purged {} expired sessions", purged),
+                Err(e) => warn!("purge expired sessions error: {}", e),
+            }
+        }
+    });
+}
+
+async fn discover_oidc(config: &AppConfig) -> Option<OidcClient> {
+    let oidc = config.auth.oidc.clone()?;
+    Some(
+        OidcClient::discover(oidc)
+            .await
+            .expect("Failed to discover oidc provider"),
+    )
+}
+
 pub(crate) async fn index_handler() -> impl IntoResponse {
     "Hello, World!"
 }
@@ -126,6 +331,10 @@ mod test_util {
     impl ChatState {
         pub async fn new_for_test() -> (Self, TestPg) {
             let config = AppConfig::load().expect("Failed to load config");
+            Self::new_for_test_with_config(config).await
+        }
+
+        pub async fn new_for_test_with_config(config: AppConfig) -> (Self, TestPg) {
             let server_url = config.db_url.rsplitn(2, '/').collect::<Vec<_>>();
             let server_url = server_url[1].to_string();
             eprintln!("server_url: {}", server_url);
@@ -133,13 +342,23 @@ mod test_util {
             let pool = tdb.get_pool().await;
             let jwt_signer = JwtSigner::new(
                 ES256KeyPair::from_pem(&config.auth.sk).expect("Failed to create jwt signer"),
-            );
+            )
+            .with_retired_keys(&config.auth.retired_pks)
+            .expect("Failed to load retired jwt keys");
+            let mailer = config.mail.mailer().expect("Failed to create mailer");
+            let oidc = discover_oidc(&config).await;
+            let auth_providers = AuthProviders::new(&config.auth.providers, &config.auth.argon2)
+                .expect("Invalid auth providers");
 
             let state = Self {
                 inner: Arc::new(ChatStateInner {
                     config,
                     pool,
                     jwt_signer,
+                    users: UserCache::default(),
+                    mailer,
+                    oidc,
+                    auth_providers,
                 }),
             };
             (state, tdb)
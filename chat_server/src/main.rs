@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use tokio::net::TcpListener;
 use tracing::info;
 use tracing::level_filters::LevelFilter;
@@ -19,6 +21,12 @@ async fn main() -> anyhow::Result<()> {
 
     let listener = TcpListener::bind(&addr).await?;
     info!("Listening on: {}", addr);
-    axum::serve(listener, get_router(state).await).await?;
+    let app = get_router(state).await;
+    // peer addresses feed the per-ip sign-in throttle
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
@@ -39,11 +39,14 @@ pub async fn verify_chat_member(
     };
     let req = Request::from_parts(parts, body);
 
-    match Chat::is_chat_member(chat_id, user.id, &state.pool).await {
+    match Chat::is_chat_member(chat_id, user.ws_id, user.id, &state.pool).await {
         Ok(true) => next.run(req).await,
         Ok(false) => (
             StatusCode::FORBIDDEN,
-            AppError::Forbidden(format!("User {} is not in the chat", user.email)),
+            AppError::Forbidden(format!(
+                "User {} is not in the chat of workspace {}",
+                user.email, user.ws_id
+            )),
         )
             .into_response(),
         Err(e) => {
@@ -86,7 +89,7 @@ mod tests {
         let user = User::find_user_by_email("alice@bbc.com", &pool)
             .await?
             .unwrap();
-        let token = state.jwt_signer.sign(user)?;
+        let token = state.jwt_signer.sign(&user)?;
         let res = app
             .clone()
             .oneshot(
@@ -8,8 +8,14 @@ use tracing::Level;
 
 use chat_core::middlewares::{request_id::with_request_id, server_time::ServerTimeLayer};
 pub use chat_member::verify_chat_member;
+pub use permission::Require;
+pub use scope::{require_scope, ACCOUNT_RESOURCE};
+pub use verified_email::require_verified_email;
 
 mod chat_member;
+pub(crate) mod permission;
+mod scope;
+mod verified_email;
 pub(crate) fn with_middleware(router: Router) -> Router {
     router.layer(
         ServiceBuilder::new()
@@ -0,0 +1,143 @@
+use std::marker::PhantomData;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+
+use chat_core::models::{Permission, User, WorkspaceMember};
+
+use crate::error::AppError;
+use crate::ChatState;
+
+/// a permission as a type, so handlers can declare it in their arguments
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+macro_rules! permissions {
+    ($($name:ident),* $(,)?) => {
+        $(
+            pub struct $name;
+
+            impl RequiredPermission for $name {
+                const PERMISSION: Permission = Permission::$name;
+            }
+        )*
+    };
+}
+
+permissions!(
+    CreateWorkspace,
+    ManageWorkspace,
+    TransferOwnership,
+    ManageMembers,
+    ViewWorkspace,
+    ViewChat,
+    CreateChat,
+    ManageChat,
+    SendMessage,
+    UploadFile,
+    DeleteWorkspace,
+);
+
+/// rejects the request unless the caller's workspace role grants `P`, e.g.
+/// `_: Require<ManageWorkspace>`. Holds the caller's membership for handlers that need the role
+pub struct Require<P> {
+    pub member: WorkspaceMember,
+    _permission: PhantomData<P>,
+}
+
+impl<P> Require<P>
+where
+    P: RequiredPermission,
+{
+    pub async fn check(user: &User, state: &ChatState) -> Result<Self, AppError> {
+        let member = WorkspaceMember::find(user.ws_id, user.id, &state.pool)
+            .await?
+            .ok_or_else(|| {
+                AppError::Forbidden(format!("{} is not a member of the workspace", user.email))
+            })?;
+        if !member.role.can(P::PERMISSION) {
+            return Err(AppError::Forbidden(format!(
+                "{:?} requires the {} role",
+                P::PERMISSION,
+                P::PERMISSION.min_role()
+            )));
+        }
+        Ok(Self {
+            member,
+            _permission: PhantomData,
+        })
+    }
+}
+
+#[async_trait]
+impl<P> FromRequestParts<ChatState> for Require<P>
+where
+    P: RequiredPermission,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ChatState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .extensions
+            .get::<User>()
+            .ok_or_else(|| AppError::Forbidden("User not found".to_string()))?;
+        Self::check(user, state).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::post;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use chat_core::middlewares::jwt::jwt_verify;
+
+    use super::*;
+
+    async fn handler(_: Require<CreateChat>) -> StatusCode {
+        StatusCode::OK
+    }
+
+    #[tokio::test]
+    async fn test_require_permission() -> Result<()> {
+        let (state, _tdb) = ChatState::new_for_test().await;
+        sqlx::query("UPDATE workspace_members SET role = 'guest' WHERE user_id = 3")
+            .execute(&state.pool)
+            .await?;
+        let app = Router::new()
+            .route("/", post(handler))
+            .layer(from_fn_with_state(state.clone(), jwt_verify::<ChatState>))
+            .with_state(state.clone());
+
+        let mut statuses = vec![];
+        for email in ["alice@bbc.com", "bob@bbc.com"] {
+            let user = User::find_user_by_email(email, &state.pool).await?.unwrap();
+            let token = state.jwt_signer.sign(&user)?;
+            let res = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/")
+                        .header("Authorization", format!("Bearer {}", token))
+                        .body(Body::empty())?,
+                )
+                .await?;
+            statuses.push(res.status());
+        }
+        // bob is a guest
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::FORBIDDEN]);
+        Ok(())
+    }
+}
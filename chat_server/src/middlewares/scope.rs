@@ -0,0 +1,30 @@
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use chat_core::models::AccessToken;
+
+use crate::error::AppError;
+
+/// resource of routes no api key can be granted, e.g. password, mfa and the api keys themselves
+pub const ACCOUNT_RESOURCE: &str = "account";
+
+/// require `{resource}:read` for safe methods and `{resource}:write` for the others,
+/// the resource is given as middleware state, e.g. `from_fn_with_state("chats", require_scope)`
+pub async fn require_scope(
+    State(resource): State<&'static str>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let action = match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => "read",
+        _ => "write",
+    };
+    let scope = format!("{}:{}", resource, action);
+    match req.extensions().get::<AccessToken>() {
+        Some(access) if access.has_scope(&scope) => next.run(req).await,
+        Some(_) => AppError::Forbidden(format!("token lacks scope {}", scope)).into_response(),
+        None => (StatusCode::UNAUTHORIZED, "token not found").into_response(),
+    }
+}
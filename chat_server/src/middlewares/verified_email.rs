@@ -0,0 +1,26 @@
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use chat_core::models::User;
+
+use crate::error::AppError;
+use crate::{ChatState, UnverifiedEmailPolicy};
+
+pub async fn require_verified_email(
+    State(state): State<ChatState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if state.config.auth.unverified_email == UnverifiedEmailPolicy::Allow {
+        return next.run(req).await;
+    }
+    match req.extensions().get::<User>() {
+        Some(user) if user.email_verified_at.is_some() => next.run(req).await,
+        Some(user) => {
+            AppError::Forbidden(format!("email {} not verified", user.email)).into_response()
+        }
+        None => (StatusCode::UNAUTHORIZED, "user not found").into_response(),
+    }
+}
@@ -18,7 +18,7 @@ impl ChatFile {
         let mut hasher = Sha1::new();
         hasher.update(content);
         let hash = format!("{:x}", hasher.finalize());
-        let ext = name.split('.').last().map(|s| s.to_string());
+        let ext = name.split('.').next_back().map(|s| s.to_string());
 
         let file = Self { ext, ws_id, hash };
         let url = file.local_path(base_url, ws_id);
@@ -44,6 +44,22 @@ impl ChatFile {
         Path::new(&self.local_path(base_url, ws_id)).exists()
     }
 
+    /// remove every file stored for the workspace
+    pub async fn remove_workspace(base_url: &str, ws_id: i64) -> Result<(), AppError> {
+        match fs::remove_dir_all(format!("{}/files/{}", base_url, ws_id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// remove the stored file, a missing file is not an error
+    pub async fn remove(&self, base_url: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.local_path(base_url, self.ws_id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
     pub async fn upload(path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> Result<(), AppError> {
         let path = path.as_ref();
         if path.exists() {
@@ -60,7 +76,9 @@ impl FromStr for ChatFile {
     type Err = AppError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let path = s.strip_prefix("files/").expect("Invalid path");
+        let path = s
+            .strip_prefix("files/")
+            .ok_or_else(|| AppError::ParseError(format!("invalid file url {}", s)))?;
         let (reaminder, ext) = match path.split_once('.') {
             Some((reaminder, ext)) => (reaminder, Some(ext.to_string())),
             None => (path, None),
@@ -69,7 +87,12 @@ impl FromStr for ChatFile {
         let ws_id = parts[0]
             .parse::<i64>()
             .map_err(|_e| AppError::CreateFileError("Invalid workspace id".to_string()))?;
+        // a sha1 hex digest split as 3/3/34, like `hash_to_path` lays it out
+        let lens = parts[1..].iter().map(|part| part.len()).collect::<Vec<_>>();
         let hash = parts[1..].join("");
+        if lens != [3, 3, 34] || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(AppError::ParseError(format!("invalid file url {}", s)));
+        }
         Ok(Self { ext, ws_id, hash })
     }
 }
@@ -92,6 +115,21 @@ mod tests {
             file.hash_to_path(1),
             "files/1/d34/86a/e9136e7856bc42212385ea797094475802.txt"
         );
+        let parsed = ChatFile::from_str(&file.hash_to_path(1))?;
+        assert_eq!(parsed.hash, file.hash);
         Ok(())
     }
+
+    #[test]
+    fn test_invalid_file_urls() {
+        for url in [
+            "files/1/ab.png",
+            "files/1/d34/86a.txt",
+            "files/1/d34/86a/e9136e7856bc42212385ea79709447580.txt",
+            "files/1/d34/86a/x9136e7856bc42212385ea797094475802.txt",
+            "files/1/d34/86a/e9/36e7856bc42212385ea797094475802.txt",
+        ] {
+            assert!(ChatFile::from_str(url).is_err(), "{}", url);
+        }
+    }
 }
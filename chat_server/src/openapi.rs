@@ -6,8 +6,11 @@ use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
 use chat_core::models::{
-    Chat, CreateChat, CreateMessage, CreateUser, CreateWorkspace, ListMessages, Messages,
-    SigninUser, User, Workspace,
+    Chat, CreateChat, CreateMessage, CreateUser, CreateWorkspace, CreateWorkspaceInvite,
+    DeactivateUser, JoinPolicy, JoinWorkspace, ListMessages, ListUsers, Messages,
+    NewWorkspaceInvite, Profile, SigninLockout, SigninUser, UpdateJoinPolicy, UpdateMemberRole,
+    UpdateProfile, UpdateWorkspace, UpdateWorkspaceSettings, User, UserSort, Workspace,
+    WorkspaceInvite, WorkspaceMember, WorkspaceRole, WorkspaceSettings,
 };
 
 use crate::handlers::*;
@@ -17,7 +20,13 @@ use crate::ChatState;
 #[openapi(
     modifiers(&SecurityAddon),
     paths(
-        list_users_handler, list_workspace_handler, create_workspace_handler, list_messages_handler
+        list_users_handler, get_me_handler, update_me_handler, get_user_handler, list_workspace_handler, create_workspace_handler, update_workspace_handler,
+        delete_workspace_handler, get_settings_handler, update_settings_handler,
+        list_lockouts_handler,
+        list_invites_handler, create_invite_handler, revoke_invite_handler,
+        update_join_policy_handler, list_members_handler, update_member_role_handler,
+        deactivate_member_handler,
+        join_workspace_handler, switch_workspace_handler, list_messages_handler
     ),
     components(schemas(
         Chat,
@@ -25,11 +34,29 @@ use crate::ChatState;
         CreateMessage,
         Messages,
         ListMessages,
+        ListUsers,
+        UserSort,
         CreateUser,
         SigninUser,
         User,
+        Profile,
+        UpdateProfile,
         Workspace,
-        CreateWorkspace
+        CreateWorkspace,
+        UpdateWorkspace,
+        WorkspaceSettings,
+        UpdateWorkspaceSettings,
+        JoinPolicy,
+        UpdateJoinPolicy,
+        WorkspaceInvite,
+        CreateWorkspaceInvite,
+        NewWorkspaceInvite,
+        WorkspaceRole,
+        WorkspaceMember,
+        UpdateMemberRole,
+        DeactivateUser,
+        JoinWorkspace,
+        SigninLockout
     )),
     tags(
         (name = "ChatServer", description = "ChatServer API")
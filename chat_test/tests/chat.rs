@@ -149,7 +149,7 @@ impl NotifyServer {
     async fn new(db_url: &str, token: &str) -> anyhow::Result<Self> {
         let mut config = notify_server::config::AppConfig::load()?;
         config.db_url = db_url.to_string();
-        let state = notify_server::NotifState::new(config);
+        let state = notify_server::NotifState::new(config).await;
 
         let app = notify_server::get_router(state).await?;
         let listener = TcpListener::bind(WILD_ADDR).await?;
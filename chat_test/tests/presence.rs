@@ -0,0 +1,212 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use futures::StreamExt;
+use reqwest_eventsource::{Event, EventSource};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+
+// alice and bob share chats in bbc (workspace 1), doe is in fox (workspace 3)
+const ALICE: &str = "alice@bbc.com";
+const BOB: &str = "bob@bbc.com";
+const WILD_ADDR: &str = "0.0.0.0:0";
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    token: String,
+}
+
+#[tokio::test]
+async fn test_presence_follows_event_streams_and_heartbeats() -> anyhow::Result<()> {
+    let (state, tdb) = chat_server::ChatState::new_for_test().await;
+    let chat_addr = start_chat_server(state).await?;
+    let notify_addr = start_notify_server(&tdb.url()).await?;
+    let client = reqwest::Client::new();
+    let alice = sign_in(&client, chat_addr, ALICE).await?;
+    let bob = sign_in(&client, chat_addr, BOB).await?;
+
+    let (mut bob_events, _bob_stream) = open_events(notify_addr, &bob).await?;
+    let (_, alice_stream) = open_events(notify_addr, &alice).await?;
+    let event = next_presence(&mut bob_events).await?;
+    assert_eq!(event["user_id"], 2);
+    assert_eq!(event["status"], "online");
+
+    // doe(5) is not in bbc and is left out
+    let res = client
+        .get(format!("http://{}/presence?ids=2,4,5", notify_addr))
+        .bearer_auth(&bob)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let presences: Vec<Value> = res.json().await?;
+    assert_eq!(presences.len(), 2);
+    assert_eq!(presences[0]["status"], "online");
+    assert_eq!(presences[1]["status"], "offline");
+    assert_eq!(presences[1]["last_seen_at"], Value::Null);
+
+    let res = client
+        .post(format!("http://{}/presence/heartbeat", notify_addr))
+        .bearer_auth(&alice)
+        .json(&json!({"status": "away"}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let event = next_presence(&mut bob_events).await?;
+    assert_eq!(event["status"], "away");
+
+    alice_stream.abort();
+    let event = next_presence(&mut bob_events).await?;
+    assert_eq!(event["user_id"], 2);
+    assert_eq!(event["status"], "offline");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_api_keys_need_the_events_scope() -> anyhow::Result<()> {
+    let (state, tdb) = chat_server::ChatState::new_for_test().await;
+    let chat_addr = start_chat_server(state).await?;
+    let notify_addr = start_notify_server(&tdb.url()).await?;
+    let client = reqwest::Client::new();
+    let alice = sign_in(&client, chat_addr, ALICE).await?;
+
+    for (scopes, status) in [
+        (vec!["chats:read"], StatusCode::FORBIDDEN),
+        (vec!["events:read"], StatusCode::OK),
+    ] {
+        let res = client
+            .post(format!("http://{}/api/tokens", chat_addr))
+            .bearer_auth(&alice)
+            .json(&json!({"name": "bot", "scopes": scopes}))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let api_key: Token = res.json().await?;
+
+        let res = client
+            .get(format!("http://{}/presence?ids=3", notify_addr))
+            .bearer_auth(&api_key.token)
+            .send()
+            .await?;
+        assert_eq!(res.status(), status);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_archiving_the_workspace_closes_its_event_streams() -> anyhow::Result<()> {
+    let (state, tdb) = chat_server::ChatState::new_for_test().await;
+    chat_core::Workspace::update_owner(1, 2, &tdb.get_pool().await).await?;
+    let chat_addr = start_chat_server(state).await?;
+    let notify_addr = start_notify_server(&tdb.url()).await?;
+    let client = reqwest::Client::new();
+    let alice = sign_in(&client, chat_addr, ALICE).await?;
+
+    // api keys have no session to revoke, only the archive closes their stream
+    let res = client
+        .post(format!("http://{}/api/tokens", chat_addr))
+        .bearer_auth(&alice)
+        .json(&json!({"name": "bot", "scopes": ["events:read"]}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let api_key: Token = res.json().await?;
+    let (mut events, _stream) = open_events(notify_addr, &api_key.token).await?;
+
+    let res = client
+        .delete(format!("http://{}/api/workspaces/1", chat_addr))
+        .bearer_auth(&alice)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    while tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await?
+        .is_some()
+    {}
+
+    Ok(())
+}
+
+async fn start_chat_server(state: chat_server::ChatState) -> anyhow::Result<SocketAddr> {
+    let app = chat_server::get_router(state).await;
+    let listener = TcpListener::bind(WILD_ADDR).await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    Ok(addr)
+}
+
+async fn start_notify_server(db_url: &str) -> anyhow::Result<SocketAddr> {
+    let mut config = notify_server::config::AppConfig::load()?;
+    config.db_url = db_url.to_string();
+    let state = notify_server::NotifState::new(config).await;
+
+    let app = notify_server::get_router(state).await?;
+    let listener = TcpListener::bind(WILD_ADDR).await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    Ok(addr)
+}
+
+async fn sign_in(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    email: &str,
+) -> anyhow::Result<String> {
+    let res = client
+        .post(format!("http://{}/api/signin", addr))
+        .json(&json!({"email": email, "password": "123456"}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let token: Token = res.json().await?;
+    Ok(token.token)
+}
+
+/// events of the stream opened with the token, aborting the task closes the stream
+async fn open_events(
+    addr: SocketAddr,
+    token: &str,
+) -> anyhow::Result<(UnboundedReceiver<Value>, JoinHandle<()>)> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let (opened_tx, opened_rx) = tokio::sync::oneshot::channel();
+    let mut es = EventSource::get(format!("http://{}/events?access_token={}", addr, token));
+    let stream = tokio::spawn(async move {
+        let mut opened_tx = Some(opened_tx);
+        while let Some(event) = es.next().await {
+            match event {
+                Ok(Event::Open) => {
+                    if let Some(opened) = opened_tx.take() {
+                        let _ = opened.send(());
+                    }
+                }
+                Ok(Event::Message(msg)) => {
+                    let _ = tx.send(serde_json::from_str(&msg.data).expect("invalid event"));
+                }
+                Err(_) => es.close(),
+            }
+        }
+    });
+    opened_rx.await?;
+
+    Ok((rx, stream))
+}
+
+async fn next_presence(events: &mut UnboundedReceiver<Value>) -> anyhow::Result<Value> {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await?
+            .expect("event stream closed");
+        if event["chat_event"] == "PresenceChanged" {
+            return Ok(event);
+        }
+    }
+}
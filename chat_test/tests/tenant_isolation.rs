@@ -0,0 +1,250 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use futures::StreamExt;
+use reqwest::{Method, RequestBuilder};
+use reqwest_eventsource::{Event, EventSource};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use chat_core::{Chat, User, Workspace};
+
+// alice is in bbc (workspace 1), doe in fox (workspace 3)
+const ALICE: &str = "alice@bbc.com";
+const DOE: &str = "doe@fox.com";
+const WILD_ADDR: &str = "0.0.0.0:0";
+
+struct ChatServer {
+    addr: SocketAddr,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    token: String,
+}
+
+#[tokio::test]
+async fn test_workspace_3_cannot_see_workspace_1() -> anyhow::Result<()> {
+    let (state, _tdb) = chat_server::ChatState::new_for_test().await;
+    let server = ChatServer::new(state).await?;
+    let doe = server.sign_in(DOE).await?;
+
+    let res = server
+        .request(Method::GET, "/api/workspaces", &doe)
+        .send()
+        .await?;
+    let workspaces: Vec<Workspace> = res.json().await?;
+    let names: Vec<_> = workspaces.iter().map(|ws| ws.name.as_str()).collect();
+    assert_eq!(names, ["fox"]);
+
+    let res = server
+        .request(Method::GET, "/api/users", &doe)
+        .send()
+        .await?;
+    let users: Vec<User> = res.json().await?;
+    assert!(users.iter().all(|u| u.email.ends_with("@fox.com")));
+
+    let res = server
+        .request(Method::GET, "/api/chat", &doe)
+        .send()
+        .await?;
+    let chats: Vec<Chat> = res.json().await?;
+    assert!(chats.iter().all(|chat| chat.ws_id == 3));
+
+    // doe is listed in the members of general_ch(3), which lives in workspace 1
+    let res = server
+        .request(Method::GET, "/api/chat/3/messages?limit=10", &doe)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workspace_3_cannot_touch_workspace_1() -> anyhow::Result<()> {
+    let (state, _tdb) = chat_server::ChatState::new_for_test().await;
+    let server = ChatServer::new(state).await?;
+    let doe = server.sign_in(DOE).await?;
+
+    let res = server
+        .request(Method::POST, "/api/chat/3", &doe)
+        .json(&json!({"content": "hello", "file": []}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = server
+        .request(Method::PATCH, "/api/chat/1", &doe)
+        .json(&json!({"new_owner_id": 5}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = server
+        .request(Method::DELETE, "/api/chat/1", &doe)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // alice(2) is not a member of workspace 3
+    let res = server
+        .request(Method::POST, "/api/chat", &doe)
+        .json(&json!({"members": [5, 2]}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let err: String = res.json().await?;
+    assert!(err.contains("cross-workspace"), "{}", err);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workspace_3_cannot_use_files_of_workspace_1() -> anyhow::Result<()> {
+    let (state, _tdb) = chat_server::ChatState::new_for_test().await;
+    let server = ChatServer::new(state).await?;
+    let alice = server.sign_in(ALICE).await?;
+    let doe = server.sign_in(DOE).await?;
+
+    let part = reqwest::multipart::Part::bytes(b"bbc only".to_vec())
+        .file_name("secret.txt")
+        .mime_str("text/plain")?;
+    let res = server
+        .request(Method::POST, "/api/files", &alice)
+        .multipart(reqwest::multipart::Form::new().part("file", part))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let files: Vec<String> = res.json().await?;
+
+    let res = server
+        .request(Method::GET, &format!("/api/download/{}", files[0]), &doe)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let chat = server.create_chat(&doe, json!({"members": [5, 6]})).await?;
+    let res = server
+        .request(Method::POST, &format!("/api/chat/{}", chat.id), &doe)
+        .json(&json!({"content": "look", "file": files}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workspace_3_receives_no_events_of_workspace_1() -> anyhow::Result<()> {
+    let (state, tdb) = chat_server::ChatState::new_for_test().await;
+    let server = ChatServer::new(state).await?;
+    let alice = server.sign_in(ALICE).await?;
+    let doe = server.sign_in(DOE).await?;
+    let mut events = notify_events(&tdb.url(), &doe).await?;
+
+    // general_ch(3) of workspace 1 still lists doe as a member
+    let res = server
+        .request(Method::POST, "/api/chat/3", &alice)
+        .json(&json!({"content": "bbc news", "file": []}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let chat = server.create_chat(&doe, json!({"members": [5, 6]})).await?;
+
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await?
+        .expect("event stream closed");
+    assert_eq!(event["chat_event"], "NewChat");
+    assert_eq!(event["id"], chat.id);
+    assert_eq!(event["ws_id"], 3);
+
+    Ok(())
+}
+
+impl ChatServer {
+    async fn new(state: chat_server::ChatState) -> anyhow::Result<Self> {
+        let app = chat_server::get_router(state).await;
+        let listener = TcpListener::bind(WILD_ADDR).await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        Ok(Self {
+            addr,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str, token: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("http://{}{}", self.addr, path))
+            .header("Authorization", format!("Bearer {}", token))
+    }
+
+    async fn sign_in(&self, email: &str) -> anyhow::Result<String> {
+        let res = self
+            .client
+            .post(format!("http://{}/api/signin", self.addr))
+            .json(&json!({"email": email, "password": "123456"}))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let token: Token = res.json().await?;
+        Ok(token.token)
+    }
+
+    async fn create_chat(&self, token: &str, chat: Value) -> anyhow::Result<Chat> {
+        let res = self
+            .request(Method::POST, "/api/chat", token)
+            .json(&chat)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        Ok(res.json().await?)
+    }
+}
+
+/// events received by the notify server stream of the token
+async fn notify_events(
+    db_url: &str,
+    token: &str,
+) -> anyhow::Result<tokio::sync::mpsc::UnboundedReceiver<Value>> {
+    let mut config = notify_server::config::AppConfig::load()?;
+    config.db_url = db_url.to_string();
+    let state = notify_server::NotifState::new(config).await;
+
+    let app = notify_server::get_router(state).await?;
+    let listener = TcpListener::bind(WILD_ADDR).await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let (opened_tx, opened_rx) = tokio::sync::oneshot::channel();
+    let mut es = EventSource::get(format!("http://{}/events?access_token={}", addr, token));
+    tokio::spawn(async move {
+        let mut opened_tx = Some(opened_tx);
+        while let Some(event) = es.next().await {
+            match event {
+                Ok(Event::Open) => {
+                    if let Some(opened) = opened_tx.take() {
+                        let _ = opened.send(());
+                    }
+                }
+                Ok(Event::Message(msg)) => {
+                    let _ = tx.send(serde_json::from_str(&msg.data).expect("invalid event"));
+                }
+                Err(_) => es.close(),
+            }
+        }
+    });
+    opened_rx.await?;
+
+    Ok(rx)
+}
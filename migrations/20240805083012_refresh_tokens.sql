@@ -0,0 +1,20 @@
+-- Add migration script here
+
+-- create refresh tokens table, only the sha256 hash of the token is stored
+CREATE TABLE IF NOT EXISTS refresh_tokens(
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    token_hash char(64) NOT NULL UNIQUE,
+    expires_at timestamptz NOT NULL,
+    revoked_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user_id ON refresh_tokens(user_id);
+
+-- create revoked access tokens table (jti denylist)
+CREATE TABLE IF NOT EXISTS revoked_tokens(
+    jti varchar(64) PRIMARY KEY,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- create password reset tokens table, tokens are single-use and only stored hashed
+CREATE TABLE IF NOT EXISTS password_reset_tokens(
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    token_hash char(64) NOT NULL UNIQUE,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_password_reset_tokens_user_id ON password_reset_tokens(user_id);
@@ -0,0 +1,4 @@
+-- Add migration script here
+
+-- bumped whenever the password changes, tokens issued for an older generation are rejected
+ALTER TABLE users ADD COLUMN IF NOT EXISTS token_generation integer NOT NULL DEFAULT 0;
@@ -0,0 +1,25 @@
+-- Add migration script here
+
+-- failed sign-in attempts per account (email) and per client ip
+CREATE TABLE IF NOT EXISTS signin_throttles(
+    scope varchar(16) NOT NULL,
+    key varchar(255) NOT NULL,
+    failures integer NOT NULL DEFAULT 0,
+    blocked_until timestamptz,
+    locked boolean NOT NULL DEFAULT FALSE,
+    last_failure_at timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (scope, key)
+);
+
+-- every lockout is recorded so credential stuffing can be spotted
+CREATE TABLE IF NOT EXISTS signin_lockouts(
+    id bigserial PRIMARY KEY,
+    scope varchar(16) NOT NULL,
+    key varchar(255) NOT NULL,
+    user_id bigint REFERENCES users(id),
+    failures integer NOT NULL,
+    locked_until timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_signin_lockouts_created_at ON signin_lockouts(created_at DESC);
@@ -0,0 +1,31 @@
+-- Add migration script here
+
+-- totp secret of a user, enabled once the first code is confirmed
+CREATE TABLE IF NOT EXISTS user_totp(
+    user_id bigint PRIMARY KEY REFERENCES users(id),
+    secret varchar(64) NOT NULL,
+    enabled_at timestamptz,
+    last_used_step bigint NOT NULL DEFAULT 0,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+-- single-use recovery codes, only the sha256 hash is stored
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes(
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    code_hash char(64) NOT NULL UNIQUE,
+    used_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+-- issued after the password was verified, exchanged for a real token with a valid code
+CREATE TABLE IF NOT EXISTS mfa_challenges(
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    token_hash char(64) NOT NULL UNIQUE,
+    attempts integer NOT NULL DEFAULT 0,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_mfa_recovery_codes_user_id ON mfa_recovery_codes(user_id);
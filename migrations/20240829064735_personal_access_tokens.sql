@@ -0,0 +1,16 @@
+-- Add migration script here
+
+-- long-lived api keys for scripts and bots, only the sha256 hash is stored
+CREATE TABLE IF NOT EXISTS personal_access_tokens(
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    name varchar(64) NOT NULL,
+    token_hash char(64) NOT NULL UNIQUE,
+    scopes varchar(32)[] NOT NULL DEFAULT '{}',
+    expires_at timestamptz,
+    last_used_at timestamptz,
+    revoked_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_personal_access_tokens_user_id ON personal_access_tokens(user_id);
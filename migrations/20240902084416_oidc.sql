@@ -0,0 +1,24 @@
+-- Add migration script here
+
+-- users provisioned through an identity provider have no password
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+
+-- pending authorization requests, consumed by the callback
+CREATE TABLE IF NOT EXISTS oidc_logins(
+    id bigserial PRIMARY KEY,
+    state_hash char(64) NOT NULL UNIQUE,
+    nonce varchar(64) NOT NULL,
+    code_verifier varchar(128) NOT NULL,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+-- external identities (issuer + subject) linked to users
+CREATE TABLE IF NOT EXISTS user_identities(
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    issuer varchar(255) NOT NULL,
+    subject varchar(255) NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    UNIQUE (issuer, subject)
+);
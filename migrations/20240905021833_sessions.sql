@@ -0,0 +1,36 @@
+-- Add migration script here
+
+-- a signed-in device, every refresh and access token belongs to one
+CREATE TABLE IF NOT EXISTS sessions(
+    id bigserial PRIMARY KEY,
+    user_id bigint NOT NULL REFERENCES users(id),
+    device_name varchar(128),
+    user_agent text,
+    ip varchar(64),
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    last_seen_at timestamptz NOT NULL DEFAULT NOW(),
+    revoked_at timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
+
+ALTER TABLE refresh_tokens ADD COLUMN session_id bigint REFERENCES sessions(id);
+
+-- if a session is revoked, notify so its event stream can be closed
+CREATE OR REPLACE FUNCTION revoke_session() RETURNS TRIGGER AS $$
+BEGIN
+    IF OLD.revoked_at IS NULL AND NEW.revoked_at IS NOT NULL THEN
+        PERFORM pg_notify('session_revoked', json_build_object(
+            'user_id', NEW.user_id,
+            'session_id', NEW.id
+        )::text);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER revoke_session_trigger
+    AFTER UPDATE
+    ON sessions
+    FOR EACH ROW
+    EXECUTE PROCEDURE revoke_session();
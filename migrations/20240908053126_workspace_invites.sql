@@ -0,0 +1,24 @@
+-- Add migration script here
+
+-- how new users may join an existing workspace at signup
+CREATE TYPE join_policy AS ENUM('invite_only', 'allowed_domains', 'open');
+
+ALTER TABLE workspaces
+    ADD COLUMN join_policy join_policy NOT NULL DEFAULT 'invite_only',
+    ADD COLUMN allowed_domains text[] NOT NULL DEFAULT '{}';
+
+-- invitation codes, only the sha256 hash of the code is stored
+CREATE TABLE IF NOT EXISTS workspace_invites(
+    id bigserial PRIMARY KEY,
+    ws_id bigint NOT NULL REFERENCES workspaces(id),
+    created_by bigint NOT NULL REFERENCES users(id),
+    code_hash char(64) NOT NULL UNIQUE,
+    -- NULL for codes that can be used any number of times
+    max_uses integer,
+    uses integer NOT NULL DEFAULT 0,
+    expires_at timestamptz NOT NULL,
+    revoked_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_workspace_invites_ws_id ON workspace_invites(ws_id);
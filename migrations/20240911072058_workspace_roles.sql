@@ -0,0 +1,17 @@
+-- Add migration script here
+
+CREATE TYPE workspace_role AS ENUM('owner', 'admin', 'member', 'guest');
+
+-- the role a user has in a workspace
+CREATE TABLE IF NOT EXISTS workspace_members(
+    ws_id bigint NOT NULL REFERENCES workspaces(id),
+    user_id bigint NOT NULL REFERENCES users(id),
+    role workspace_role NOT NULL DEFAULT 'member',
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (ws_id, user_id)
+);
+
+INSERT INTO workspace_members(ws_id, user_id, role)
+SELECT u.ws_id, u.id, CASE WHEN w.owner_id = u.id THEN 'owner' ELSE 'member' END::workspace_role
+FROM users u
+JOIN workspaces w ON w.id = u.ws_id;
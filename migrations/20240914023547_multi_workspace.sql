@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- users may belong to several workspaces through workspace_members,
+-- users.ws_id is the default workspace tokens are scoped to at sign-in
+CREATE INDEX IF NOT EXISTS idx_workspace_members_user_id ON workspace_members(user_id);
+
+-- the workspace a session switched to, NULL for the default workspace of the user
+ALTER TABLE sessions ADD COLUMN ws_id bigint REFERENCES workspaces(id);
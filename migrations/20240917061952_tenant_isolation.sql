@@ -0,0 +1,19 @@
+-- Add migration script here
+
+-- message notifications carry the workspace of the chat,
+-- event streams only forward events of the workspace their token is scoped to
+CREATE OR REPLACE FUNCTION add_to_messages() RETURNS TRIGGER AS $$
+DECLARE
+users bigint[];
+workspace bigint;
+BEGIN
+    RAISE NOTICE 'add_to_messages:%', NEW;
+    SELECT members, ws_id INTO users, workspace FROM chats WHERE id = NEW.chat_id;
+    PERFORM pg_notify('messages_create', json_build_object(
+        'messages', NEW,
+        'ws_id', workspace,
+        'users', users
+    )::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
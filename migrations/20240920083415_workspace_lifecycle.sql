@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- deleted workspaces are archived: the row stays for the users and sessions still pointing at it,
+-- while its chats, messages, invitations and memberships are removed
+ALTER TABLE workspaces ADD COLUMN archived_at timestamptz;
+
+-- the name of an archived workspace can be taken again
+ALTER TABLE workspaces DROP CONSTRAINT IF EXISTS workspaces_name_key;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_workspaces_name ON workspaces(name) WHERE archived_at IS NULL;
@@ -0,0 +1,17 @@
+-- Add migration script here
+
+-- limits and policies of a workspace, rows are created with the defaults on first read
+CREATE TABLE IF NOT EXISTS workspace_settings(
+    ws_id bigint PRIMARY KEY REFERENCES workspaces(id),
+    -- chats with more members are channels, which need a name
+    max_group_size int NOT NULL DEFAULT 8,
+    -- in bytes, per uploaded file
+    max_upload_size bigint NOT NULL DEFAULT 2097152,
+    -- lowercase extensions without the dot, empty to allow any
+    allowed_extensions text[] NOT NULL DEFAULT '{}',
+    -- messages older than this are deleted, NULL to keep them forever
+    message_retention_days int,
+    -- lowest role allowed to create public channels
+    public_channel_role workspace_role NOT NULL DEFAULT 'member',
+    updated_at timestamptz NOT NULL DEFAULT NOW()
+);
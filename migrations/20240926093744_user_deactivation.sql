@@ -0,0 +1,23 @@
+-- Add migration script here
+
+-- deactivated accounts can't sign in or use tokens, their name and email are replaced
+-- so their messages read as sent by a deactivated user
+ALTER TABLE users ADD COLUMN deactivated_at timestamptz;
+
+-- if a user is deactivated, notify so their event streams can be closed
+CREATE OR REPLACE FUNCTION deactivate_user() RETURNS TRIGGER AS $$
+BEGIN
+    IF OLD.deactivated_at IS NULL AND NEW.deactivated_at IS NOT NULL THEN
+        PERFORM pg_notify('user_deactivated', json_build_object(
+            'user_id', NEW.id
+        )::text);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER deactivate_user_trigger
+    AFTER UPDATE
+    ON users
+    FOR EACH ROW
+    EXECUTE PROCEDURE deactivate_user();
@@ -0,0 +1,29 @@
+-- Add migration script here
+
+-- what users tell about themselves, the avatar is the url of an uploaded file
+ALTER TABLE users
+    ADD COLUMN display_name varchar(64),
+    ADD COLUMN title varchar(128),
+    ADD COLUMN timezone varchar(64),
+    ADD COLUMN pronoun varchar(32),
+    ADD COLUMN avatar text;
+
+-- if a profile changes, notify so the members of the user's workspaces can be told
+CREATE OR REPLACE FUNCTION update_user_profile() RETURNS TRIGGER AS $$
+BEGIN
+    IF (OLD.fullname, OLD.display_name, OLD.title, OLD.timezone, OLD.pronoun, OLD.avatar)
+        IS DISTINCT FROM
+        (NEW.fullname, NEW.display_name, NEW.title, NEW.timezone, NEW.pronoun, NEW.avatar) THEN
+        PERFORM pg_notify('user_updated', json_build_object(
+            'user_id', NEW.id
+        )::text);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER update_user_profile_trigger
+    AFTER UPDATE
+    ON users
+    FOR EACH ROW
+    EXECUTE PROCEDURE update_user_profile();
@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- trigram indexes serve both the prefix (ILIKE) and the fuzzy (<%) directory searches
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_users_fullname_trgm ON users USING gin (fullname gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_email_trgm ON users USING gin (email gin_trgm_ops);
+
+-- keyset pagination of the directory sorted by name or email
+CREATE INDEX IF NOT EXISTS idx_users_fullname_id ON users(lower(fullname), id);
+CREATE INDEX IF NOT EXISTS idx_users_email_id ON users(lower(email), id);
+
+CREATE INDEX IF NOT EXISTS idx_workspace_members_ws_id_role ON workspace_members(ws_id, role);
+CREATE INDEX IF NOT EXISTS idx_workspace_members_ws_id_created_at ON workspace_members(ws_id, created_at, user_id);
@@ -0,0 +1,5 @@
+-- Add migration script here
+
+-- recovery codes are hashed with a random salt of their own, older codes keep a plain sha256
+ALTER TABLE mfa_recovery_codes
+    ADD COLUMN salt char(32);
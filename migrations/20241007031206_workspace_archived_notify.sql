@@ -0,0 +1,24 @@
+-- Add migration script here
+
+-- if a workspace is archived, notify its members so their event streams acting in it can be closed
+CREATE OR REPLACE FUNCTION archive_workspace() RETURNS TRIGGER AS $$
+BEGIN
+    IF OLD.archived_at IS NULL AND NEW.archived_at IS NOT NULL THEN
+        PERFORM pg_notify('workspace_archived', json_build_object(
+            'ws_id', NEW.id,
+            'users', (
+                SELECT COALESCE(array_agg(user_id), '{}')
+                FROM workspace_members
+                WHERE ws_id = NEW.id
+            )
+        )::text);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER archive_workspace_trigger
+    AFTER UPDATE
+    ON workspaces
+    FOR EACH ROW
+    EXECUTE PROCEDURE archive_workspace();
@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- the email of a deactivated account is replaced, its hash keeps the address reserved
+-- so nobody can sign up or be provisioned under it
+ALTER TABLE users ADD COLUMN deactivated_email_hash char(64);
+
+CREATE INDEX IF NOT EXISTS idx_users_deactivated_email_hash ON users(deactivated_email_hash)
+    WHERE deactivated_email_hash IS NOT NULL;
@@ -3,6 +3,9 @@ use std::fs::File;
 use anyhow::bail;
 use serde::{Deserialize, Serialize};
 
+use chat_core::error::ChatCoreError;
+use chat_core::utils::jwt::JwtVerifier;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
@@ -14,9 +17,38 @@ pub struct ServerConfig {
     pub port: u16,
 }
 
+/// notify_server only verifies tokens, so it should be configured with public keys only
 #[derive(Debug, Serialize, Deserialize)]
-pub struct AuthConfig {
-    pub sk: String,
+#[serde(untagged)]
+pub enum AuthConfig {
+    /// public key (PEM) of the chat_server signing key
+    PublicKey {
+        pk: String,
+        /// public keys (PEM) of rotated-out signing keys, still accepted for verification
+        #[serde(default)]
+        retired_pks: Vec<String>,
+    },
+    /// url or file path of the JWKS published by chat_server, reloaded to follow key rotations
+    Jwks { jwks: String },
+}
+
+impl AuthConfig {
+    pub async fn verifier(&self) -> Result<JwtVerifier, ChatCoreError> {
+        match self {
+            Self::PublicKey { pk, retired_pks } => {
+                JwtVerifier::from_pem(pk)?.with_retired_keys(retired_pks)
+            }
+            Self::Jwks { jwks } => JwtVerifier::load_jwks(jwks).await,
+        }
+    }
+
+    /// where the keys are reloaded from, None for keys fixed in config
+    pub fn jwks_source(&self) -> Option<&str> {
+        match self {
+            Self::Jwks { jwks } => Some(jwks),
+            Self::PublicKey { .. } => None,
+        }
+    }
 }
 
 impl AppConfig {
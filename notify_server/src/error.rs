@@ -10,12 +10,15 @@ pub enum NotifyError {
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
     #[error("{0}")]
+    ChatCoreError(#[from] chat_core::error::ChatCoreError),
+    #[error("{0}")]
     NotificationFault(String),
 }
 
 impl IntoResponse for NotifyError {
     fn into_response(self) -> Response {
         let status = match self {
+            Self::ChatCoreError(e) => return e.into_response(),
             Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::JwtError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::NotificationFault(_) => StatusCode::INTERNAL_SERVER_ERROR,
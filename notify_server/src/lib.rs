@@ -1,28 +1,43 @@
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-use axum::middleware::from_fn_with_state;
-use axum::response::{Html, IntoResponse};
-use axum::routing::get;
+use axum::extract::Request;
+use axum::http::{Method, StatusCode};
+use axum::middleware::{from_fn, from_fn_with_state, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
 use axum::Router;
 use dashmap::DashMap;
-use jwt_simple::prelude::ES256KeyPair;
+use sqlx::PgPool;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::Notify;
+use tracing::{info, warn};
 
 use chat_core::middlewares::jwt::{jwt_verify, JwtVerify};
-use chat_core::models::User;
-use chat_core::utils::jwt::JwtSigner;
+use chat_core::models::{AccessToken, PersonalAccessToken, User};
+use chat_core::utils::cache::UserCache;
+use chat_core::utils::jwt::JwtVerifier;
 
 use crate::config::AppConfig;
 use crate::notif::{setup_pglistener, ChatEvent};
+use crate::presence::{
+    heartbeat_handler, presence_handler, spawn_presence_announcer, spawn_presence_sweep,
+    PresenceTracker,
+};
 use crate::sse::sse_handler;
 
 pub mod config;
 mod error;
 pub mod notif;
+pub mod presence;
 mod sse;
 
 const INDEX_HTML: &str = include_str!("../index.html");
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// unknown key ids trigger a reload, but no more often than this
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 
 #[derive(Clone)]
 pub struct NotifState {
@@ -31,14 +46,26 @@ pub struct NotifState {
 
 pub struct NotifStateInner {
     pub config: AppConfig,
-    verifier: JwtSigner,
+    pool: PgPool,
+    verifier: RwLock<JwtVerifier>,
+    // wakes the JWKS refresh up early when a token is signed by an unknown key
+    jwks_refresh: Notify,
+    users: UserCache,
     users_map: DashMap<i64, Sender<Arc<ChatEvent>>>,
+    presence: PresenceTracker,
+    // users whose presence changed, announced in order by a single task
+    presence_changes: UnboundedSender<i64>,
 }
 
 pub async fn get_router(state: NotifState) -> anyhow::Result<Router> {
     setup_pglistener(state.clone()).await?;
+    spawn_presence_sweep(state.clone());
+    spawn_jwks_refresh(state.clone());
     let router = Router::new()
         .route("/events", get(sse_handler))
+        .route("/presence", get(presence_handler))
+        .route("/presence/heartbeat", post(heartbeat_handler))
+        .layer(from_fn(require_events_scope))
         .layer(from_fn_with_state(state.clone(), jwt_verify::<NotifState>))
         .route("/", get(index_handler))
         .with_state(state);
@@ -47,8 +74,26 @@ pub async fn get_router(state: NotifState) -> anyhow::Result<Router> {
 
 impl JwtVerify for NotifState {
     type Error = chat_core::error::ChatCoreError;
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        self.inner.verifier.verify(token)
+    fn verify(&self, token: &str) -> Result<AccessToken, Self::Error> {
+        let verifier = self.verifier.read().expect("jwt verifier lock poisoned");
+        if !verifier.knows_key(token) {
+            self.jwks_refresh.notify_one();
+        }
+        verifier.verify(token)
+    }
+
+    async fn verify_personal_token(&self, token: &str) -> Result<Option<AccessToken>, Self::Error> {
+        PersonalAccessToken::verify(token, &self.pool).await
+    }
+
+    async fn is_revoked(&self, access: &AccessToken) -> Result<bool, Self::Error> {
+        access.is_revoked(&self.pool).await
+    }
+
+    async fn resolve_user(&self, access: &AccessToken) -> Result<Option<User>, Self::Error> {
+        self.users
+            .get(access.user_id, access.ws_id, &self.pool)
+            .await
     }
 }
 
@@ -60,20 +105,75 @@ impl Deref for NotifState {
 }
 
 impl NotifState {
-    pub fn new(config: AppConfig) -> Self {
-        let verifier = JwtSigner::new(
-            ES256KeyPair::from_pem(&config.auth.sk).expect("Failed to create jwt verifier"),
-        );
-        Self {
+    pub async fn new(config: AppConfig) -> Self {
+        let verifier = config
+            .auth
+            .verifier()
+            .await
+            .expect("Failed to create jwt verifier");
+        let pool = PgPool::connect_lazy(&config.db_url).expect("Failed to create database pool");
+        let (presence_changes, changed) = mpsc::unbounded_channel();
+        let state = Self {
             inner: Arc::new(NotifStateInner {
                 config,
-                verifier,
+                pool,
+                verifier: RwLock::new(verifier),
+                jwks_refresh: Notify::new(),
+                users: UserCache::default(),
                 users_map: DashMap::new(),
+                presence: PresenceTracker::default(),
+                presence_changes,
             }),
-        }
+        };
+        spawn_presence_announcer(state.clone(), changed);
+        state
+    }
+}
+
+/// api keys need the `events` scope, `read` to listen and `write` to report presence
+async fn require_events_scope(req: Request, next: Next) -> Response {
+    let scope = match *req.method() {
+        Method::GET | Method::HEAD => "events:read",
+        _ => "events:write",
+    };
+    match req.extensions().get::<AccessToken>() {
+        Some(access) if access.has_scope(scope) => next.run(req).await,
+        Some(_) => (
+            StatusCode::FORBIDDEN,
+            format!("token lacks scope {}", scope),
+        )
+            .into_response(),
+        None => (StatusCode::UNAUTHORIZED, "token not found").into_response(),
     }
 }
 
+/// reload the JWKS periodically, and when tokens show up signed by a key not known yet
+fn spawn_jwks_refresh(state: NotifState) {
+    let Some(source) = state.config.auth.jwks_source().map(str::to_string) else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut last_refresh = Instant::now();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(JWKS_REFRESH_INTERVAL) => {}
+                _ = state.jwks_refresh.notified() => {
+                    let wait = JWKS_MIN_REFRESH_INTERVAL.saturating_sub(last_refresh.elapsed());
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            last_refresh = Instant::now();
+            match JwtVerifier::load_jwks(&source).await {
+                Ok(verifier) => {
+                    *state.verifier.write().expect("jwt verifier lock poisoned") = verifier;
+                    info!("reloaded jwks from {}", source);
+                }
+                Err(e) => warn!("reload jwks from {} error: {}", source, e),
+            }
+        }
+    });
+}
+
 async fn index_handler() -> impl IntoResponse {
     Html(INDEX_HTML)
 }
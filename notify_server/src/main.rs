@@ -17,7 +17,7 @@ async fn main() -> anyhow::Result<()> {
     let config = AppConfig::load().expect("Failed to load config");
     let addr = format!("0.0.0.0:{}", config.server.port);
 
-    let state = NotifState::new(config);
+    let state = NotifState::new(config).await;
 
     let listener = TcpListener::bind(&addr).await?;
     info!("Listening on: {}", addr);
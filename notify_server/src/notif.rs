@@ -4,12 +4,14 @@ use std::sync::Arc;
 use jwt_simple::reexports::serde_json;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
+use sqlx::PgPool;
 use tracing::{info, warn};
 
-use chat_core::{Chat, Messages};
+use chat_core::{Chat, Messages, Profile, User};
 
 use crate::error::NotifyError;
 use crate::error::NotifyError::NotificationFault;
+use crate::presence::Presence;
 use crate::NotifState;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -18,13 +20,83 @@ pub enum ChatEvent {
     NewChat(Chat),
     UpdateChat(Chat),
     DeleteChat(Chat),
-    NewMessage(Messages),
+    NewMessage(NewMessage),
+    SessionRevoked(SessionRevoked),
+    UserDeactivated(UserDeactivated),
+    UserUpdated(UserUpdated),
+    PresenceChanged(Presence),
+    WorkspaceArchived(WorkspaceArchived),
+}
+
+/// a message along with the workspace of its chat
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewMessage {
+    #[serde(flatten)]
+    pub message: Messages,
+    pub ws_id: i64,
+}
+
+impl ChatEvent {
+    /// the workspace the event happened in, None for account-wide events
+    pub fn ws_id(&self) -> Option<i64> {
+        match self {
+            ChatEvent::NewChat(chat)
+            | ChatEvent::UpdateChat(chat)
+            | ChatEvent::DeleteChat(chat) => Some(chat.ws_id),
+            ChatEvent::NewMessage(message) => Some(message.ws_id),
+            ChatEvent::WorkspaceArchived(archived) => Some(archived.ws_id),
+            ChatEvent::SessionRevoked(_)
+            | ChatEvent::UserDeactivated(_)
+            | ChatEvent::UserUpdated(_)
+            | ChatEvent::PresenceChanged(_) => None,
+        }
+    }
+}
+
+/// a signed-out session, the event streams opened with its tokens are closed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRevoked {
+    pub user_id: i64,
+    pub session_id: i64,
+}
+
+/// an offboarded user, all their event streams are closed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserDeactivated {
+    pub user_id: i64,
+}
+
+/// a changed profile, sent to everyone sharing a workspace with the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserUpdated {
+    pub id: i64,
+    pub fullname: String,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+/// an archived workspace, the event streams acting in it are closed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceArchived {
+    pub ws_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UserChanged {
+    user_id: i64,
 }
 
 pub async fn setup_pglistener(state: NotifState) -> Result<(), NotifyError> {
     let mut listener = PgListener::connect(&state.config.db_url).await?;
     listener
-        .listen_all(["chat_update", "messages_create"])
+        .listen_all([
+            "chat_update",
+            "messages_create",
+            "session_revoked",
+            "user_deactivated",
+            "user_updated",
+            "workspace_archived",
+        ])
         .await?;
 
     tokio::spawn(async move {
@@ -32,7 +104,20 @@ pub async fn setup_pglistener(state: NotifState) -> Result<(), NotifyError> {
             match listener.recv().await {
                 Ok(notif) => {
                     info!("received notification: {:?}", notif);
-                    let decoded = Notification::decode(notif.channel(), notif.payload())?;
+                    let decoded = match notif.channel() {
+                        // the profile and the recipients are not in the payload
+                        "user_updated" => {
+                            match Notification::user_updated(notif.payload(), &state.pool).await {
+                                Ok(Some(decoded)) => decoded,
+                                Ok(None) => continue,
+                                Err(err) => {
+                                    warn!("load user update error: {}", err);
+                                    continue;
+                                }
+                            }
+                        }
+                        channel => Notification::decode(channel, notif.payload())?,
+                    };
                     for u in decoded.users {
                         if let Some(tx) = state.users_map.get(&u) {
                             if let Err(e) = tx.send(decoded.event.clone()) {
@@ -40,6 +125,9 @@ pub async fn setup_pglistener(state: NotifState) -> Result<(), NotifyError> {
                             }
                         }
                     }
+                    if let ChatEvent::UserDeactivated(deactivated) = decoded.event.as_ref() {
+                        state.users_map.remove(&deactivated.user_id);
+                    }
                 }
                 Err(err) => {
                     warn!("receive notification error: {}", err);
@@ -88,10 +176,39 @@ impl Notification {
                 let message: MessageCreate =
                     serde_json::from_str(payload).expect("Invalid message");
                 Ok(Self {
-                    event: Arc::new(ChatEvent::NewMessage(message.messages)),
+                    event: Arc::new(ChatEvent::NewMessage(NewMessage {
+                        message: message.messages,
+                        ws_id: message.ws_id,
+                    })),
                     users: message.users.iter().copied().collect(),
                 })
             }
+            "session_revoked" => {
+                let revoked: SessionRevoked =
+                    serde_json::from_str(payload).expect("Invalid session revoked");
+                Ok(Self {
+                    users: HashSet::from([revoked.user_id]),
+                    event: Arc::new(ChatEvent::SessionRevoked(revoked)),
+                })
+            }
+            "user_deactivated" => {
+                let deactivated: UserDeactivated =
+                    serde_json::from_str(payload).expect("Invalid user deactivated");
+                Ok(Self {
+                    users: HashSet::from([deactivated.user_id]),
+                    event: Arc::new(ChatEvent::UserDeactivated(deactivated)),
+                })
+            }
+            "workspace_archived" => {
+                let archived: ArchiveWorkspace =
+                    serde_json::from_str(payload).expect("Invalid workspace archived");
+                Ok(Self {
+                    event: Arc::new(ChatEvent::WorkspaceArchived(WorkspaceArchived {
+                        ws_id: archived.ws_id,
+                    })),
+                    users: archived.users.into_iter().collect(),
+                })
+            }
             _ => {
                 warn!("unknown channel: {}", channel);
                 Err(NotificationFault("unknown channel".to_string()))
@@ -99,6 +216,22 @@ impl Notification {
         }
     }
 
+    pub async fn user_updated(payload: &str, pool: &PgPool) -> Result<Option<Self>, NotifyError> {
+        let changed: UserChanged = serde_json::from_str(payload).expect("Invalid user updated");
+        let Some(user) = User::find_user_by_id(changed.user_id, pool).await? else {
+            return Ok(None);
+        };
+        let users = User::coworker_ids(user.id, pool).await?;
+        Ok(Some(Self {
+            event: Arc::new(ChatEvent::UserUpdated(UserUpdated {
+                id: user.id,
+                fullname: user.fullname,
+                profile: user.profile,
+            })),
+            users: users.into_iter().collect(),
+        }))
+    }
+
     fn get_notified_users(old: &Option<Chat>, new: &Option<Chat>) -> HashSet<i64> {
         match (old, new) {
             (Some(old), Some(new)) => {
@@ -129,5 +262,12 @@ pub struct ChatUpdate {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MessageCreate {
     pub messages: Messages,
+    pub ws_id: i64,
+    pub users: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveWorkspace {
+    pub ws_id: i64,
     pub users: Vec<i64>,
 }
@@ -0,0 +1,263 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{info, warn};
+
+use chat_core::error::ChatCoreError;
+use chat_core::{Chat, User};
+
+use crate::error::NotifyError;
+use crate::notif::ChatEvent;
+use crate::NotifState;
+
+/// online users without a heartbeat for that long are shown as away
+const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_PRESENCE_IDS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+/// presence of a user, last_seen_at is unknown for users not seen since the server started
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct PresenceEntry {
+    streams: usize,
+    status: PresenceStatus,
+    last_seen_at: DateTime<Utc>,
+}
+
+/// presence of the users, kept from their event streams and heartbeats
+#[derive(Debug, Default)]
+pub struct PresenceTracker {
+    users: DashMap<i64, PresenceEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresenceQuery {
+    /// comma separated user ids
+    ids: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Heartbeat {
+    status: PresenceStatus,
+}
+
+/// marks the user online while an event stream is open, offline once the last one closes
+pub(crate) struct PresenceGuard {
+    state: NotifState,
+    user_id: i64,
+}
+
+impl PresenceTracker {
+    pub fn get(&self, user_id: i64) -> Presence {
+        match self.users.get(&user_id) {
+            Some(entry) => Presence {
+                user_id,
+                status: entry.status,
+                last_seen_at: Some(entry.last_seen_at),
+            },
+            None => Presence {
+                user_id,
+                status: PresenceStatus::Offline,
+                last_seen_at: None,
+            },
+        }
+    }
+
+    /// the changed presence, None if the status stays the same
+    fn connect(&self, user_id: i64) -> Option<Presence> {
+        let mut entry = self.users.entry(user_id).or_insert(PresenceEntry {
+            streams: 0,
+            status: PresenceStatus::Offline,
+            last_seen_at: Utc::now(),
+        });
+        entry.streams += 1;
+        entry.last_seen_at = Utc::now();
+        let changed = entry.status != PresenceStatus::Online;
+        entry.status = PresenceStatus::Online;
+        drop(entry);
+        changed.then(|| self.get(user_id))
+    }
+
+    fn disconnect(&self, user_id: i64) -> Option<Presence> {
+        let mut entry = self.users.get_mut(&user_id)?;
+        entry.streams = entry.streams.saturating_sub(1);
+        if entry.streams > 0 {
+            return None;
+        }
+        entry.status = PresenceStatus::Offline;
+        entry.last_seen_at = Utc::now();
+        drop(entry);
+        Some(self.get(user_id))
+    }
+
+    /// heartbeats only count while the user has an event stream open
+    fn heartbeat(&self, user_id: i64, status: PresenceStatus) -> Option<Presence> {
+        let mut entry = self.users.get_mut(&user_id)?;
+        if entry.streams == 0 {
+            return None;
+        }
+        entry.last_seen_at = Utc::now();
+        let changed = entry.status != status;
+        entry.status = status;
+        drop(entry);
+        changed.then(|| self.get(user_id))
+    }
+
+    /// online users gone quiet for longer than `idle`, now away
+    fn sweep(&self, idle: Duration) -> Vec<Presence> {
+        let Ok(idle) = chrono::Duration::from_std(idle) else {
+            return vec![];
+        };
+        let since = Utc::now() - idle;
+        let mut changed = vec![];
+        for mut entry in self.users.iter_mut() {
+            if entry.status == PresenceStatus::Online && entry.last_seen_at < since {
+                entry.status = PresenceStatus::Away;
+                changed.push(Presence {
+                    user_id: *entry.key(),
+                    status: entry.status,
+                    last_seen_at: Some(entry.last_seen_at),
+                });
+            }
+        }
+        changed
+    }
+}
+
+impl NotifState {
+    /// queue the user's presence to be announced, changes go out in the order they happened
+    fn presence_changed(&self, user_id: i64) {
+        if let Err(e) = self.presence_changes.send(user_id) {
+            warn!("queue presence of user {} error: {}", user_id, e);
+        }
+    }
+
+    /// tell the users sharing a chat with the user about its new presence
+    async fn announce(&self, presence: Presence) {
+        let peers = match Chat::peer_ids(presence.user_id, &self.pool).await {
+            Ok(peers) => peers,
+            Err(e) => {
+                warn!("find peers of user {} error: {}", presence.user_id, e);
+                return;
+            }
+        };
+        let event = Arc::new(ChatEvent::PresenceChanged(presence));
+        for peer in peers {
+            if let Some(tx) = self.users_map.get(&peer) {
+                if let Err(e) = tx.send(event.clone()) {
+                    warn!("send presence event error: {}", e);
+                }
+            }
+        }
+    }
+}
+
+impl PresenceGuard {
+    pub(crate) fn connect(state: NotifState, user_id: i64) -> Self {
+        if state.presence.connect(user_id).is_some() {
+            state.presence_changed(user_id);
+        }
+        Self { state, user_id }
+    }
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        if self.state.presence.disconnect(self.user_id).is_some() {
+            info!("user {} went offline", self.user_id);
+            self.state.presence_changed(self.user_id);
+        }
+    }
+}
+
+/// announce the queued changes one at a time. The presence is read when it is sent,
+/// so a change overtaken by a later one never goes out last
+pub(crate) fn spawn_presence_announcer(state: NotifState, mut changed: UnboundedReceiver<i64>) {
+    tokio::spawn(async move {
+        while let Some(user_id) = changed.recv().await {
+            state.announce(state.presence.get(user_id)).await;
+        }
+    });
+}
+
+pub(crate) fn spawn_presence_sweep(state: NotifState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            for presence in state.presence.sweep(AWAY_AFTER) {
+                state.presence_changed(presence.user_id);
+            }
+        }
+    });
+}
+
+/// presence of the listed users, those outside the caller's workspace are left out
+pub(crate) async fn presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<NotifState>,
+    Query(query): Query<PresenceQuery>,
+) -> Result<impl IntoResponse, NotifyError> {
+    let ids = query
+        .ids
+        .split(',')
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| id.trim().parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ChatCoreError::BadRequest(format!("invalid user ids {}", query.ids)))?;
+    if ids.len() > MAX_PRESENCE_IDS {
+        return Err(ChatCoreError::BadRequest(format!(
+            "at most {} user ids at a time",
+            MAX_PRESENCE_IDS
+        ))
+        .into());
+    }
+
+    let users = User::find_user_by_ids(&ids, user.ws_id, &state.pool).await?;
+    let presences: Vec<_> = ids
+        .iter()
+        .filter(|id| users.iter().any(|u| u.id == **id))
+        .map(|id| state.presence.get(*id))
+        .collect();
+
+    Ok(Json(presences))
+}
+
+pub(crate) async fn heartbeat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<NotifState>,
+    heartbeat: Option<Json<Heartbeat>>,
+) -> Result<impl IntoResponse, NotifyError> {
+    let status = heartbeat.map_or(PresenceStatus::Online, |Json(h)| h.status);
+    if status == PresenceStatus::Offline {
+        return Err(
+            ChatCoreError::BadRequest("close the event stream to go offline".to_string()).into(),
+        );
+    }
+    if state.presence.heartbeat(user.id, status).is_some() {
+        state.presence_changed(user.id);
+    }
+
+    Ok(Json(state.presence.get(user.id)))
+}
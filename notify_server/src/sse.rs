@@ -8,18 +8,21 @@ use futures::Stream;
 use futures_util::stream;
 use jwt_simple::reexports::serde_json;
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 use tokio_stream::StreamExt;
-use tracing::info;
+use tracing::{info, warn};
 
-use chat_core::User;
+use chat_core::{AccessToken, User};
 
 use crate::notif::ChatEvent;
+use crate::presence::PresenceGuard;
 use crate::NotifState;
 
 const CHANNEL_CAP: usize = 256;
 
 pub(crate) async fn sse_handler(
     Extension(user): Extension<User>,
+    Extension(access): Extension<AccessToken>,
     State(state): State<NotifState>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let user_id = user.id;
@@ -31,17 +34,43 @@ pub(crate) async fn sse_handler(
         state.users_map.insert(user_id, tx);
         rx
     };
+    let presence = PresenceGuard::connect(state.clone(), user_id);
 
-    let stream = stream::unfold(
-        rx,
-        |mut rx| async move { Some((rx.recv().await.unwrap(), rx)) },
-    )
-    .map(|msg| {
+    let stream = stream::unfold(rx, move |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => return Some((msg, rx)),
+                // a slow client misses events rather than losing the stream
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("user {} lagged behind, {} events skipped", user_id, skipped)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    // the stream ends once its own session is revoked, the user is deactivated
+    // or its workspace is archived
+    .take_while(move |msg| match msg.as_ref() {
+        ChatEvent::SessionRevoked(revoked) => Some(revoked.session_id) != access.session_id,
+        ChatEvent::UserDeactivated(_) => false,
+        ChatEvent::WorkspaceArchived(archived) => archived.ws_id != access.ws_id,
+        _ => true,
+    })
+    // tokens act in a single workspace, events of the others are dropped
+    .filter(move |msg| msg.ws_id().is_none_or(|ws_id| ws_id == access.ws_id))
+    .map(move |msg| {
+        // the user stays online as long as the stream is alive
+        let _presence = &presence;
         let name = match msg.as_ref() {
             ChatEvent::NewChat(_) => "new_chat",
             ChatEvent::UpdateChat(_) => "update_chat",
             ChatEvent::DeleteChat(_) => "delete_chat",
             ChatEvent::NewMessage(_) => "new_message",
+            ChatEvent::SessionRevoked(_) => "session_revoked",
+            ChatEvent::UserDeactivated(_) => "user_deactivated",
+            ChatEvent::UserUpdated(_) => "user_updated",
+            ChatEvent::PresenceChanged(_) => "presence_changed",
+            ChatEvent::WorkspaceArchived(_) => "workspace_archived",
         };
         let data = serde_json::to_string(&msg).expect("Failed to serialize data");
         Ok(Event::default().event(name).data(data))